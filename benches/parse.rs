@@ -0,0 +1,44 @@
+//! `cargo bench` : times `parse_obj` on objs/Grenade.obj
+//! An optional argument replaces the file, to try bigger models, and `--threads=<n>` parses
+//! with n threads
+
+use scop::obj::{ParseOptions, parse_obj};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 20;
+
+fn main() {
+    let path = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or("objs/Grenade.obj".into());
+    let file = std::fs::read(&path).expect("Could not read the obj file");
+    let options = ParseOptions {
+        threads: std::env::args()
+            .find_map(|arg| arg.strip_prefix("--threads=")?.parse().ok())
+            .unwrap_or(1),
+        ..Default::default()
+    };
+
+    let mut times = Vec::with_capacity(RUNS);
+    let mut triangles = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let (model, _) = parse_obj(file.as_slice(), &options).expect("Could not parse the obj file");
+        times.push(start.elapsed());
+        triangles = model.indices.len() / 3;
+    }
+    times.sort();
+    let median = times[RUNS / 2];
+    let mean = times.iter().sum::<Duration>() / RUNS as u32;
+    let megabytes = file.len() as f64 / 1e6;
+    println!(
+        "{path} : {megabytes:.1} MB, {triangles} triangles, {} threads, {RUNS} runs",
+        options.threads
+    );
+    println!(
+        "min {:?}  median {median:?}  mean {mean:?}  ({:.0} MB/s)",
+        times[0],
+        megabytes / median.as_secs_f64()
+    );
+}
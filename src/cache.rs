@@ -0,0 +1,544 @@
+use crate::math::vector::{Vector3, Vector4};
+use crate::obj::{BoundingBox, MaterialRange, Model, Submesh, Vertex, VertexAttribute};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{self, Error as IOError, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"SCOPMDL\0";
+/// Bumped whenever the layout of the cache changes
+const VERSION: u32 = 6;
+/// Magic, version, source hash, options hash, payload length and payload checksum
+const HEADER_SIZE: usize = 8 + 4 + 8 + 8 + 8 + 8;
+const EXTENSION: &str = "scopcache";
+
+#[derive(Debug)]
+pub enum CacheError {
+    IOError(IOError),
+    /// Truncated or damaged file
+    Corrupted(&'static str),
+    /// Written by another version, for another source file or with other options
+    Stale(&'static str),
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::IOError(e) => write!(f, "{e}"),
+            CacheError::Corrupted(reason) => write!(f, "Corrupted cache : {reason}"),
+            CacheError::Stale(reason) => write!(f, "Stale cache : {reason}"),
+        }
+    }
+}
+
+impl Error for CacheError {}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// `hash` followed by `data`
+fn fnv1a_update(hash: u64, data: &[u8]) -> u64 {
+    (data.iter()).fold(hash, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 64 bits FNV-1a
+pub fn fnv1a(data: &[u8]) -> u64 {
+    fnv1a_update(FNV_OFFSET, data)
+}
+
+/// 64 bits FNV-1a of everything `reader` gives, read a piece at a time
+pub fn fnv1a_reader(mut reader: impl Read) -> io::Result<u64> {
+    let mut buffer = vec![0; 1 << 16];
+    let mut hash = FNV_OFFSET;
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hash),
+            Ok(n) => hash = fnv1a_update(hash, &buffer[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Cache file of one source file, loaded with some options
+/// The cache holds the model as the loader returns it, with the diagnostics of the loader,
+/// materials are not part of it
+pub struct Cache {
+    pub path: PathBuf,
+    source_hash: u64,
+    options_hash: u64,
+}
+
+impl Cache {
+    /// Cache of the file at `source_path`, whose content hashes to `source_hash` with `fnv1a`
+    /// `options` describes everything that changes the loaded model
+    /// The cache is a hidden file next to the source, or in `dir`, named after the source and a
+    /// hash of its path so files with the same name don't share it
+    pub fn new(source_path: &Path, source_hash: u64, options: &str, dir: Option<&Path>) -> Cache {
+        let name = source_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let path = match dir {
+            Some(dir) => {
+                let absolute = fs::canonicalize(source_path).unwrap_or(source_path.into());
+                let path_hash = fnv1a(absolute.as_os_str().as_encoded_bytes());
+                dir.join(format!("{name}-{path_hash:016x}.{EXTENSION}"))
+            }
+            None => source_path.with_file_name(format!(".{name}.{EXTENSION}")),
+        };
+        Cache {
+            path,
+            source_hash,
+            options_hash: fnv1a(options.as_bytes()),
+        }
+    }
+
+    /// Loads the cached model and diagnostics, with a single read of the cache file
+    pub fn load(&self) -> Result<(Model, Vec<String>), CacheError> {
+        let data = fs::read(&self.path).map_err(CacheError::IOError)?;
+        let header = data
+            .get(..HEADER_SIZE)
+            .ok_or(CacheError::Corrupted("truncated header"))?;
+        // the header is complete, so reading it can't fail
+        let mut decoder = Decoder {
+            data: header,
+            position: 0,
+        };
+        if decoder.bytes(MAGIC.len()) != Some(MAGIC) {
+            return Err(CacheError::Corrupted("not a cache file"));
+        }
+        if decoder.u32() != Some(VERSION) {
+            return Err(CacheError::Stale("written by another version"));
+        }
+        let [source_hash, options_hash, length, checksum] = [(); 4].map(|_| decoder.u64().unwrap());
+        if source_hash != self.source_hash {
+            return Err(CacheError::Stale("the source file changed"));
+        }
+        if options_hash != self.options_hash {
+            return Err(CacheError::Stale("written with other options"));
+        }
+        let payload = &data[HEADER_SIZE..];
+        if payload.len() as u64 != length {
+            return Err(CacheError::Corrupted("wrong payload length"));
+        }
+        if fnv1a(payload) != checksum {
+            return Err(CacheError::Corrupted("wrong checksum"));
+        }
+        decode_model(payload).ok_or(CacheError::Corrupted("invalid payload"))
+    }
+
+    /// Writes the cache of `model` and of the `diagnostics` of its loader
+    /// The file is written under another name first and renamed, so a crash never leaves a
+    /// half written cache behind
+    pub fn store(&self, model: &Model, diagnostics: &[String]) -> io::Result<()> {
+        let payload = encode_model(model, diagnostics);
+        let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+        data.extend(MAGIC);
+        data.extend(VERSION.to_le_bytes());
+        for value in [
+            self.source_hash,
+            self.options_hash,
+            payload.len() as u64,
+            fnv1a(&payload),
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(payload);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = self.path.with_extension(format!("{EXTENSION}.tmp"));
+        fs::write(&temporary, &data)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Little endian encoding of the parts of the model
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u64(&mut self, value: usize) {
+        self.0.extend((value as u64).to_le_bytes());
+    }
+    fn f32s(&mut self, values: &[f32]) {
+        self.0.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    }
+    fn u32s(&mut self, values: &[u32]) {
+        self.u64(values.len());
+        self.0.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    }
+    fn string(&mut self, value: &str) {
+        self.u64(value.len());
+        self.0.extend(value.as_bytes());
+    }
+    fn range(&mut self, range: &Range<usize>) {
+        self.u64(range.start);
+        self.u64(range.end);
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    fn usize(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+    /// Element count of a list of `size` bytes elements, checked against the remaining data so
+    /// a damaged count can't allocate too much
+    fn count(&mut self, size: usize) -> Option<usize> {
+        let count = self.usize()?;
+        (count.checked_mul(size)? <= self.data.len() - self.position).then_some(count)
+    }
+    fn f32s<const N: usize>(&mut self) -> Option<[f32; N]> {
+        let bytes = self.bytes(N * 4)?;
+        Some(std::array::from_fn(|i| {
+            f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap())
+        }))
+    }
+    fn u32s(&mut self) -> Option<Box<[u32]>> {
+        let count = self.count(4)?;
+        let bytes = self.bytes(count * 4)?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        )
+    }
+    fn string(&mut self) -> Option<String> {
+        let length = self.count(1)?;
+        String::from_utf8(self.bytes(length)?.to_vec()).ok()
+    }
+    fn range(&mut self) -> Option<Range<usize>> {
+        Some(self.usize()?..self.usize()?)
+    }
+    /// List of elements of at least `size` bytes each
+    fn list<T>(
+        &mut self,
+        size: usize,
+        mut item: impl FnMut(&mut Self) -> Option<T>,
+    ) -> Option<Vec<T>> {
+        let count = self.count(size)?;
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+fn vector([x, y, z]: [f32; 3]) -> Vector3<f32> {
+    Vector3 { x, y, z }
+}
+
+fn encode_model(model: &Model, diagnostics: &[String]) -> Vec<u8> {
+    let mut encoder = Encoder(Vec::new());
+    encoder.u64(model.vertices.len());
+    for vertex in &model.vertices {
+        let (p, c, n, t) = (vertex.position, vertex.color, vertex.normal, vertex.tangent);
+        let (u, v) = vertex.texture_coordinates;
+        encoder.f32s(&[
+            p.x, p.y, p.z, c.x, c.y, c.z, u, v, n.x, n.y, n.z, t.x, t.y, t.z, t.w,
+        ]);
+    }
+    encoder.u32s(&model.indices);
+    encoder.u64(model.material_libs.len());
+    for lib in &model.material_libs {
+        encoder.string(lib);
+    }
+    encoder.u64(model.material_ranges.len());
+    for range in &model.material_ranges {
+        encoder.string(&range.material);
+        encoder.range(&range.indices);
+    }
+    encoder.u64(model.submeshes.len());
+    for submesh in &model.submeshes {
+        encoder.string(&submesh.object);
+        encoder.string(&submesh.group);
+        encoder.range(&submesh.indices);
+    }
+    encoder.u64(model.polygons.len());
+    for polygon in &model.polygons {
+        encoder.range(polygon);
+    }
+    encoder.u32s(&model.line_indices);
+    encoder.u64(model.line_strips.len());
+    for strip in &model.line_strips {
+        encoder.range(strip);
+    }
+    encoder.u32s(&model.point_indices);
+    encoder.u64(model.attributes.len());
+    for attribute in &model.attributes {
+        encoder.string(&attribute.name);
+        encoder.u64(attribute.values.len());
+        encoder
+            .0
+            .extend(attribute.values.iter().flat_map(|v| v.to_le_bytes()));
+    }
+    encoder.u64(model.file_colors.len());
+    for color in &model.file_colors {
+        match color {
+            Some(c) => {
+                encoder.0.push(1);
+                encoder.f32s(&[c.x, c.y, c.z]);
+            }
+            None => encoder.0.extend([0; 13]),
+        }
+    }
+    // faces are numbered from 0, u32::MAX stands for no face
+    let vertex_faces: Vec<_> = (model.vertex_faces.iter())
+        .map(|face| face.unwrap_or(u32::MAX))
+        .collect();
+    encoder.u32s(&vertex_faces);
+    encoder.u64(model.face_count);
+    encoder
+        .0
+        .extend([model.file_normals, model.file_texture_coordinates].map(u8::from));
+    let bounds = &model.bounds;
+    encoder.f32s(&[bounds.min.x, bounds.min.y, bounds.min.z]);
+    encoder.f32s(&[bounds.max.x, bounds.max.y, bounds.max.z]);
+    encoder.u64(diagnostics.len());
+    for diagnostic in diagnostics {
+        encoder.string(diagnostic);
+    }
+    encoder.0
+}
+
+/// None if the payload does not describe a valid model
+fn decode_model(payload: &[u8]) -> Option<(Model, Vec<String>)> {
+    let mut decoder = Decoder {
+        data: payload,
+        position: 0,
+    };
+    let vertices: Box<[Vertex]> = decoder
+        .list(60, |d| {
+            let [px, py, pz, cx, cy, cz, u, v, nx, ny, nz, tx, ty, tz, tw] = d.f32s()?;
+            Some(Vertex {
+                position: vector([px, py, pz]),
+                color: vector([cx, cy, cz]),
+                texture_coordinates: (u, v),
+                normal: vector([nx, ny, nz]),
+                tangent: Vector4 {
+                    x: tx,
+                    y: ty,
+                    z: tz,
+                    w: tw,
+                },
+            })
+        })?
+        .into();
+    let indices = decoder.u32s()?;
+    let material_libs = decoder.list(8, Decoder::string)?;
+    let material_ranges = decoder.list(24, |d| {
+        Some(MaterialRange {
+            material: d.string()?,
+            indices: d.range()?,
+        })
+    })?;
+    let submeshes = decoder.list(32, |d| {
+        Some(Submesh {
+            object: d.string()?,
+            group: d.string()?,
+            indices: d.range()?,
+        })
+    })?;
+    let polygons = decoder.list(16, Decoder::range)?;
+    let line_indices = decoder.u32s()?;
+    let line_strips = decoder.list(16, Decoder::range)?;
+    let point_indices = decoder.u32s()?;
+    let attributes = decoder.list(16, |d| {
+        let name = d.string()?;
+        let count = d.count(8)?;
+        let values = d.bytes(count * 8)?;
+        Some(VertexAttribute {
+            name,
+            values: values
+                .chunks_exact(8)
+                .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        })
+    })?;
+    let file_colors = decoder.list(13, |d| match d.bytes(1)?[0] {
+        0 => d.bytes(12).map(|_| None),
+        1 => Some(Some(vector(d.f32s()?))),
+        _ => None,
+    })?;
+    let vertex_faces: Box<[_]> = (decoder.u32s()?.iter())
+        .map(|&face| (face != u32::MAX).then_some(face))
+        .collect();
+    let face_count = decoder.usize()?;
+    let [file_normals, file_texture_coordinates] = match decoder.bytes(2)? {
+        &[normals @ 0..=1, texture_coordinates @ 0..=1] => {
+            [normals, texture_coordinates].map(|b| b == 1)
+        }
+        _ => return None,
+    };
+    let bounds = BoundingBox {
+        min: vector(decoder.f32s()?),
+        max: vector(decoder.f32s()?),
+    };
+    let diagnostics = decoder.list(8, Decoder::string)?;
+    if decoder.position != payload.len() {
+        return None;
+    }
+
+    // the rest of the program trusts the model, so every index and range is checked
+    let vertex_count = vertices.len() as u32;
+    let valid_indices = |indices: &[u32]| indices.iter().all(|&i| i < vertex_count);
+    let valid_range =
+        |range: &Range<usize>, len: usize| range.start <= range.end && range.end <= len;
+    let valid = valid_indices(&indices)
+        && valid_indices(&line_indices)
+        && valid_indices(&point_indices)
+        && indices.len() % 3 == 0
+        && material_ranges
+            .iter()
+            .all(|r| valid_range(&r.indices, indices.len()))
+        && submeshes
+            .iter()
+            .all(|s| valid_range(&s.indices, indices.len()))
+        && polygons.iter().all(|p| valid_range(p, indices.len()))
+        && line_strips
+            .iter()
+            .all(|s| valid_range(s, line_indices.len()))
+        && attributes.iter().all(|a| a.values.len() == vertices.len())
+        && (file_colors.is_empty() || file_colors.len() == vertices.len())
+        && vertex_faces.len() == vertices.len();
+    let model = Model {
+        vertices,
+        indices,
+        material_libs,
+        materials: Vec::new(),
+        material_ranges,
+        submeshes,
+        polygons,
+        line_indices,
+        line_strips,
+        point_indices,
+        attributes,
+        file_colors: file_colors.into(),
+        vertex_faces,
+        face_count,
+        file_normals,
+        file_texture_coordinates,
+        bounds,
+    };
+    valid.then_some((model, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+    /// Cache of `SOURCE` in its own directory, named after the test
+    fn cache(test: &str, source: &[u8]) -> Cache {
+        let dir = std::env::temp_dir().join(format!("scop-{test}-{}", std::process::id()));
+        Cache::new(Path::new("model.obj"), fnv1a(source), "options", Some(&dir))
+    }
+
+    fn model() -> Model {
+        let vertices = (0..4)
+            .map(|i| Vertex {
+                position: vector([i as f32, (i * i) as f32, 0.0]),
+                color: vector([0.5; 3]),
+                texture_coordinates: (i as f32, 0.0),
+                normal: vector([0.0, 0.0, 1.0]),
+                tangent: Vector4::zero(),
+            })
+            .collect();
+        let mut model = Model::new(vertices, vec![0, 1, 2, 0, 2, 3]);
+        model.polygons.push(0..6);
+        model.point_indices = Box::new([1]);
+        model.file_colors = Box::new([None, Some(vector([1.0, 0.0, 0.0])), None, None]);
+        model
+    }
+
+    /// Stores the model, changes the cache file with `damage` and loads it back
+    fn damaged(test: &str, damage: impl FnOnce(&mut Vec<u8>)) -> Result<(), CacheError> {
+        let cache = cache(test, SOURCE);
+        cache.store(&model(), &[]).unwrap();
+        let mut data = fs::read(&cache.path).unwrap();
+        damage(&mut data);
+        fs::write(&cache.path, data).unwrap();
+        let loaded = cache.load().map(|_| ());
+        fs::remove_dir_all(cache.path.parent().unwrap()).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn same_model_and_diagnostics() {
+        let cache = cache("same", SOURCE);
+        let diagnostics = ["Line 3 : unknown statement".to_string()];
+        cache.store(&model(), &diagnostics).unwrap();
+        let (loaded, loaded_diagnostics) = cache.load().unwrap();
+        fs::remove_dir_all(cache.path.parent().unwrap()).unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{:?}", model()));
+        assert_eq!(loaded_diagnostics, diagnostics);
+    }
+
+    #[test]
+    fn hash_read_in_pieces() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        // the first read gives 3 bytes, the others fill the buffer
+        let reader = Read::chain(&data[..3], &data[3..]);
+        assert_eq!(fnv1a_reader(reader).unwrap(), fnv1a(&data));
+        assert_eq!(fnv1a_reader(&[][..]).unwrap(), fnv1a(&[]));
+    }
+
+    #[test]
+    fn flipped_payload_byte() {
+        let loaded = damaged("flipped", |data| data[HEADER_SIZE + 10] ^= 1);
+        assert!(matches!(
+            loaded,
+            Err(CacheError::Corrupted("wrong checksum"))
+        ));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let loaded = damaged("header", |data| data.truncate(HEADER_SIZE - 1));
+        assert!(matches!(
+            loaded,
+            Err(CacheError::Corrupted("truncated header"))
+        ));
+        let loaded = damaged("payload", |data| data.truncate(data.len() - 1));
+        assert!(matches!(loaded, Err(CacheError::Corrupted(_))));
+    }
+
+    #[test]
+    fn changed_source() {
+        let stored = cache("source", SOURCE);
+        stored.store(&model(), &[]).unwrap();
+        // same cache file, for another content of the source
+        let loaded = cache("source", b"v 0 0 0\n").load().map(|_| ());
+        fs::remove_dir_all(stored.path.parent().unwrap()).unwrap();
+        assert!(matches!(
+            loaded,
+            Err(CacheError::Stale("the source file changed"))
+        ));
+    }
+
+    #[test]
+    fn other_version() {
+        let loaded = damaged("version", |data| data[MAGIC.len()] ^= 1);
+        assert!(matches!(loaded, Err(CacheError::Stale(_))));
+    }
+}
@@ -0,0 +1,374 @@
+use crate::cache::fnv1a;
+use crate::math::vector::Vector3;
+use crate::obj::Model;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// What the colors of the vertices show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    /// Colors of the file, `Face` for the vertices without one
+    #[default]
+    File,
+    /// Gray shade growing with the face number
+    Face,
+    /// Random color per face, the same on every run
+    Random,
+    /// One color per group name
+    Group,
+    /// One color per object name
+    Object,
+    /// One color per material name
+    Material,
+    /// Red where the surface is convex, blue where it is concave
+    Curvature,
+    /// From blue at the bottom to red at the top of the model
+    Height,
+}
+
+impl ColorScheme {
+    pub const ALL: [ColorScheme; 8] = [
+        ColorScheme::File,
+        ColorScheme::Face,
+        ColorScheme::Random,
+        ColorScheme::Group,
+        ColorScheme::Object,
+        ColorScheme::Material,
+        ColorScheme::Curvature,
+        ColorScheme::Height,
+    ];
+    /// The scheme after this one in `ALL`, looping back to the first
+    pub fn next(self) -> ColorScheme {
+        let i = ColorScheme::ALL
+            .iter()
+            .position(|&s| s == self)
+            .unwrap_or(0);
+        ColorScheme::ALL[(i + 1) % ColorScheme::ALL.len()]
+    }
+}
+
+/// Shade of gray of the face `face_no`, so neighbouring faces can be told apart
+pub fn face_color(face_no: usize, total: usize) -> Vector3<f32> {
+    Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    } * ((0.2 * face_no as f32 % 1.0 / 2.5) + (face_no as f32 / total as f32 / 2.0))
+}
+
+/// Saturated color picked by `hash`
+fn hashed_color(hash: u64) -> Vector3<f32> {
+    let hue = (hash % 360) as f32 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    // keeps the colors apart from white lines and from the background
+    Vector3 {
+        x: 0.25 + 0.65 * r,
+        y: 0.25 + 0.65 * g,
+        z: 0.25 + 0.65 * b,
+    }
+}
+
+/// Blue, cyan, green, yellow then red as `t` goes from 0 to 1
+fn ramp(t: f32) -> Vector3<f32> {
+    let t = if t.is_finite() {
+        t.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let channel = |center: f32| (1.5 - (4.0 * t - center).abs()).clamp(0.0, 1.0);
+    Vector3 {
+        x: channel(3.0),
+        y: channel(2.0),
+        z: channel(1.0),
+    }
+}
+
+/// Mean curvature around each vertex, estimated along the edges from the change of the normal
+/// Vertices at the same position share their curvature, so seams don't show
+fn curvatures(model: &Model) -> Vec<f32> {
+    let mut ids = HashMap::new();
+    let position_ids: Vec<usize> = model
+        .vertices
+        .iter()
+        .map(|v| {
+            let next = ids.len();
+            *ids.entry([v.position.x, v.position.y, v.position.z].map(f32::to_bits))
+                .or_insert(next)
+        })
+        .collect();
+    let mut normals = vec![Vector3::zero(); ids.len()];
+    let mut positions = vec![Vector3::zero(); ids.len()];
+    for triangle in model.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| model.vertices[triangle[k] as usize].position);
+        let normal = (b - a).cross(&(c - a));
+        for &index in triangle {
+            normals[position_ids[index as usize]] += normal;
+            positions[position_ids[index as usize]] = model.vertices[index as usize].position;
+        }
+    }
+    for normal in &mut normals {
+        if normal.norm2() > 0.0 {
+            *normal = normal.normalized();
+        }
+    }
+    let mut sums = vec![(0.0, 0); ids.len()];
+    for triangle in model.indices.chunks_exact(3) {
+        for k in 0..3 {
+            let a = position_ids[triangle[k] as usize];
+            let b = position_ids[triangle[(k + 1) % 3] as usize];
+            let edge = positions[b] - positions[a];
+            if a == b || edge.norm2() == 0.0 {
+                continue;
+            }
+            // positive when the normals open up along the edge, like on a sphere
+            let curvature = (normals[b] - normals[a]).dot(&edge) / edge.norm2();
+            for id in [a, b] {
+                sums[id].0 += curvature;
+                sums[id].1 += 1;
+            }
+        }
+    }
+    position_ids
+        .iter()
+        .map(|&id| match sums[id] {
+            (_, 0) => 0.0,
+            (sum, count) => sum / count as f32,
+        })
+        .collect()
+}
+
+/// Colors of the vertices of `model` in `scheme`
+/// Vertices outside of the triangles keep their `Face` color with the name based schemes
+pub fn scheme_colors(model: &Model, scheme: ColorScheme) -> Vec<Vector3<f32>> {
+    let vertex_count = model.vertices.len();
+    let face = |i: usize| match model.vertex_faces.get(i).copied().flatten() {
+        Some(face) => face_color(face as usize, model.face_count),
+        None => face_color(i, vertex_count),
+    };
+    let mut colors: Vec<_> = (0..vertex_count).map(face).collect();
+    let named: Vec<(&str, &Range<usize>)> = match scheme {
+        ColorScheme::Group => (model.submeshes.iter())
+            .map(|s| (s.group.as_str(), &s.indices))
+            .collect(),
+        ColorScheme::Object => (model.submeshes.iter())
+            .map(|s| (s.object.as_str(), &s.indices))
+            .collect(),
+        ColorScheme::Material => (model.material_ranges.iter())
+            .map(|r| (r.material.as_str(), &r.indices))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (name, range) in named {
+        let color = hashed_color(fnv1a(name.as_bytes()));
+        for &index in &model.indices[range.clone()] {
+            colors[index as usize] = color;
+        }
+    }
+    match scheme {
+        ColorScheme::File => {
+            for (color, file_color) in colors.iter_mut().zip(&model.file_colors) {
+                if let Some(file_color) = file_color {
+                    *color = *file_color;
+                }
+            }
+        }
+        ColorScheme::Face | ColorScheme::Group | ColorScheme::Object | ColorScheme::Material => {}
+        ColorScheme::Random => {
+            for (i, color) in colors.iter_mut().enumerate() {
+                let id = model.vertex_faces.get(i).copied().flatten();
+                let key = id.map_or(u64::MAX - i as u64, u64::from);
+                *color = hashed_color(fnv1a(&key.to_le_bytes()));
+            }
+        }
+        ColorScheme::Curvature => {
+            let curvatures = curvatures(model);
+            // the strongest curvatures would wash out the rest
+            let mut magnitudes: Vec<f32> = curvatures
+                .iter()
+                .map(|c| c.abs())
+                .filter(|c| *c > 0.0 && c.is_finite())
+                .collect();
+            magnitudes.sort_unstable_by(f32::total_cmp);
+            let scale = magnitudes
+                .get(magnitudes.len() * 9 / 10)
+                .copied()
+                .unwrap_or(1.0);
+            for (color, curvature) in colors.iter_mut().zip(curvatures) {
+                let t = (curvature / scale).clamp(-1.0, 1.0);
+                let t = if t.is_finite() { t } else { 0.0 };
+                let light = 0.85;
+                *color = if t >= 0.0 {
+                    Vector3 {
+                        x: light + (1.0 - light) * t,
+                        y: light * (1.0 - t),
+                        z: light * (1.0 - t),
+                    }
+                } else {
+                    Vector3 {
+                        x: light * (1.0 + t),
+                        y: light * (1.0 + t),
+                        z: light - (1.0 - light) * t,
+                    }
+                };
+            }
+        }
+        ColorScheme::Height => {
+            let (min, max) = (model.bounds.min.y, model.bounds.max.y);
+            for (color, vertex) in colors.iter_mut().zip(&model.vertices) {
+                let t = if max > min {
+                    (vertex.position.y - min) / (max - min)
+                } else {
+                    0.5
+                };
+                *color = ramp(t);
+            }
+        }
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{ParseOptions, parse_obj};
+
+    /// A unit square in two triangles of their own group and material, the first corner red
+    const SQUARE: &[u8] = b"o square
+v 0 0 0 1 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+g top
+usemtl red
+f 1 2 3
+g bottom
+usemtl blue
+f 1 3 4
+";
+
+    fn parse(source: &[u8]) -> Model {
+        let (model, diagnostics) = parse_obj(source, &ParseOptions::default()).unwrap();
+        assert!(diagnostics.is_empty());
+        model
+    }
+
+    /// Color of the vertex at `position`, all of its copies have the same one
+    fn color_at(model: &Model, scheme: ColorScheme, position: [f32; 3]) -> [f32; 3] {
+        let colors = scheme_colors(model, scheme);
+        let mut found = (model.vertices.iter().zip(colors))
+            .filter(|(v, _)| [v.position.x, v.position.y, v.position.z] == position)
+            .map(|(_, c)| [c.x, c.y, c.z]);
+        let color = found.next().unwrap();
+        assert!(found.all(|c| c == color));
+        color
+    }
+
+    fn rgb(color: Vector3<f32>) -> [f32; 3] {
+        [color.x, color.y, color.z]
+    }
+
+    #[test]
+    fn cycling() {
+        let mut scheme = ColorScheme::default();
+        for expected in ColorScheme::ALL.iter().skip(1) {
+            scheme = scheme.next();
+            assert_eq!(scheme, *expected);
+        }
+        assert_eq!(scheme.next(), ColorScheme::File);
+    }
+
+    #[test]
+    fn file_and_face() {
+        let model = parse(SQUARE);
+        // only in the first face, then only in the second one
+        let (first, second) = ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        assert_eq!(
+            color_at(&model, ColorScheme::File, [0.0; 3]),
+            [1.0, 0.0, 0.0]
+        );
+        let gray = rgb(face_color(1, 2));
+        assert_eq!(color_at(&model, ColorScheme::File, second), gray);
+        assert_eq!(color_at(&model, ColorScheme::Face, second), gray);
+        assert_eq!(
+            color_at(&model, ColorScheme::Face, first),
+            rgb(face_color(0, 2))
+        );
+        assert_ne!(gray, rgb(face_color(0, 2)));
+    }
+
+    #[test]
+    fn random() {
+        let model = parse(SQUARE);
+        let (first, second) = ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let color = color_at(&model, ColorScheme::Random, second);
+        assert_eq!(color, rgb(hashed_color(fnv1a(&1u64.to_le_bytes()))));
+        assert_ne!(color, color_at(&model, ColorScheme::Random, first));
+        // the same on every run
+        assert_eq!(color, color_at(&parse(SQUARE), ColorScheme::Random, second));
+    }
+
+    #[test]
+    fn names() {
+        let model = parse(SQUARE);
+        let (first, second) = ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let named = |name: &str| rgb(hashed_color(fnv1a(name.as_bytes())));
+        assert_eq!(color_at(&model, ColorScheme::Group, first), named("top"));
+        assert_eq!(
+            color_at(&model, ColorScheme::Group, second),
+            named("bottom")
+        );
+        assert_eq!(color_at(&model, ColorScheme::Material, first), named("red"));
+        assert_eq!(
+            color_at(&model, ColorScheme::Material, second),
+            named("blue")
+        );
+        assert_eq!(
+            color_at(&model, ColorScheme::Object, first),
+            named("square")
+        );
+        assert_eq!(
+            color_at(&model, ColorScheme::Object, second),
+            named("square")
+        );
+    }
+
+    #[test]
+    fn curvature() {
+        // flat
+        let model = parse(SQUARE);
+        assert_eq!(
+            color_at(&model, ColorScheme::Curvature, [0.0; 3]),
+            [0.85; 3]
+        );
+        // convex everywhere
+        let tetrahedron =
+            parse(b"v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n");
+        for position in [[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]] {
+            let [r, g, b] = color_at(&tetrahedron, ColorScheme::Curvature, position);
+            assert!(r > 0.85 && g < 0.85 && g == b, "{:?}", [r, g, b]);
+        }
+    }
+
+    #[test]
+    fn height() {
+        let model = parse(SQUARE);
+        // dark blue at the bottom, dark red at the top
+        assert_eq!(
+            color_at(&model, ColorScheme::Height, [0.0; 3]),
+            [0.0, 0.0, 0.5]
+        );
+        assert_eq!(
+            color_at(&model, ColorScheme::Height, [1.0, 1.0, 0.0]),
+            [0.5, 0.0, 0.0]
+        );
+        assert_eq!(rgb(ramp(0.5)), [0.5, 1.0, 0.5]);
+    }
+}
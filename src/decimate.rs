@@ -0,0 +1,829 @@
+use crate::math::vector::Vector3;
+use crate::mesh::NormalOptions;
+use crate::obj::{MaterialRange, Model, Submesh, Vertex, VertexAttribute};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecimateOptions {
+    /// Collapses stop once the model has this many triangles or fewer
+    pub target_triangles: usize,
+    /// Collapses moving the surface further than this from the faces it replaces are skipped, as
+    /// a fraction of the diagonal of the model bounds
+    pub max_error: f32,
+    /// Smoothing of the reduced triangles when the normals don't come from the file
+    pub normals: NormalOptions,
+}
+
+impl Default for DecimateOptions {
+    /// As few triangles as the topology allows
+    fn default() -> Self {
+        DecimateOptions {
+            target_triangles: 0,
+            max_error: f32::INFINITY,
+            normals: NormalOptions::default(),
+        }
+    }
+}
+
+/// Weight of the planes keeping borders and seams in place, against the area weight of the faces
+const FEATURE_WEIGHT: f64 = 10.0;
+/// Borders and seams turning by more than this angle, in degrees, have a corner that stays
+const CORNER_ANGLE: f32 = 45.0;
+
+/// Weighted sum of the squared distances to planes, with the area of the faces they come from
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric {
+    /// aa, ab, ac, ad, bb, bc, bd, cc, cd and dd, for the planes ax + by + cz + d = 0
+    terms: [f64; 10],
+    area: f64,
+}
+
+impl Quadric {
+    /// Plane through `point`, orthogonal to the unit vector `normal`
+    fn plane(normal: Vector3<f32>, point: Vector3<f32>, weight: f64) -> Quadric {
+        let [a, b, c] = [normal.x, normal.y, normal.z].map(f64::from);
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+        let terms = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        Quadric {
+            terms: terms.map(|t| t * weight),
+            area: 0.0,
+        }
+    }
+    fn add(&mut self, other: &Quadric) {
+        for (term, other) in self.terms.iter_mut().zip(other.terms) {
+            *term += other;
+        }
+        self.area += other.area;
+    }
+    fn error(&self, p: Vector3<f32>) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.terms;
+        let [x, y, z] = [p.x, p.y, p.z].map(f64::from);
+        let error = aa * x * x
+            + bb * y * y
+            + cc * z * z
+            + 2.0 * (ab * x * y + ac * x * z + bc * y * z)
+            + 2.0 * (ad * x + bd * y + cd * z)
+            + dd;
+        // rounding can make it slightly negative
+        error.max(0.0)
+    }
+    /// Root mean square distance to the planes of the faces
+    fn distance(&self, p: Vector3<f32>) -> f64 {
+        if self.area > 0.0 {
+            (self.error(p) / self.area).sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    /// Between two triangles sharing the vertices of both its ends
+    Inner,
+    /// Border, or seam between two sets of vertices, which keeps its shape
+    Feature,
+    /// Shared by more than 2 triangles
+    NonManifold,
+}
+
+/// Collapse of position `from` into position `to`, the cheapest on top of the heap
+struct Candidate {
+    cost: f64,
+    from: u32,
+    to: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// Triangles of the model over two levels of vertices : the positions, which collapse into each
+/// other, and the wedges, which give each corner its attributes. The vertices of a position with
+/// the same texture coordinates, file color and file normal are one wedge within one submesh and
+/// material. Triangles sharing a position but not a wedge meet on a seam
+struct Decimator {
+    positions: Vec<Vector3<f32>>,
+    /// First vertex of the model of each wedge
+    wedge_vertices: Vec<u32>,
+    wedge_positions: Vec<u32>,
+    /// As wedges
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    /// Triangles around each position, some of them dead
+    around: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    /// Positions of lines and points, and positions collapsed into another one
+    locked: Vec<bool>,
+}
+
+impl Decimator {
+    fn new(model: &Model) -> Decimator {
+        let mut position_ids = HashMap::new();
+        let mut positions = Vec::new();
+        let vertex_positions: Vec<u32> = (model.vertices.iter())
+            .map(|vertex| {
+                let p = vertex.position;
+                *position_ids
+                    .entry([p.x, p.y, p.z].map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(p);
+                        positions.len() as u32 - 1
+                    })
+            })
+            .collect();
+
+        // the submeshes and materials split the triangles in parts, whose outlines are seams
+        let mut part_starts: Vec<usize> = (model.submeshes.iter().map(|s| &s.indices))
+            .chain(model.material_ranges.iter().map(|r| &r.indices))
+            .flat_map(|range| [range.start, range.end])
+            .collect();
+        part_starts.sort_unstable();
+        part_starts.dedup();
+        let mut wedge_ids = HashMap::new();
+        let mut wedge_vertices = Vec::new();
+        // computed normals are computed again once reduced, they don't make seams
+        let attributes = |v: u32| {
+            let vertex = &model.vertices[v as usize];
+            let (s, t) = vertex.texture_coordinates;
+            let n = match model.file_normals {
+                true => vertex.normal,
+                false => Vector3::zero(),
+            };
+            let color = model.file_colors.get(v as usize).copied().flatten();
+            (
+                [s, t, n.x, n.y, n.z].map(f32::to_bits),
+                color.map(|c| [c.x, c.y, c.z].map(f32::to_bits)),
+            )
+        };
+        let triangles: Vec<[u32; 3]> = (model.indices.chunks_exact(3).enumerate())
+            .map(|(t, triangle)| {
+                let part = part_starts.partition_point(|&start| start <= t * 3);
+                [0, 1, 2].map(|k| {
+                    let v = triangle[k];
+                    let key = (vertex_positions[v as usize], part, attributes(v));
+                    *wedge_ids.entry(key).or_insert_with(|| {
+                        wedge_vertices.push(v);
+                        wedge_vertices.len() as u32 - 1
+                    })
+                })
+            })
+            .collect();
+        let wedge_positions = (wedge_vertices.iter())
+            .map(|&v| vertex_positions[v as usize])
+            .collect();
+
+        let mut around = vec![Vec::new(); positions.len()];
+        let mut locked = vec![false; positions.len()];
+        for &v in model.line_indices.iter().chain(&model.point_indices) {
+            locked[vertex_positions[v as usize] as usize] = true;
+        }
+        let mut decimator = Decimator {
+            quadrics: vec![Quadric::default(); positions.len()],
+            positions,
+            wedge_vertices,
+            wedge_positions,
+            alive: vec![true; triangles.len()],
+            triangles,
+            around: Vec::new(),
+            locked,
+        };
+        for t in 0..decimator.triangles.len() as u32 {
+            for corner in decimator.corners(t) {
+                around[corner as usize].push(t);
+            }
+        }
+        decimator.around = around;
+
+        for t in 0..decimator.triangles.len() as u32 {
+            let corners = decimator.corners(t);
+            let [a, b, c] = corners.map(|v| decimator.positions[v as usize]);
+            let normal = (b - a).cross(&(c - a));
+            let length = normal.norm();
+            if length == 0.0 {
+                continue;
+            }
+            let normal = normal / length;
+            let mut face = Quadric::plane(normal, a, length as f64 / 2.0);
+            face.area = length as f64 / 2.0;
+            for k in 0..3 {
+                decimator.quadrics[corners[k] as usize].add(&face);
+                // planes along the borders and seams keep them from moving sideways
+                let (from, to) = (corners[k], corners[(k + 1) % 3]);
+                if decimator.edge(from, to) == Edge::Feature {
+                    let edge =
+                        decimator.positions[to as usize] - decimator.positions[from as usize];
+                    let side = edge.cross(&normal);
+                    if side.norm2() > 0.0 {
+                        let weight = edge.norm2() as f64 * FEATURE_WEIGHT;
+                        let point = decimator.positions[from as usize];
+                        let constraint = Quadric::plane(side.normalized(), point, weight);
+                        decimator.quadrics[from as usize].add(&constraint);
+                        decimator.quadrics[to as usize].add(&constraint);
+                    }
+                }
+            }
+        }
+        decimator
+    }
+
+    /// Positions of the corners of triangle `t`
+    fn corners(&self, t: u32) -> [u32; 3] {
+        self.triangles[t as usize].map(|wedge| self.wedge_positions[wedge as usize])
+    }
+
+    /// Living triangles around position `v`
+    fn fan(&self, v: u32) -> impl Iterator<Item = u32> + '_ {
+        (self.around[v as usize].iter().copied()).filter(|&t| self.alive[t as usize])
+    }
+
+    /// Wedge of the corner of triangle `t` at position `v`
+    fn wedge(&self, t: u32, v: u32) -> u32 {
+        let k = self.corners(t).iter().position(|&c| c == v).unwrap();
+        self.triangles[t as usize][k]
+    }
+
+    /// Living triangles with both `a` and `b` as corners
+    fn edge_triangles(&self, a: u32, b: u32) -> Vec<u32> {
+        self.fan(a)
+            .filter(|&t| self.corners(t).contains(&b))
+            .collect()
+    }
+
+    fn edge(&self, a: u32, b: u32) -> Edge {
+        self.edge_kind(a, b, &self.edge_triangles(a, b))
+    }
+
+    /// Kind of the edge from `a` to `b`, whose living triangles are `triangles`
+    fn edge_kind(&self, a: u32, b: u32, triangles: &[u32]) -> Edge {
+        match triangles[..] {
+            [t, u] => {
+                // consistent windings go along the edge in opposite directions
+                let forward = |t: u32| {
+                    let corners = self.corners(t);
+                    (0..3).any(|k| corners[k] == a && corners[(k + 1) % 3] == b)
+                };
+                let same_wedges =
+                    self.wedge(t, a) == self.wedge(u, a) && self.wedge(t, b) == self.wedge(u, b);
+                if same_wedges && forward(t) != forward(u) {
+                    Edge::Inner
+                } else {
+                    Edge::Feature
+                }
+            }
+            [_] => Edge::Feature,
+            _ => Edge::NonManifold,
+        }
+    }
+
+    /// Positions sharing a triangle with `v`, sorted
+    fn neighbors(&self, v: u32) -> Vec<u32> {
+        let mut neighbors: Vec<_> = (self.fan(v).flat_map(|t| self.corners(t)))
+            .filter(|&c| c != v)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    /// Neighbors `v` can collapse into : any of them inside the surface, the two along the
+    /// border or seam it is on, and none for the corners of borders and seams, where they meet
+    /// or turn sharply, and for the vertices of borders of 3 edges
+    fn destinations(&self, v: u32) -> Vec<u32> {
+        if self.locked[v as usize] {
+            return Vec::new();
+        }
+        // the triangles of each edge in one pass, so vertices with many triangles stay cheap
+        let mut edges: Vec<(u32, u32)> = (self.fan(v))
+            .flat_map(|t| self.corners(t).map(|c| (c, t)))
+            .filter(|&(c, _)| c != v)
+            .collect();
+        edges.sort_unstable();
+        let mut neighbors = Vec::new();
+        let mut features = Vec::new();
+        for edge in edges.chunk_by(|a, b| a.0 == b.0) {
+            let n = edge[0].0;
+            let triangles: Vec<u32> = edge.iter().map(|&(_, t)| t).collect();
+            match self.edge_kind(v, n, &triangles) {
+                Edge::Inner => {}
+                Edge::Feature => features.push(n),
+                Edge::NonManifold => return Vec::new(),
+            }
+            neighbors.push(n);
+        }
+        match features[..] {
+            [] => neighbors,
+            [a, b] if !self.corner(v, a, b) => features,
+            _ => Vec::new(),
+        }
+    }
+
+    /// The border or seam going from `a` to `b` through `v` turns sharply there, or closes a
+    /// loop of 3 edges collapsing `v` would fold away
+    fn corner(&self, v: u32, a: u32, b: u32) -> bool {
+        let [p, a_position, b_position] = [v, a, b].map(|c| self.positions[c as usize]);
+        let (to_a, to_b) = (a_position - p, b_position - p);
+        let lengths = to_a.norm() * to_b.norm();
+        // straight through, the two edges point in opposite directions
+        let turn = lengths > 0.0 && to_a.dot(&to_b) / lengths > -CORNER_ANGLE.to_radians().cos();
+        let triangles = self.edge_triangles(a, b);
+        turn || (!triangles.is_empty() && self.edge_kind(a, b, &triangles) == Edge::Feature)
+    }
+
+    fn merged_quadric(&self, from: u32, to: u32) -> Quadric {
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+        quadric
+    }
+
+    fn push_candidates(&self, from: u32, heap: &mut BinaryHeap<Candidate>) {
+        for to in self.destinations(from) {
+            let cost = self
+                .merged_quadric(from, to)
+                .error(self.positions[to as usize]);
+            heap.push(Candidate { cost, from, to });
+        }
+    }
+
+    /// Wedges of `from` paired with the wedges of `to` on the same side of the seams, or None
+    /// when the collapse would tear, fold or flip the surface
+    fn wedge_map(&self, from: u32, to: u32) -> Option<Vec<(u32, u32)>> {
+        let shared = self.edge_triangles(from, to);
+        // all the triangles of `from` would go, and the surface with them
+        if shared.len() == self.fan(from).count() {
+            return None;
+        }
+        // the only common neighbors may be the third corners of the shared triangles, or the
+        // surface would pinch
+        let opposite: Vec<u32> = (shared.iter().flat_map(|&t| self.corners(t)))
+            .filter(|&c| c != from && c != to)
+            .collect();
+        let to_neighbors = self.neighbors(to);
+        let pinched = (self.neighbors(from).iter())
+            .any(|n| to_neighbors.binary_search(n).is_ok() && !opposite.contains(n));
+        if pinched {
+            return None;
+        }
+
+        let mut map: Vec<(u32, u32)> = Vec::new();
+        for &t in &shared {
+            let (wedge, target) = (self.wedge(t, from), self.wedge(t, to));
+            match map.iter().find(|(w, _)| *w == wedge) {
+                Some(&(_, other)) if other != target => return None,
+                Some(_) => {}
+                None => map.push((wedge, target)),
+            }
+        }
+        let target = self.positions[to as usize];
+        for t in self.fan(from).filter(|t| !shared.contains(t)) {
+            if !map.iter().any(|&(w, _)| w == self.wedge(t, from)) {
+                return None;
+            }
+            let corners = self.corners(t);
+            let mut points = corners.map(|c| self.positions[c as usize]);
+            let normal = |[a, b, c]: [Vector3<f32>; 3]| (b - a).cross(&(c - a));
+            let before = normal(points);
+            points[corners.iter().position(|&c| c == from).unwrap()] = target;
+            if before.norm2() > 0.0 && normal(points).dot(&before) <= 0.0 {
+                return None;
+            }
+            // a triangle already there, on the other side of a closed part
+            let mut moved = corners.map(|c| if c == from { to } else { c });
+            moved.sort_unstable();
+            let duplicate = self.fan(to).any(|u| {
+                let mut corners = self.corners(u);
+                corners.sort_unstable();
+                corners == moved
+            });
+            if duplicate {
+                return None;
+            }
+        }
+        Some(map)
+    }
+
+    /// Moves every corner of `from` to `to`, returns the number of triangles removed
+    fn collapse(&mut self, from: u32, to: u32, map: &[(u32, u32)]) -> usize {
+        let mut removed = 0;
+        let triangles = std::mem::take(&mut self.around[from as usize]);
+        for t in triangles {
+            if !self.alive[t as usize] {
+                continue;
+            }
+            let corners = self.corners(t);
+            if corners.contains(&to) {
+                self.alive[t as usize] = false;
+                removed += 1;
+                continue;
+            }
+            let k = corners.iter().position(|&c| c == from).unwrap();
+            let wedge = &mut self.triangles[t as usize][k];
+            *wedge = map.iter().find(|(w, _)| w == wedge).unwrap().1;
+            self.around[to as usize].push(t);
+        }
+        let alive = &self.alive;
+        self.around[to as usize].retain(|&t| alive[t as usize]);
+        let quadric = self.quadrics[from as usize];
+        self.quadrics[to as usize].add(&quadric);
+        self.locked[from as usize] = true;
+        removed
+    }
+
+    /// The model with the living triangles, and only the vertices still in use
+    fn into_model(self, model: &Model) -> Model {
+        let mut new_indices = vec![u32::MAX; model.vertices.len()];
+        let mut kept = Vec::new();
+        let mut keep = |v: u32| {
+            let index = &mut new_indices[v as usize];
+            if *index == u32::MAX {
+                *index = kept.len() as u32;
+                kept.push(v);
+            }
+            *index
+        };
+        // living triangles before each triangle of the model
+        let mut alive_before = Vec::with_capacity(self.triangles.len() + 1);
+        let mut indices = Vec::new();
+        for (triangle, &alive) in self.triangles.iter().zip(&self.alive) {
+            alive_before.push(indices.len());
+            if alive {
+                for &wedge in triangle {
+                    indices.push(keep(self.wedge_vertices[wedge as usize]));
+                }
+            }
+        }
+        alive_before.push(indices.len());
+        let line_indices: Box<[u32]> = model.line_indices.iter().map(|&v| keep(v)).collect();
+        let point_indices: Box<[u32]> = model.point_indices.iter().map(|&v| keep(v)).collect();
+        let range =
+            |range: &Range<usize>| alive_before[range.start / 3]..alive_before[range.end / 3];
+
+        let vertices = (kept.iter().map(|&v| &model.vertices[v as usize]))
+            .map(|vertex| Vertex {
+                position: vertex.position,
+                ..*vertex
+            })
+            .collect();
+        let mut reduced = Model::new(vertices, indices);
+        reduced.material_libs = model.material_libs.clone();
+        reduced.materials = model.materials.clone();
+        reduced.material_ranges = (model.material_ranges.iter())
+            .map(|r| MaterialRange {
+                material: r.material.clone(),
+                indices: range(&r.indices),
+            })
+            .collect();
+        reduced.submeshes = (model.submeshes.iter())
+            .map(|s| Submesh {
+                indices: range(&s.indices),
+                ..s.clone()
+            })
+            .filter(|s| !s.indices.is_empty())
+            .collect();
+        reduced.line_indices = line_indices;
+        reduced.line_strips = model.line_strips.clone();
+        reduced.point_indices = point_indices;
+        reduced.attributes = (model.attributes.iter())
+            .map(|attribute| VertexAttribute {
+                name: attribute.name.clone(),
+                values: pick(&attribute.values, &kept),
+            })
+            .collect();
+        if !model.file_colors.is_empty() {
+            reduced.file_colors = pick(&model.file_colors, &kept);
+        }
+        reduced.vertex_faces = pick(&model.vertex_faces, &kept);
+        reduced.face_count = model.face_count;
+        reduced.file_normals = model.file_normals;
+        reduced.file_texture_coordinates = model.file_texture_coordinates;
+        reduced
+    }
+}
+
+/// Values of the `kept` vertices
+fn pick<T: Copy>(values: &[T], kept: &[u32]) -> Box<[T]> {
+    kept.iter().map(|&v| values[v as usize]).collect()
+}
+
+/// Reduces the triangles of the model with quadric error metrics : the edge whose collapse moves
+/// the surface the least away from the planes of the original faces is collapsed first, until
+/// `options.target_triangles` is reached or every remaining collapse is over
+/// `options.max_error`
+/// Vertices collapse into one of their neighbors, so the reduced model only has vertices of
+/// the model, with all their attributes. Borders, texture and normal seams, and the outlines of
+/// submeshes and materials only collapse along themselves, their corners stay. Lines and points
+/// are kept as they are
+/// Normals that don't come from the file are computed again with `options.normals`
+pub fn decimate(model: &Model, options: &DecimateOptions) -> Model {
+    let mut decimator = Decimator::new(model);
+    let max_distance = options.max_error as f64 * model.bounds.size().norm() as f64;
+    let mut heap = BinaryHeap::new();
+    for v in 0..decimator.positions.len() as u32 {
+        decimator.push_candidates(v, &mut heap);
+    }
+    let mut triangles = decimator.triangles.len();
+    while triangles > options.target_triangles
+        && let Some(Candidate { cost, from, to }) = heap.pop()
+    {
+        // `from` collapsed since, or `to` into another position
+        if decimator.locked[from as usize] || decimator.fan(to).next().is_none() {
+            continue;
+        }
+        // the neighborhood changed since the candidate was pushed
+        if !decimator.destinations(from).contains(&to) {
+            continue;
+        }
+        let quadric = decimator.merged_quadric(from, to);
+        let target = decimator.positions[to as usize];
+        let current = quadric.error(target);
+        if current > cost {
+            heap.push(Candidate {
+                cost: current,
+                from,
+                to,
+            });
+            continue;
+        }
+        if quadric.distance(target) > max_distance {
+            continue;
+        }
+        let Some(map) = decimator.wedge_map(from, to) else {
+            continue;
+        };
+        triangles -= decimator.collapse(from, to, &map);
+        decimator.push_candidates(to, &mut heap);
+        // checked once popped, like the candidates the collapse made invalid
+        for n in decimator.neighbors(to) {
+            let cost = decimator.merged_quadric(n, to).error(target);
+            heap.push(Candidate { cost, from: n, to });
+        }
+    }
+    let mut reduced = decimator.into_model(model);
+    if !model.file_normals {
+        reduced.compute_normals(&options.normals);
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vector::Vector4;
+
+    fn vertex(position: Vector3<f32>, texture_coordinates: (f32, f32)) -> Vertex {
+        Vertex {
+            position,
+            color: Vector3::zero(),
+            texture_coordinates,
+            normal: Vector3::zero(),
+            tangent: Vector4::zero(),
+        }
+    }
+
+    /// Square of n by n cells at the given heights, with a texture seam down the middle if
+    /// `seam`, the texture of the right half being 10 to the right
+    fn grid(n: usize, height: impl Fn(f32, f32) -> f32, seam: bool) -> Model {
+        let mut ids = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for cell in 0..n * n {
+            let (i, j) = (cell % n, cell / n);
+            let side = (seam && i >= n / 2) as usize;
+            let mut corner = |i: usize, j: usize| {
+                *ids.entry((i, j, side)).or_insert_with(|| {
+                    let (x, y) = (i as f32 / n as f32, j as f32 / n as f32);
+                    let position = Vector3 {
+                        x,
+                        y,
+                        z: height(x, y),
+                    };
+                    vertices.push(vertex(position, (x + 10.0 * side as f32, y)));
+                    vertices.len() as u32 - 1
+                })
+            };
+            let [a, b, c, d] =
+                [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)].map(|(i, j)| corner(i, j));
+            indices.extend([a, b, c, a, c, d]);
+        }
+        Model::new(vertices, indices)
+    }
+
+    /// Unit cube whose sides are grids of n by n cells, with the normals of their side
+    fn cube(n: usize) -> Model {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for axis in 0..3 {
+            for side in [0.0, 1.0] {
+                let start = vertices.len() as u32;
+                let mut normal = [0.0; 3];
+                normal[axis] = side * 2.0 - 1.0;
+                for (i, j) in (0..=n).flat_map(|j| (0..=n).map(move |i| (i, j))) {
+                    let mut p = [0.0; 3];
+                    p[axis] = side;
+                    p[(axis + 1) % 3] = i as f32 / n as f32;
+                    p[(axis + 2) % 3] = j as f32 / n as f32;
+                    let [x, y, z] = p;
+                    let mut vertex = vertex(Vector3 { x, y, z }, (0.0, 0.0));
+                    let [x, y, z] = normal;
+                    vertex.normal = Vector3 { x, y, z };
+                    vertices.push(vertex);
+                }
+                for (i, j) in (0..n).flat_map(|j| (0..n).map(move |i| (i, j))) {
+                    let corner = |i: usize, j: usize| start + (j * (n + 1) + i) as u32;
+                    let mut quad = [
+                        corner(i, j),
+                        corner(i + 1, j),
+                        corner(i + 1, j + 1),
+                        corner(i, j + 1),
+                    ];
+                    if side == 0.0 {
+                        quad.reverse();
+                    }
+                    let [a, b, c, d] = quad;
+                    indices.extend([a, b, c, a, c, d]);
+                }
+            }
+        }
+        let mut model = Model::new(vertices, indices);
+        model.file_normals = true;
+        model
+    }
+
+    fn exact() -> DecimateOptions {
+        DecimateOptions {
+            max_error: 1e-6,
+            ..DecimateOptions::default()
+        }
+    }
+
+    /// Triangles sharing each edge, from their positions
+    fn edge_uses(model: &Model) -> HashMap<[[u32; 3]; 2], usize> {
+        let bits = |v: u32| {
+            let p = model.vertices[v as usize].position;
+            [p.x, p.y, p.z].map(f32::to_bits)
+        };
+        let mut uses = HashMap::new();
+        for triangle in model.indices.chunks_exact(3) {
+            for k in 0..3 {
+                let mut edge = [bits(triangle[k]), bits(triangle[(k + 1) % 3])];
+                edge.sort();
+                *uses.entry(edge).or_insert(0) += 1;
+            }
+        }
+        uses
+    }
+
+    #[test]
+    fn flat_grid_keeps_its_outline() {
+        let model = grid(8, |_, _| 0.0, false);
+        let reduced = decimate(&model, &exact());
+        assert_eq!(reduced.indices.len(), 6);
+        let corners = |model: &Model| -> Vec<_> {
+            let mut corners: Vec<_> = (model.indices.iter())
+                .map(|&i| model.vertices[i as usize].position)
+                .map(|p| [p.x, p.y, p.z].map(f32::to_bits))
+                .collect();
+            corners.sort();
+            corners.dedup();
+            corners
+        };
+        assert_eq!(corners(&reduced).len(), 4);
+        assert_eq!(
+            format!("{:?}", reduced.bounds),
+            format!("{:?}", model.bounds)
+        );
+        for vertex in reduced.vertices.iter() {
+            let n = vertex.normal;
+            assert_eq!([n.x, n.y, n.z], [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn texture_seams_stay_in_place() {
+        let model = grid(8, |_, _| 0.0, true);
+        let reduced = decimate(&model, &exact());
+        // two triangles on each side of the seam, which does not move
+        assert_eq!(reduced.indices.len(), 12);
+        for triangle in reduced.indices.chunks_exact(3) {
+            let corners = [0, 1, 2].map(|k| &reduced.vertices[triangle[k] as usize]);
+            let right = corners[0].texture_coordinates.0 >= 5.0;
+            for corner in corners {
+                let (u, v) = corner.texture_coordinates;
+                let p = corner.position;
+                assert_eq!(u >= 5.0, right);
+                assert_eq!((u - 10.0 * right as u32 as f32, v), (p.x, p.y));
+                assert!(if right { p.x >= 0.5 } else { p.x <= 0.5 });
+            }
+        }
+    }
+
+    #[test]
+    fn target_triangle_count() {
+        let bumps = |x: f32, y: f32| (x * 7.0).sin() * (y * 5.0).cos() * 0.2;
+        let mut model = grid(16, bumps, false);
+        model.submeshes = vec![
+            Submesh {
+                object: String::new(),
+                group: "bottom".into(),
+                indices: 0..768,
+            },
+            Submesh {
+                object: String::new(),
+                group: "top".into(),
+                indices: 768..1536,
+            },
+        ];
+        let options = DecimateOptions {
+            target_triangles: 100,
+            ..DecimateOptions::default()
+        };
+        let reduced = decimate(&model, &options);
+        assert!((90..=100).contains(&(reduced.indices.len() / 3)));
+        assert_eq!(reduced.submeshes.len(), 2);
+        assert_eq!(reduced.submeshes[0].indices.start, 0);
+        assert_eq!(
+            reduced.submeshes[0].indices.end,
+            reduced.submeshes[1].indices.start
+        );
+        assert_eq!(reduced.submeshes[1].indices.end, reduced.indices.len());
+        // the seam between the submeshes stays straight
+        for submesh in &reduced.submeshes {
+            let bottom = submesh.group == "bottom";
+            for &i in &reduced.indices[submesh.indices.clone()] {
+                let y = reduced.vertices[i as usize].position.y;
+                assert!(if bottom { y <= 0.5 } else { y >= 0.5 });
+            }
+        }
+        let fewer = DecimateOptions {
+            max_error: 0.001,
+            ..options
+        };
+        assert!(decimate(&model, &fewer).indices.len() > reduced.indices.len());
+    }
+
+    #[test]
+    fn closed_cube_stays_closed() {
+        let reduced = decimate(&cube(4), &DecimateOptions::default());
+        assert_eq!(reduced.indices.len(), 36);
+        assert!(edge_uses(&reduced).values().all(|&uses| uses == 2));
+        for &i in reduced.indices.iter() {
+            let n = reduced.vertices[i as usize].normal;
+            assert_eq!(n.norm(), 1.0);
+        }
+    }
+
+    #[test]
+    fn open_surfaces_stay() {
+        let all = DecimateOptions::default();
+        let point = |x: f32, y: f32| vertex(Vector3 { x, y, z: 0.0 }, (x, y));
+        let triangle = Model::new(
+            vec![point(0.0, 0.0), point(1.0, 0.0), point(0.0, 1.0)],
+            vec![0, 1, 2],
+        );
+        assert_eq!(decimate(&triangle, &all).indices.len(), 3);
+        let quad = grid(1, |_, _| 0.0, false);
+        assert_eq!(decimate(&quad, &all).indices.len(), 6);
+        // a quad with a vertex in the middle of its bottom side loses that vertex only
+        let vertices = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (2.0, 1.0), (0.0, 1.0)];
+        let vertices = vertices.map(|(x, y)| point(x, y)).into();
+        let quad = Model::new(vertices, vec![0, 1, 4, 1, 3, 4, 1, 2, 3]);
+        let reduced = decimate(&quad, &all);
+        assert_eq!(reduced.indices.len(), 6);
+        assert_eq!(
+            format!("{:?}", reduced.bounds),
+            format!("{:?}", quad.bounds)
+        );
+        // the sharp corners of a fine grid stay too
+        let reduced = decimate(&grid(8, |_, _| 0.0, false), &all);
+        assert_eq!(reduced.indices.len(), 6);
+    }
+}
@@ -9,12 +9,17 @@ uniform sampler2D texture1;
 uniform sampler2D texture2;
 uniform vec3 lightPos;
 uniform float TextureOrColor;
+uniform vec3 diffuseColor;
+uniform float useTexture;
+uniform float unlit;
 
 void main()
 {
-    vec4 objColor = texture(texture1, TexCoord);
+    vec4 objColor = mix(vec4(1.0), texture(texture1, TexCoord), useTexture) * vec4(diffuseColor, 1.0);
     vec3 lightDir = normalize(lightPos - FragPos);
     vec3 norm = normalize(vertexNorm);
     vec4 lightColor = max(dot(norm, lightDir), 0.0) * vec4(1.0, 1.0, 1.0, 1.0) + vec4(0.3, 0.3, 0.3, 1.0);
+    // lines and points have no normal
+    lightColor = mix(lightColor, vec4(1.0), unlit);
     FragColor = mix(lightColor * objColor, + vec4(vertexColor, 1.0), TextureOrColor);
 }
@@ -9,6 +9,8 @@ use std::fs::File;
 use std::io::Error as IOError;
 use std::io::Read;
 use std::mem;
+use std::ops::Range;
+use std::path::Path;
 use std::ptr;
 
 use crate::obj::Vertex;
@@ -163,10 +165,19 @@ pub mod vao {
                     mem::size_of::<Vertex>() as i32,
                     (mem::size_of::<f32>() * 8) as *const c_void,
                 );
+                gl::VertexAttribPointer(
+                    4,
+                    4,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mem::size_of::<Vertex>() as i32,
+                    (mem::size_of::<f32>() * 11) as *const c_void,
+                );
                 gl::EnableVertexArrayAttrib(self.raw(), 0);
                 gl::EnableVertexArrayAttrib(self.raw(), 1);
                 gl::EnableVertexArrayAttrib(self.raw(), 2);
                 gl::EnableVertexArrayAttrib(self.raw(), 3);
+                gl::EnableVertexArrayAttrib(self.raw(), 4);
             };
         }
         pub fn unbind_vbo(&mut self) {
@@ -210,6 +221,21 @@ pub mod vao {
             };
             get_error().unwrap()
         }
+        /// Draws the primitives of `range`, a range of indices into the ebo
+        /// `mode` is the primitive type: gl::TRIANGLES, gl::LINES, gl::LINE_STRIP, gl::POINTS...
+        pub fn draw_elements_range(&self, mode: GLenum, range: Range<usize>) {
+            let ebo = self.vao.ebo.unwrap().borrow();
+            assert!(range.end <= ebo.length());
+            unsafe {
+                gl::DrawElements(
+                    mode,
+                    range.len() as i32,
+                    gl::UNSIGNED_INT,
+                    (range.start * mem::size_of::<u32>()) as *const c_void,
+                )
+            };
+            get_error().unwrap()
+        }
     }
 }
 
@@ -647,7 +673,7 @@ pub mod texture {
         }
         pub fn bind_data_from_path(
             &self,
-            path: &str,
+            path: impl AsRef<Path>,
             active_context: &mut ActiveContext,
         ) -> image::error::ImageResult<()> {
             let mut img = image::ImageReader::open(path)?.decode()?;
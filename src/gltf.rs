@@ -0,0 +1,831 @@
+pub mod json;
+mod writer;
+
+pub use writer::{GlbWriteOptions, write_glb};
+
+use crate::color::ColorScheme;
+use crate::math::matrix::Mat4;
+use crate::math::vector::{Vector3, Vector4};
+use crate::obj::{Model, Submesh, Vertex, transform_vertices};
+use crate::uv::UvOptions;
+use json::{Json, JsonError};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{Error as IOError, Read};
+use std::ops::Range;
+use std::path::Path;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const CHUNK_JSON: u32 = 0x4e4f534a;
+const CHUNK_BIN: u32 = 0x004e4942;
+/// Required extensions that change nothing for this loader
+const SUPPORTED_EXTENSIONS: [&str; 1] = ["KHR_mesh_quantization"];
+
+#[derive(Debug)]
+pub enum GltfError {
+    IOError(IOError),
+    Json(JsonError),
+    InvalidGlb(&'static str),
+    /// Missing or invalid member of an object, like `count` of `accessors[3]`
+    InvalidMember {
+        path: String,
+        member: &'static str,
+    },
+    IndexOutOfBound {
+        array: &'static str,
+        index: usize,
+    },
+    Unsupported(String),
+}
+
+impl Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::IOError(e) => write!(f, "{e}"),
+            GltfError::Json(e) => write!(f, "{e}"),
+            GltfError::InvalidGlb(e) => write!(f, "Invalid glb : {e}"),
+            GltfError::InvalidMember { path, member } => {
+                write!(f, "Missing or invalid '{member}' in {path}")
+            }
+            GltfError::IndexOutOfBound { array, index } => {
+                write!(f, "{array}[{index}] does not exist")
+            }
+            GltfError::Unsupported(t) => write!(f, "Unsupported {t}"),
+        }
+    }
+}
+
+impl Error for GltfError {}
+
+/// Whether `start` is the beginning of a glb file
+pub fn is_glb(start: &[u8]) -> bool {
+    start.starts_with(GLB_MAGIC)
+}
+
+/// An object of the document, with its path for the error messages
+struct Object<'a> {
+    json: &'a Json,
+    path: String,
+}
+
+impl<'a> Object<'a> {
+    fn invalid(&self, member: &'static str) -> GltfError {
+        GltfError::InvalidMember {
+            path: self.path.clone(),
+            member,
+        }
+    }
+    fn get(&self, member: &str) -> Option<&'a Json> {
+        self.json.get(member)
+    }
+    fn child(&self, member: &'static str) -> Result<Object<'a>, GltfError> {
+        match self.get(member) {
+            Some(json @ Json::Object(_)) => Ok(Object {
+                json,
+                path: format!("{}.{member}", self.path),
+            }),
+            _ => Err(self.invalid(member)),
+        }
+    }
+    fn optional_usize(&self, member: &'static str) -> Result<Option<usize>, GltfError> {
+        self.get(member)
+            .map(|value| value.as_usize().ok_or_else(|| self.invalid(member)))
+            .transpose()
+    }
+    fn usize(&self, member: &'static str) -> Result<usize, GltfError> {
+        self.optional_usize(member)?
+            .ok_or_else(|| self.invalid(member))
+    }
+    fn floats<const N: usize>(&self, member: &'static str) -> Result<Option<[f32; N]>, GltfError> {
+        let Some(value) = self.get(member) else {
+            return Ok(None);
+        };
+        value
+            .as_array()
+            .filter(|values| values.len() == N)
+            .and_then(|values| {
+                let floats: Option<Vec<_>> = values
+                    .iter()
+                    .map(|v| v.as_f64().map(|v| v as f32))
+                    .collect();
+                floats?.try_into().ok()
+            })
+            .map(Some)
+            .ok_or_else(|| self.invalid(member))
+    }
+    fn name(&self) -> Option<&'a str> {
+        self.get("name").and_then(Json::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ComponentType {
+    I8,
+    U8,
+    I16,
+    U16,
+    U32,
+    F32,
+}
+
+impl ComponentType {
+    fn from_code(code: usize) -> Option<ComponentType> {
+        Some(match code {
+            5120 => ComponentType::I8,
+            5121 => ComponentType::U8,
+            5122 => ComponentType::I16,
+            5123 => ComponentType::U16,
+            5125 => ComponentType::U32,
+            5126 => ComponentType::F32,
+            _ => return None,
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            ComponentType::I8 | ComponentType::U8 => 1,
+            ComponentType::I16 | ComponentType::U16 => 2,
+            ComponentType::U32 | ComponentType::F32 => 4,
+        }
+    }
+    /// Reads a little endian value, mapping normalized integers to [-1, 1] or [0, 1]
+    fn read(self, bytes: &[u8], normalized: bool) -> f64 {
+        let (value, max) = match self {
+            ComponentType::I8 => (bytes[0] as i8 as f64, i8::MAX as f64),
+            ComponentType::U8 => (bytes[0] as f64, u8::MAX as f64),
+            ComponentType::I16 => (
+                i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                i16::MAX as f64,
+            ),
+            ComponentType::U16 => (
+                u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                u16::MAX as f64,
+            ),
+            ComponentType::U32 => {
+                let value = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+                (value as f64, u32::MAX as f64)
+            }
+            ComponentType::F32 => {
+                return f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64;
+            }
+        };
+        if normalized {
+            (value / max).max(-1.0)
+        } else {
+            value
+        }
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for b in text.bytes() {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Relative uris can have percent encoded characters, like spaces
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// JSON and binary chunks of a glb file
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    let word = |at: usize| {
+        data.get(at..at + 4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+    };
+    let (Some(version), Some(length)) = (word(4), word(8)) else {
+        return Err(GltfError::InvalidGlb("Header is truncated"));
+    };
+    if version != 2 {
+        return Err(GltfError::Unsupported(format!("glb version {version}")));
+    }
+    let data = data
+        .get(..length as usize)
+        .ok_or(GltfError::InvalidGlb("File is truncated"))?;
+    let mut chunks = Vec::new();
+    let mut position = 12;
+    while position < data.len() {
+        let (Some(chunk_length), Some(chunk_type)) = (word(position), word(position + 4)) else {
+            return Err(GltfError::InvalidGlb("Chunk header is truncated"));
+        };
+        let start = position + 8;
+        let chunk = data
+            .get(start..start + chunk_length as usize)
+            .ok_or(GltfError::InvalidGlb("Chunk is truncated"))?;
+        chunks.push((chunk_type, chunk));
+        position = start + chunk_length as usize;
+    }
+    match chunks.as_slice() {
+        [(CHUNK_JSON, json), rest @ ..] => Ok((
+            json,
+            rest.iter()
+                .find(|(t, _)| *t == CHUNK_BIN)
+                .map(|(_, bin)| *bin),
+        )),
+        _ => Err(GltfError::InvalidGlb("The first chunk is not JSON")),
+    }
+}
+
+struct Document<'a> {
+    json: &'a Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+/// Vertices and elements of the model, in the order of the primitives
+#[derive(Default)]
+struct Builder {
+    vertices: Vec<Vertex>,
+    /// Whether the file gave the color of each vertex
+    has_color: Vec<bool>,
+    has_texture_coords: Vec<bool>,
+    /// Whether a primitive gave its normals
+    has_normals: bool,
+    indices: Vec<u32>,
+    submeshes: Vec<Submesh>,
+    line_indices: Vec<u32>,
+    line_strips: Vec<Range<usize>>,
+    point_indices: Vec<u32>,
+}
+
+impl Builder {
+    fn push(&mut self, vertex: Vertex, has_color: bool, has_texture_coords: bool) -> u32 {
+        self.vertices.push(vertex);
+        self.has_color.push(has_color);
+        self.has_texture_coords.push(has_texture_coords);
+        (self.vertices.len() - 1) as u32
+    }
+    fn push_triangle(&mut self, triangle: [u32; 3]) {
+        self.indices.extend(triangle);
+    }
+}
+
+impl<'a> Document<'a> {
+    /// Element `index` of the top level array `array`
+    fn object(&self, array: &'static str, index: usize) -> Result<Object<'a>, GltfError> {
+        self.json
+            .get(array)
+            .and_then(Json::as_array)
+            .and_then(|values| values.get(index))
+            .filter(|value| matches!(value, Json::Object(_)))
+            .map(|json| Object {
+                json,
+                path: format!("{array}[{index}]"),
+            })
+            .ok_or(GltfError::IndexOutOfBound { array, index })
+    }
+    fn array_len(&self, array: &str) -> usize {
+        self.json
+            .get(array)
+            .and_then(Json::as_array)
+            .map_or(0, |a| a.len())
+    }
+
+    fn load_buffers(&mut self, glb_bin: Option<&[u8]>, dir: &Path) -> Result<(), GltfError> {
+        for index in 0..self.array_len("buffers") {
+            let buffer = self.object("buffers", index)?;
+            let length = buffer.usize("byteLength")?;
+            let data = match buffer.get("uri") {
+                None => match glb_bin {
+                    Some(bin) if index == 0 => bin.to_vec(),
+                    _ => return Err(buffer.invalid("uri")),
+                },
+                Some(uri) => {
+                    let uri = uri.as_str().ok_or_else(|| buffer.invalid("uri"))?;
+                    if let Some(data) = uri.strip_prefix("data:") {
+                        let (_, encoded) = data
+                            .split_once(";base64,")
+                            .ok_or_else(|| buffer.invalid("uri"))?;
+                        decode_base64(encoded).ok_or_else(|| buffer.invalid("uri"))?
+                    } else if uri.contains("://") {
+                        return Err(GltfError::Unsupported(format!("remote buffer {uri}")));
+                    } else {
+                        fs::read(dir.join(percent_decode(uri))).map_err(GltfError::IOError)?
+                    }
+                }
+            };
+            if data.len() < length {
+                return Err(buffer.invalid("byteLength"));
+            }
+            self.buffers.push(data);
+        }
+        Ok(())
+    }
+
+    /// `count` elements of `components` values, read from a buffer view
+    fn read_view(
+        &self,
+        view: usize,
+        offset: usize,
+        component_type: ComponentType,
+        normalized: bool,
+        (count, components): (usize, usize),
+        values: &mut Vec<f64>,
+    ) -> Result<(), GltfError> {
+        let view = self.object("bufferViews", view)?;
+        let buffer_index = view.usize("buffer")?;
+        let buffer = self
+            .buffers
+            .get(buffer_index)
+            .ok_or(GltfError::IndexOutOfBound {
+                array: "buffers",
+                index: buffer_index,
+            })?;
+        let view_offset = view.optional_usize("byteOffset")?.unwrap_or(0);
+        let view_length = view.usize("byteLength")?;
+        let data = buffer
+            .get(view_offset..view_offset + view_length)
+            .ok_or_else(|| view.invalid("byteLength"))?;
+        let element_size = component_type.size() * components;
+        let stride = view.optional_usize("byteStride")?.unwrap_or(element_size);
+        if count > 0 && offset + stride * (count - 1) + element_size > data.len() {
+            return Err(view.invalid("byteLength"));
+        }
+        values.reserve(count * components);
+        for i in 0..count {
+            let element = &data[offset + i * stride..];
+            values.extend(
+                (0..components).map(|c| {
+                    component_type.read(&element[c * component_type.size()..], normalized)
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Values of an accessor, and the number of values of each element
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), GltfError> {
+        let accessor = self.object("accessors", index)?;
+        let component_type = ComponentType::from_code(accessor.usize("componentType")?)
+            .ok_or_else(|| accessor.invalid("componentType"))?;
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        let count = accessor.usize("count")?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4" | "MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(accessor.invalid("type")),
+        };
+        let mut values = Vec::new();
+        match accessor.optional_usize("bufferView")? {
+            Some(view) => {
+                let offset = accessor.optional_usize("byteOffset")?.unwrap_or(0);
+                self.read_view(
+                    view,
+                    offset,
+                    component_type,
+                    normalized,
+                    (count, components),
+                    &mut values,
+                )?;
+            }
+            None => values.resize(count * components, 0.0),
+        }
+        if accessor.get("sparse").is_some() {
+            // replaces some of the elements
+            let sparse = accessor.child("sparse")?;
+            let sparse_count = sparse.usize("count")?;
+            let indices = sparse.child("indices")?;
+            let index_type = ComponentType::from_code(indices.usize("componentType")?)
+                .ok_or_else(|| indices.invalid("componentType"))?;
+            let mut sparse_indices = Vec::new();
+            self.read_view(
+                indices.usize("bufferView")?,
+                indices.optional_usize("byteOffset")?.unwrap_or(0),
+                index_type,
+                false,
+                (sparse_count, 1),
+                &mut sparse_indices,
+            )?;
+            let sparse_values = sparse.child("values")?;
+            let mut replacements = Vec::new();
+            self.read_view(
+                sparse_values.usize("bufferView")?,
+                sparse_values.optional_usize("byteOffset")?.unwrap_or(0),
+                component_type,
+                normalized,
+                (sparse_count, components),
+                &mut replacements,
+            )?;
+            for (&i, replacement) in sparse_indices
+                .iter()
+                .zip(replacements.chunks_exact(components))
+            {
+                let i = i as usize;
+                if i >= count {
+                    return Err(indices.invalid("bufferView"));
+                }
+                values[i * components..(i + 1) * components].copy_from_slice(replacement);
+            }
+        }
+        Ok((values, components))
+    }
+
+    /// Accessor of a vertex attribute, which must have `components` values per vertex
+    fn attribute(
+        &self,
+        attributes: &Object,
+        name: &'static str,
+        components: &[usize],
+        vertex_count: Option<usize>,
+    ) -> Result<Option<(Vec<f64>, usize)>, GltfError> {
+        let Some(index) = attributes.optional_usize(name)? else {
+            return Ok(None);
+        };
+        let (values, size) = self.accessor(index)?;
+        if !components.contains(&size) || vertex_count.is_some_and(|n| values.len() != n * size) {
+            return Err(attributes.invalid(name));
+        }
+        Ok(Some((values, size)))
+    }
+
+    fn add_primitive(
+        &self,
+        primitive: &Object,
+        world: &Mat4<f32>,
+        mirrored: bool,
+        builder: &mut Builder,
+    ) -> Result<(), GltfError> {
+        let attributes = primitive.child("attributes")?;
+        let (positions, _) = self
+            .attribute(&attributes, "POSITION", &[3], None)?
+            .ok_or_else(|| attributes.invalid("POSITION"))?;
+        let count = positions.len() / 3;
+        let normals = self.attribute(&attributes, "NORMAL", &[3], Some(count))?;
+        let tangents = self.attribute(&attributes, "TANGENT", &[4], Some(count))?;
+        let texture_coords = self.attribute(&attributes, "TEXCOORD_0", &[2], Some(count))?;
+        let colors = self.attribute(&attributes, "COLOR_0", &[3, 4], Some(count))?;
+        let vector = |values: &[f64], i: usize, size: usize| Vector3 {
+            x: values[i * size] as f32,
+            y: values[i * size + 1] as f32,
+            z: values[i * size + 2] as f32,
+        };
+        let mut vertices: Vec<_> = (0..count)
+            .map(|i| Vertex {
+                position: vector(&positions, i, 3),
+                color: colors
+                    .as_ref()
+                    .map_or(Vector3::zero(), |(c, size)| vector(c, i, *size)),
+                texture_coordinates: texture_coords.as_ref().map_or((0.0, 0.0), |(uv, _)| {
+                    (uv[i * 2] as f32, uv[i * 2 + 1] as f32)
+                }),
+                normal: normals
+                    .as_ref()
+                    .map_or(Vector3::zero(), |(n, _)| vector(n, i, 3)),
+                tangent: tangents.as_ref().map_or(Vector4::zero(), |(t, _)| Vector4 {
+                    x: t[i * 4] as f32,
+                    y: t[i * 4 + 1] as f32,
+                    z: t[i * 4 + 2] as f32,
+                    w: t[i * 4 + 3] as f32,
+                }),
+            })
+            .collect();
+        transform_vertices(&mut vertices, world);
+
+        let indices: Vec<u32> = match primitive.optional_usize("indices")? {
+            Some(accessor) => {
+                let (indices, size) = self.accessor(accessor)?;
+                if size != 1 || indices.iter().any(|&i| i as usize >= count) {
+                    return Err(primitive.invalid("indices"));
+                }
+                indices.into_iter().map(|i| i as u32).collect()
+            }
+            None => (0..count as u32).collect(),
+        };
+        let mode = primitive.optional_usize("mode")?.unwrap_or(4);
+        let mut triangles: Vec<[u32; 3]> = match mode {
+            4 => indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            // strip, every other triangle is reversed to keep the winding
+            5 => (0..indices.len().saturating_sub(2))
+                .map(|i| match i % 2 {
+                    0 => [indices[i], indices[i + 1], indices[i + 2]],
+                    _ => [indices[i + 1], indices[i], indices[i + 2]],
+                })
+                .collect(),
+            // fan
+            6 => (1..indices.len().saturating_sub(1))
+                .map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+            0..=3 => Vec::new(),
+            _ => return Err(primitive.invalid("mode")),
+        };
+        if mirrored {
+            for triangle in &mut triangles {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let has_color = colors.is_some();
+        let has_texture_coords = texture_coords.is_some();
+        builder.has_normals |= normals.is_some();
+        if normals.is_none() && !triangles.is_empty() {
+            // flat normals, so every triangle has its own vertices
+            for triangle in triangles {
+                let [a, b, c] = triangle.map(|i| &vertices[i as usize]);
+                let normal = (b.position - a.position).cross(&(c.position - a.position));
+                let normal = if normal.norm2() > 0.0 {
+                    normal.normalized()
+                } else {
+                    normal
+                };
+                let corners = triangle.map(|i| {
+                    let vertex = &vertices[i as usize];
+                    builder.push(Vertex { normal, ..*vertex }, has_color, has_texture_coords)
+                });
+                builder.push_triangle(corners);
+            }
+        } else {
+            let first = builder.vertices.len() as u32;
+            for vertex in vertices {
+                builder.push(vertex, has_color, has_texture_coords);
+            }
+            for triangle in triangles {
+                builder.push_triangle(triangle.map(|i| first + i));
+            }
+            let indices = indices.iter().map(|i| first + i);
+            match mode {
+                0 => builder.point_indices.extend(indices),
+                1 => {
+                    for pair in indices.collect::<Vec<_>>().chunks_exact(2) {
+                        let strip = builder.line_indices.len();
+                        builder.line_indices.extend(pair);
+                        builder.line_strips.push(strip..strip + 2);
+                    }
+                }
+                2 | 3 => {
+                    let strip = builder.line_indices.len();
+                    builder.line_indices.extend(indices);
+                    // line loop
+                    if mode == 2 && builder.line_indices.len() > strip {
+                        builder.line_indices.push(builder.line_indices[strip]);
+                    }
+                    builder.line_strips.push(strip..builder.line_indices.len());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_node(
+        &self,
+        index: usize,
+        parent: &Mat4<f32>,
+        depth: usize,
+        builder: &mut Builder,
+    ) -> Result<(), GltfError> {
+        let node = self.object("nodes", index)?;
+        if depth > self.array_len("nodes") {
+            // only possible with a cycle
+            return Err(node.invalid("children"));
+        }
+        let local = match node.floats::<16>("matrix")? {
+            // column major
+            Some(m) => Mat4 {
+                components: [0, 1, 2, 3].map(|row| [0, 1, 2, 3].map(|col| m[col * 4 + row])),
+            },
+            None => {
+                let [x, y, z] = node.floats("translation")?.unwrap_or([0.0; 3]);
+                let [qx, qy, qz, qw] = node.floats("rotation")?.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+                let [sx, sy, sz] = node.floats("scale")?.unwrap_or([1.0; 3]);
+                let rotation = Vector4 {
+                    x: qx,
+                    y: qy,
+                    z: qz,
+                    w: qw,
+                };
+                Mat4::translate(&Vector3 { x, y, z })
+                    * Mat4::from_quaternion(&rotation)
+                    * Mat4::scale(&Vector3 {
+                        x: sx,
+                        y: sy,
+                        z: sz,
+                    })
+            }
+        };
+        let world = parent * &local;
+        if let Some(mesh_index) = node.optional_usize("mesh")? {
+            let mesh = self.object("meshes", mesh_index)?;
+            let m = &world.components;
+            let determinant = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+            let primitives = mesh
+                .get("primitives")
+                .and_then(Json::as_array)
+                .ok_or_else(|| mesh.invalid("primitives"))?;
+            for (k, json) in primitives.iter().enumerate() {
+                let primitive = Object {
+                    json,
+                    path: format!("{}.primitives[{k}]", mesh.path),
+                };
+                builder.submeshes.push(Submesh {
+                    object: node.name().map_or(format!("node {index}"), String::from),
+                    group: mesh
+                        .name()
+                        .map_or(format!("mesh {mesh_index}"), String::from),
+                    indices: builder.indices.len()..builder.indices.len(),
+                });
+                self.add_primitive(&primitive, &world, determinant < 0.0, builder)?;
+                if let Some(last) = builder.submeshes.last_mut() {
+                    last.indices.end = builder.indices.len();
+                }
+            }
+        }
+        if let Some(children) = node.get("children") {
+            let children = children
+                .as_array()
+                .ok_or_else(|| node.invalid("children"))?;
+            for child in children {
+                let child = child.as_usize().ok_or_else(|| node.invalid("children"))?;
+                self.add_node(child, &world, depth + 1, builder)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Nodes of the default scene, or every node without a parent if there are no scenes
+    fn root_nodes(&self) -> Result<Vec<usize>, GltfError> {
+        let scene = match self.json.get("scene") {
+            Some(scene) => Some(scene.as_usize().ok_or(GltfError::InvalidMember {
+                path: "the document".into(),
+                member: "scene",
+            })?),
+            None if self.array_len("scenes") > 0 => Some(0),
+            None => None,
+        };
+        match scene {
+            Some(scene) => {
+                let scene = self.object("scenes", scene)?;
+                let Some(nodes) = scene.get("nodes") else {
+                    return Ok(Vec::new());
+                };
+                nodes
+                    .as_array()
+                    .and_then(|nodes| nodes.iter().map(Json::as_usize).collect())
+                    .ok_or_else(|| scene.invalid("nodes"))
+            }
+            None => {
+                let children: Vec<usize> = self
+                    .json
+                    .get("nodes")
+                    .and_then(Json::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|node| node.get("children").and_then(Json::as_array))
+                    .flatten()
+                    .filter_map(Json::as_usize)
+                    .collect();
+                Ok((0..self.array_len("nodes"))
+                    .filter(|i| !children.contains(i))
+                    .collect())
+            }
+        }
+    }
+}
+
+fn json_error(text: &[u8], valid_up_to: usize) -> GltfError {
+    let before = &text[..valid_up_to];
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    GltfError::Json(JsonError {
+        line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+        column: valid_up_to - line_start + 1,
+        message: "Invalid utf-8",
+    })
+}
+
+/// Parses a .gltf or .glb file, external buffers are looked up relative to `dir`
+/// Every mesh of the default scene becomes submeshes of the model, named after its node and
+/// mesh, with the world transform of its node applied
+pub fn parse_gltf(mut reader: impl Read, dir: &Path) -> Result<Model, GltfError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(GltfError::IOError)?;
+    let (text, glb_bin) = if is_glb(&data) {
+        split_glb(&data)?
+    } else {
+        (data.as_slice(), None)
+    };
+    let text = text.strip_prefix(b"\xef\xbb\xbf").unwrap_or(text);
+    let text = std::str::from_utf8(text).map_err(|err| json_error(text, err.valid_up_to()))?;
+    let json = Json::parse(text).map_err(GltfError::Json)?;
+
+    let version = json
+        .get("asset")
+        .and_then(|asset| asset.get("version"))
+        .and_then(Json::as_str);
+    match version {
+        Some(version) if version.starts_with("2.") => {}
+        Some(version) => return Err(GltfError::Unsupported(format!("glTF version {version}"))),
+        None => {
+            return Err(GltfError::InvalidMember {
+                path: "asset".into(),
+                member: "version",
+            });
+        }
+    }
+    let required: Vec<_> = json
+        .get("extensionsRequired")
+        .and_then(Json::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Json::as_str)
+        .filter(|extension| !SUPPORTED_EXTENSIONS.contains(extension))
+        .collect();
+    if !required.is_empty() {
+        return Err(GltfError::Unsupported(format!(
+            "extensions {}",
+            required.join(", ")
+        )));
+    }
+
+    let mut document = Document {
+        json: &json,
+        buffers: Vec::new(),
+    };
+    document.load_buffers(glb_bin, dir)?;
+    let mut builder = Builder::default();
+    for root in document.root_nodes()? {
+        document.add_node(root, &Mat4::identity(), 0, &mut builder)?;
+    }
+
+    let Builder {
+        vertices,
+        has_color,
+        has_texture_coords,
+        has_normals,
+        indices,
+        submeshes,
+        line_indices,
+        line_strips,
+        point_indices,
+    } = builder;
+    let file_colors = (vertices.iter().zip(&has_color))
+        .map(|(vertex, &has_color)| has_color.then_some(vertex.color))
+        .collect();
+    let mut model = Model::new(vertices, indices);
+    // the faces are the triangles
+    if has_color.contains(&true) {
+        model.file_colors = file_colors;
+    }
+    model.file_normals = has_normals;
+    model.file_texture_coordinates = has_texture_coords.contains(&true);
+    model.set_colors(ColorScheme::default());
+    model.project_uvs(&UvOptions::default(), |i| !has_texture_coords[i as usize]);
+    model.submeshes = submeshes
+        .into_iter()
+        .filter(|s| !s.indices.is_empty())
+        .collect();
+    model.line_indices = line_indices.into();
+    model.line_strips = line_strips;
+    model.point_indices = point_indices.into();
+    Ok(model)
+}
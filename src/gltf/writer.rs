@@ -0,0 +1,453 @@
+use super::json::Json;
+use crate::obj::Model;
+use std::io::{self, Write};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+#[derive(Debug, Clone, Default)]
+pub struct GlbWriteOptions {
+    /// Write the vertex colors as `COLOR_0`
+    pub vertex_colors: bool,
+}
+
+fn number(n: impl Into<f64>) -> Json {
+    Json::Number(n.into())
+}
+
+fn object(members: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        members
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+/// Triangles, polyline or points, as indices of the model vertices
+struct Primitive<'a> {
+    mode: u32,
+    indices: &'a [u32],
+}
+
+/// One node with its mesh, written for every submesh, and for the lines and points
+struct Node<'a> {
+    name: &'a str,
+    mesh: &'a str,
+    primitives: Vec<Primitive<'a>>,
+}
+
+/// Bytes of each buffer view, in the order of `View`
+#[derive(Default)]
+struct Views([Vec<u8>; 5]);
+
+#[derive(Clone, Copy)]
+enum View {
+    Positions,
+    Normals,
+    TextureCoords,
+    Colors,
+    Indices,
+}
+
+struct Accessor {
+    view: View,
+    offset: usize,
+    count: usize,
+    kind: &'static str,
+    component: u32,
+    bounds: Option<([f32; 3], [f32; 3])>,
+}
+
+impl Views {
+    fn extend(&mut self, view: View, floats: &[f32]) {
+        self.0[view as usize].extend(floats.iter().flat_map(|f| f.to_le_bytes()));
+    }
+}
+
+/// Writes the model as a binary glTF file
+/// Each submesh becomes a node named after its object, with a mesh named after its group, so the
+/// loader gives back the same submeshes. Polylines and points go in their own nodes
+/// Every primitive has its own copy of the vertices it uses
+pub fn write_glb(
+    model: &Model,
+    mut writer: impl Write,
+    options: &GlbWriteOptions,
+) -> io::Result<()> {
+    let mut nodes: Vec<Node> = model
+        .submeshes
+        .iter()
+        .map(|submesh| Node {
+            name: &submesh.object,
+            mesh: &submesh.group,
+            primitives: vec![Primitive {
+                mode: 4,
+                indices: &model.indices[submesh.indices.clone()],
+            }],
+        })
+        .collect();
+    if model.submeshes.is_empty() && !model.indices.is_empty() {
+        nodes.push(Node {
+            name: "",
+            mesh: "default",
+            primitives: vec![Primitive {
+                mode: 4,
+                indices: &model.indices,
+            }],
+        });
+    }
+    if !model.line_strips.is_empty() {
+        nodes.push(Node {
+            name: "lines",
+            mesh: "lines",
+            primitives: model
+                .line_strips
+                .iter()
+                .map(|strip| Primitive {
+                    mode: 3,
+                    indices: &model.line_indices[strip.clone()],
+                })
+                .collect(),
+        });
+    }
+    if !model.point_indices.is_empty() {
+        nodes.push(Node {
+            name: "points",
+            mesh: "points",
+            primitives: vec![Primitive {
+                mode: 0,
+                indices: &model.point_indices,
+            }],
+        });
+    }
+
+    for node in &mut nodes {
+        node.primitives
+            .retain(|primitive| !primitive.indices.is_empty());
+    }
+    nodes.retain(|node| !node.primitives.is_empty());
+
+    let mut views = Views::default();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    for node in &nodes {
+        let mut primitives = Vec::new();
+        for primitive in &node.primitives {
+            let mut used = primitive.indices.to_vec();
+            used.sort_unstable();
+            used.dedup();
+            let first = views.0[View::Positions as usize].len() / 12;
+            let mut min = [f32::INFINITY; 3];
+            let mut max = [f32::NEG_INFINITY; 3];
+            for &index in &used {
+                let vertex = &model.vertices[index as usize];
+                let p = [vertex.position.x, vertex.position.y, vertex.position.z];
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(p[axis]);
+                    max[axis] = max[axis].max(p[axis]);
+                }
+                views.extend(View::Positions, &p);
+                if primitive.mode == 4 {
+                    // normals have to be unit vectors
+                    let n = vertex.normal;
+                    let n = if n.norm2() > 0.0 {
+                        [n.x, n.y, n.z].map(|c| c / n.norm())
+                    } else {
+                        [0.0, 0.0, 1.0]
+                    };
+                    views.extend(View::Normals, &n);
+                }
+                let (u, v) = vertex.texture_coordinates;
+                views.extend(View::TextureCoords, &[u, v]);
+                if options.vertex_colors {
+                    let c = vertex.color;
+                    views.extend(View::Colors, &[c.x, c.y, c.z]);
+                }
+            }
+            let index_offset = views.0[View::Indices as usize].len();
+            views.0[View::Indices as usize].extend(primitive.indices.iter().flat_map(|index| {
+                let local = used.binary_search(index).expect("Every index is in `used`") as u32;
+                local.to_le_bytes()
+            }));
+
+            let mut accessor = |view, offset, count, kind, component, bounds| {
+                accessors.push(Accessor {
+                    view,
+                    offset,
+                    count,
+                    kind,
+                    component,
+                    bounds,
+                });
+                number((accessors.len() - 1) as u32)
+            };
+            let count = used.len();
+            let mut attributes = vec![(
+                "POSITION",
+                accessor(
+                    View::Positions,
+                    first * 12,
+                    count,
+                    "VEC3",
+                    FLOAT,
+                    Some((min, max)),
+                ),
+            )];
+            // lines and points are unlit, they don't need normals
+            if primitive.mode == 4 {
+                let first_normal = views.0[View::Normals as usize].len() / 12 - count;
+                attributes.push((
+                    "NORMAL",
+                    accessor(View::Normals, first_normal * 12, count, "VEC3", FLOAT, None),
+                ));
+            }
+            attributes.push((
+                "TEXCOORD_0",
+                accessor(View::TextureCoords, first * 8, count, "VEC2", FLOAT, None),
+            ));
+            if options.vertex_colors {
+                attributes.push((
+                    "COLOR_0",
+                    accessor(View::Colors, first * 12, count, "VEC3", FLOAT, None),
+                ));
+            }
+            let count = primitive.indices.len();
+            let indices = accessor(
+                View::Indices,
+                index_offset,
+                count,
+                "SCALAR",
+                UNSIGNED_INT,
+                None,
+            );
+            primitives.push(object(vec![
+                ("attributes", object(attributes)),
+                ("indices", indices),
+                ("mode", number(primitive.mode)),
+            ]));
+        }
+        meshes.push(object(vec![
+            ("name", Json::String(node.mesh.into())),
+            ("primitives", Json::Array(primitives)),
+        ]));
+    }
+
+    // empty views are not allowed, the others are packed in one buffer
+    let mut bin = Vec::new();
+    let mut view_indices = [0; 5];
+    let mut buffer_views = Vec::new();
+    for (view, bytes) in views
+        .0
+        .iter()
+        .enumerate()
+        .filter(|(_, bytes)| !bytes.is_empty())
+    {
+        let target = if view == View::Indices as usize {
+            ELEMENT_ARRAY_BUFFER
+        } else {
+            ARRAY_BUFFER
+        };
+        view_indices[view] = buffer_views.len() as u32;
+        buffer_views.push(object(vec![
+            ("buffer", number(0)),
+            ("byteOffset", number(bin.len() as u32)),
+            ("byteLength", number(bytes.len() as u32)),
+            ("target", number(target)),
+        ]));
+        // every component is 4 bytes, so the views stay aligned
+        bin.extend(bytes);
+    }
+    let accessors = accessors
+        .iter()
+        .map(|accessor| {
+            let mut members = vec![
+                ("bufferView", number(view_indices[accessor.view as usize])),
+                ("byteOffset", number(accessor.offset as u32)),
+                ("componentType", number(accessor.component)),
+                ("count", number(accessor.count as u32)),
+                ("type", Json::String(accessor.kind.into())),
+            ];
+            if let Some((min, max)) = accessor.bounds {
+                members.push(("min", Json::Array(min.map(number).to_vec())));
+                members.push(("max", Json::Array(max.map(number).to_vec())));
+            }
+            object(members)
+        })
+        .collect();
+
+    let mut document = vec![(
+        "asset",
+        object(vec![
+            ("version", Json::String("2.0".into())),
+            ("generator", Json::String("scop".into())),
+        ]),
+    )];
+    // arrays can't be empty, an empty model is just the asset
+    if !nodes.is_empty() {
+        let scene_nodes = (0..nodes.len() as u32).map(number).collect();
+        let nodes = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                object(vec![
+                    ("name", Json::String(node.name.into())),
+                    ("mesh", number(i as u32)),
+                ])
+            })
+            .collect();
+        document.extend([
+            ("scene", number(0)),
+            (
+                "scenes",
+                Json::Array(vec![object(vec![("nodes", Json::Array(scene_nodes))])]),
+            ),
+            ("nodes", Json::Array(nodes)),
+            ("meshes", Json::Array(meshes)),
+            ("accessors", Json::Array(accessors)),
+            ("bufferViews", Json::Array(buffer_views)),
+            (
+                "buffers",
+                Json::Array(vec![object(vec![("byteLength", number(bin.len() as u32))])]),
+            ),
+        ]);
+    }
+    let mut json = object(document).to_string().into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let bin_chunk = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let length = u32::try_from(12 + 8 + json.len() + bin_chunk)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Model too big for a glb file"))?;
+    writer.write_all(super::GLB_MAGIC)?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(&super::CHUNK_JSON.to_le_bytes())?;
+    writer.write_all(&json)?;
+    if !bin.is_empty() {
+        writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+        writer.write_all(&super::CHUNK_BIN.to_le_bytes())?;
+        writer.write_all(&bin)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gltf::parse_gltf;
+    use crate::math::vector::{Vector3, Vector4};
+    use crate::obj::{Submesh, Vertex};
+    use std::path::Path;
+
+    /// Vertex `i` of the test models, every attribute different from the other vertices
+    fn vertex(i: usize) -> Vertex {
+        let i = i as f32;
+        Vertex {
+            position: Vector3 {
+                x: i,
+                y: i * i,
+                z: -i,
+            },
+            color: Vector3 {
+                x: i / 8.0,
+                y: 1.0 - i / 8.0,
+                z: 0.5,
+            },
+            texture_coordinates: (i / 4.0, 1.0 - i / 4.0),
+            normal: Vector3 {
+                x: i,
+                y: 1.0,
+                z: 0.0,
+            }
+            .normalized(),
+            tangent: Vector4::zero(),
+        }
+    }
+
+    /// Two submeshes of one and two triangles, a polyline and a point
+    fn model() -> Model {
+        let vertices = (0..8).map(vertex).collect();
+        let mut model = Model::new(vertices, vec![0, 1, 2, 2, 3, 4, 2, 4, 5]);
+        model.submeshes = vec![
+            Submesh {
+                object: "first".into(),
+                group: "one".into(),
+                indices: 0..3,
+            },
+            Submesh {
+                object: "second".into(),
+                group: "two".into(),
+                indices: 3..9,
+            },
+        ];
+        model.line_indices = Box::new([5, 6, 7]);
+        model.line_strips.push(0..3);
+        model.point_indices = Box::new([7]);
+        model
+    }
+
+    type Corner = ([f32; 3], [f32; 3], [f32; 2], [f32; 3]);
+
+    /// Position, normal, texture coordinates and color of the vertices of `indices`
+    fn corners(model: &Model, indices: &[u32]) -> Vec<Corner> {
+        let array = |v: Vector3<f32>| [v.x, v.y, v.z].map(|f| (f * 1e5).round() / 1e5);
+        indices
+            .iter()
+            .map(|&i| &model.vertices[i as usize])
+            .map(|v| {
+                let (s, t) = v.texture_coordinates;
+                (array(v.position), array(v.normal), [s, t], array(v.color))
+            })
+            .collect()
+    }
+
+    fn round_trip(model: &Model) -> Model {
+        let mut glb = Vec::new();
+        let options = GlbWriteOptions {
+            vertex_colors: true,
+        };
+        write_glb(model, &mut glb, &options).unwrap();
+        parse_gltf(glb.as_slice(), Path::new(".")).unwrap()
+    }
+
+    #[test]
+    fn same_triangles() {
+        let model = model();
+        let copy = round_trip(&model);
+        assert_eq!(copy.indices.len(), model.indices.len());
+        assert_eq!(copy.submeshes.len(), 2);
+        for (submesh, copied) in model.submeshes.iter().zip(&copy.submeshes) {
+            assert_eq!(copied.object, submesh.object);
+            assert_eq!(copied.group, submesh.group);
+            assert_eq!(copied.indices, submesh.indices);
+            assert_eq!(
+                corners(&copy, &copy.indices[copied.indices.clone()]),
+                corners(&model, &model.indices[submesh.indices.clone()])
+            );
+        }
+        // every primitive has its own vertices, in the order of their first use
+        assert_eq!(copy.indices[3..9], [3, 4, 5, 3, 5, 6]);
+    }
+
+    #[test]
+    fn same_lines_and_points() {
+        let model = model();
+        let copy = round_trip(&model);
+        // lines and points are written without normals
+        let unlit = |model: &Model, indices: &[u32]| -> Vec<_> {
+            let corners = corners(model, indices).into_iter();
+            corners.map(|(p, _, uv, color)| (p, uv, color)).collect()
+        };
+        assert_eq!(copy.line_strips, model.line_strips);
+        assert_eq!(
+            unlit(&copy, &copy.line_indices),
+            unlit(&model, &model.line_indices)
+        );
+        assert_eq!(
+            unlit(&copy, &copy.point_indices),
+            unlit(&model, &model.point_indices)
+        );
+    }
+}
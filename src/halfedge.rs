@@ -0,0 +1,455 @@
+use crate::math::vector::Vector3;
+use crate::obj::Model;
+use std::collections::HashMap;
+
+/// What is on the other side of a half-edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opposite {
+    /// The half-edge of the only other face on the edge, going the other way
+    Twin(u32),
+    /// No other face
+    Boundary,
+    /// More than two faces meet on the edge, or two faces of opposite orientations, this is the
+    /// index of the edge in `HalfEdgeMesh::non_manifold_edges`
+    NonManifold(u32),
+}
+
+/// Connectivity of the triangles of a model, for topology queries
+/// Vertices of the model with the same position are one vertex, so the seams of the texture
+/// coordinates and of the normals don't split the surface. Faces are the triangles of the model
+/// with three different vertices, and face `f` has the half-edges `3 * f`, `3 * f + 1` and
+/// `3 * f + 2`, each one going from a corner to the next
+#[derive(Debug, Clone)]
+pub struct HalfEdgeMesh {
+    positions: Vec<Vector3<f32>>,
+    /// Vertex of every vertex of the model
+    vertex_ids: Vec<u32>,
+    /// Triangle of the model of every face
+    triangles: Vec<u32>,
+    /// Vertex every half-edge starts from
+    origins: Vec<u32>,
+    opposites: Vec<Opposite>,
+    /// A half-edge leaving every vertex, one without a twin when there is one
+    outgoing: Vec<Option<u32>>,
+    /// Half-edges of every non-manifold edge
+    non_manifold: Vec<Vec<u32>>,
+}
+
+impl HalfEdgeMesh {
+    pub fn new(model: &Model) -> HalfEdgeMesh {
+        let mut position_ids = HashMap::new();
+        let mut positions = Vec::new();
+        let vertex_ids: Vec<u32> = (model.vertices.iter())
+            .map(|vertex| {
+                let p = vertex.position;
+                *position_ids
+                    .entry([p.x, p.y, p.z].map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(p);
+                        positions.len() as u32 - 1
+                    })
+            })
+            .collect();
+
+        let mut triangles = Vec::new();
+        let mut origins = Vec::new();
+        for (t, triangle) in model.indices.chunks_exact(3).enumerate() {
+            let [a, b, c] = [0, 1, 2].map(|k| vertex_ids[triangle[k] as usize]);
+            if a != b && b != c && c != a {
+                triangles.push(t as u32);
+                origins.extend([a, b, c]);
+            }
+        }
+
+        let mut edges: HashMap<(u32, u32), Vec<u32>> = HashMap::new();
+        for h in 0..origins.len() as u32 {
+            let (from, to) = (origins[h as usize], origins[next(h) as usize]);
+            edges
+                .entry((from.min(to), from.max(to)))
+                .or_default()
+                .push(h);
+        }
+        let mut opposites = vec![Opposite::Boundary; origins.len()];
+        let mut non_manifold = Vec::new();
+        for half_edges in edges.into_values() {
+            match half_edges[..] {
+                [_] => {}
+                [h, g] if origins[h as usize] != origins[g as usize] => {
+                    opposites[h as usize] = Opposite::Twin(g);
+                    opposites[g as usize] = Opposite::Twin(h);
+                }
+                _ => non_manifold.push(half_edges),
+            }
+        }
+        // the edges come in the order of the hash map, sorted to be the same on every run
+        non_manifold.sort_unstable();
+        for (e, half_edges) in non_manifold.iter().enumerate() {
+            for &h in half_edges {
+                opposites[h as usize] = Opposite::NonManifold(e as u32);
+            }
+        }
+        // the fans around the vertices of a border start on it
+        let mut outgoing = vec![None; positions.len()];
+        for (h, &origin) in origins.iter().enumerate() {
+            let first = &mut outgoing[origin as usize];
+            if first.is_none() || !matches!(opposites[h], Opposite::Twin(_)) {
+                *first = Some(h as u32);
+            }
+        }
+
+        HalfEdgeMesh {
+            positions,
+            vertex_ids,
+            triangles,
+            origins,
+            opposites,
+            outgoing,
+            non_manifold,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn face_count(&self) -> usize {
+        self.triangles.len()
+    }
+    pub fn half_edge_count(&self) -> usize {
+        self.origins.len()
+    }
+    pub fn position(&self, v: u32) -> Vector3<f32> {
+        self.positions[v as usize]
+    }
+    /// Vertex of the vertex `model_vertex` of the model
+    pub fn vertex(&self, model_vertex: u32) -> u32 {
+        self.vertex_ids[model_vertex as usize]
+    }
+    /// Triangle of the model of face `f`, its corners start at `indices[3 * triangle]`
+    pub fn triangle(&self, f: u32) -> u32 {
+        self.triangles[f as usize]
+    }
+    /// Vertices of the corners of face `f`
+    pub fn corners(&self, f: u32) -> [u32; 3] {
+        [0, 1, 2].map(|k| self.origins[3 * f as usize + k])
+    }
+
+    pub fn origin(&self, h: u32) -> u32 {
+        self.origins[h as usize]
+    }
+    pub fn target(&self, h: u32) -> u32 {
+        self.origins[next(h) as usize]
+    }
+    pub fn face(&self, h: u32) -> u32 {
+        h / 3
+    }
+    /// Next half-edge of the face of `h`
+    pub fn next(&self, h: u32) -> u32 {
+        next(h)
+    }
+    /// Previous half-edge of the face of `h`
+    pub fn prev(&self, h: u32) -> u32 {
+        next(next(h))
+    }
+    pub fn opposite(&self, h: u32) -> Opposite {
+        self.opposites[h as usize]
+    }
+    pub fn twin(&self, h: u32) -> Option<u32> {
+        match self.opposites[h as usize] {
+            Opposite::Twin(twin) => Some(twin),
+            _ => None,
+        }
+    }
+    pub fn is_boundary(&self, h: u32) -> bool {
+        self.opposites[h as usize] == Opposite::Boundary
+    }
+
+    /// Half-edges leaving `v`, turning around it, starting on the border when it is on one
+    /// Only the fan of one of its faces is walked around a non-manifold vertex
+    pub fn outgoing(&self, v: u32) -> impl Iterator<Item = u32> + '_ {
+        let first = self.outgoing[v as usize];
+        let mut h = first;
+        std::iter::from_fn(move || {
+            let current = h?;
+            h = self.twin(self.prev(current)).filter(|&h| Some(h) != first);
+            Some(current)
+        })
+    }
+    /// Vertices linked to `v` by an edge, turning around it, in the fan of `outgoing`
+    pub fn one_ring(&self, v: u32) -> impl Iterator<Item = u32> + '_ {
+        self.outgoing(v).flat_map(|h| {
+            // a fan ending on a border has one more vertex, the origin of its last half-edge
+            let incoming = self.prev(h);
+            let last = self.twin(incoming).is_none().then(|| self.origin(incoming));
+            std::iter::once(self.target(h)).chain(last)
+        })
+    }
+    /// Faces sharing an edge with face `f`, those of non-manifold edges included
+    pub fn face_neighbors(&self, f: u32) -> impl Iterator<Item = u32> + '_ {
+        (3 * f..3 * f + 3).flat_map(move |h| {
+            let half_edges: &[u32] = match &self.opposites[h as usize] {
+                Opposite::Twin(twin) => std::slice::from_ref(twin),
+                Opposite::Boundary => &[],
+                Opposite::NonManifold(e) => &self.non_manifold[*e as usize],
+            };
+            (half_edges.iter())
+                .filter(move |&&g| g != h)
+                .map(|&g| self.face(g))
+        })
+    }
+
+    /// Boundary half-edge following `h` along its border, None when the border goes on through
+    /// a non-manifold edge
+    fn next_boundary(&self, h: u32) -> Option<u32> {
+        let mut g = self.next(h);
+        while let Some(twin) = self.twin(g) {
+            g = self.next(twin);
+        }
+        Some(g).filter(|&g| self.is_boundary(g))
+    }
+    /// Half-edges of every border, in order along it, with the faces on their left
+    /// Borders going through non-manifold edges are cut there
+    pub fn boundary_loops(&self) -> impl Iterator<Item = Vec<u32>> + '_ {
+        let boundary: Vec<u32> = (0..self.origins.len() as u32)
+            .filter(|&h| self.is_boundary(h))
+            .collect();
+        let mut followed = vec![false; self.origins.len()];
+        for &h in &boundary {
+            if let Some(next) = self.next_boundary(h) {
+                followed[next as usize] = true;
+            }
+        }
+        // the cut borders are walked from their first half-edge, before the loops
+        let starts: Vec<u32> = (boundary.iter().copied())
+            .filter(|&h| !followed[h as usize])
+            .chain(boundary.iter().copied())
+            .collect();
+        let mut visited = vec![false; self.origins.len()];
+        starts.into_iter().filter_map(move |start| {
+            let mut border = Vec::new();
+            let mut h = Some(start);
+            while let Some(current) = h.filter(|&h| !visited[h as usize]) {
+                visited[current as usize] = true;
+                border.push(current);
+                h = self.next_boundary(current);
+            }
+            Some(border).filter(|border| !border.is_empty())
+        })
+    }
+    /// Half-edges of every edge with more than two faces, or with two faces of opposite
+    /// orientations
+    pub fn non_manifold_edges(&self) -> impl Iterator<Item = &[u32]> {
+        self.non_manifold.iter().map(|half_edges| &half_edges[..])
+    }
+    /// Vertices with faces out of the fan `outgoing` walks, where several fans meet at a point
+    pub fn non_manifold_vertices(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut leaving = vec![0; self.positions.len()];
+        for &origin in &self.origins {
+            leaving[origin as usize] += 1;
+        }
+        (0..self.positions.len() as u32)
+            .filter(move |&v| self.outgoing(v).count() < leaving[v as usize])
+    }
+}
+
+/// Next half-edge of the same face
+fn next(h: u32) -> u32 {
+    if h % 3 == 2 { h - 2 } else { h + 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{ParseOptions, parse_obj};
+
+    fn mesh(source: &str) -> HalfEdgeMesh {
+        let model = parse_obj(source.as_bytes(), &ParseOptions::default())
+            .unwrap()
+            .0;
+        HalfEdgeMesh::new(&model)
+    }
+
+    /// Vertex at `position`
+    fn at(mesh: &HalfEdgeMesh, x: f32, y: f32, z: f32) -> u32 {
+        (0..mesh.vertex_count() as u32)
+            .find(|&v| {
+                let p = mesh.position(v);
+                [p.x, p.y, p.z] == [x, y, z]
+            })
+            .unwrap()
+    }
+
+    fn sorted(iter: impl Iterator<Item = u32>) -> Vec<u32> {
+        let mut values: Vec<u32> = iter.collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn closed_cube() {
+        // the texture coordinates split the vertices along their seams
+        let cube = mesh(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 4/2 3/3 2/4\nf 5/1 6/2 7/3 8/4\nf 1/1 2/2 6/3 5/4\n\
+             f 2/1 3/2 7/3 6/4\nf 3/1 4/2 8/3 7/4\nf 4/1 1/2 5/3 8/4\n",
+        );
+        assert_eq!((cube.vertex_count(), cube.face_count()), (8, 12));
+        for h in 0..cube.half_edge_count() as u32 {
+            let twin = cube.twin(h).unwrap();
+            assert_eq!(cube.twin(twin), Some(h));
+            assert_eq!(
+                (cube.origin(twin), cube.target(twin)),
+                (cube.target(h), cube.origin(h))
+            );
+        }
+        assert_eq!(cube.boundary_loops().count(), 0);
+        assert_eq!(cube.non_manifold_edges().count(), 0);
+        assert_eq!(cube.non_manifold_vertices().count(), 0);
+        // every vertex sees its 3 cube edges, and the face diagonals leaving it
+        for v in 0..8 {
+            let ring = sorted(cube.one_ring(v));
+            assert!(ring.len() >= 3 && !ring.contains(&v), "{ring:?}");
+            assert_eq!(ring.len(), cube.outgoing(v).count());
+            ring.windows(2)
+                .for_each(|pair| assert_ne!(pair[0], pair[1]));
+        }
+        for f in 0..12 {
+            assert_eq!(cube.face_neighbors(f).count(), 3);
+        }
+    }
+
+    #[test]
+    fn one_ring_of_a_grid() {
+        let mut source = String::new();
+        for y in 0..3 {
+            for x in 0..3 {
+                source += &format!("v {x} {y} 0\n");
+            }
+        }
+        source += "f 1 2 5\nf 1 5 4\nf 2 3 6\nf 2 6 5\nf 4 5 8\nf 4 8 7\nf 5 6 9\nf 5 9 8\n";
+        let grid = mesh(&source);
+        let center = at(&grid, 1.0, 1.0, 0.0);
+        let ring: Vec<u32> = grid.one_ring(center).collect();
+        // the diagonals of the quads don't go to (2, 0) and (0, 2)
+        assert_eq!(ring.len(), 6);
+        let expected = [
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (0.0, 1.0),
+            (2.0, 1.0),
+            (1.0, 2.0),
+            (2.0, 2.0),
+        ];
+        assert_eq!(
+            sorted(ring.iter().copied()),
+            sorted(expected.iter().map(|&(x, y)| at(&grid, x, y, 0.0)))
+        );
+        // consecutive vertices of the ring share a face with the center
+        for k in 0..6 {
+            let (a, b) = (ring[k], ring[(k + 1) % 6]);
+            assert!((0..8).any(|f| {
+                let corners = grid.corners(f);
+                [center, a, b].iter().all(|v| corners.contains(v))
+            }));
+        }
+        // the ring of a border vertex goes from one side of the border to the other
+        let side = at(&grid, 1.0, 0.0, 0.0);
+        let ring: Vec<u32> = grid.one_ring(side).collect();
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring[0], at(&grid, 2.0, 0.0, 0.0));
+        assert_eq!(ring[3], at(&grid, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn boundary_loops() {
+        let mut source = String::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                source += &format!("v {x} {y} 0\n");
+            }
+        }
+        // a square ring of 8 quads around a hole
+        for (x, y) in [
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (0, 1),
+            (2, 1),
+            (0, 2),
+            (1, 2),
+            (2, 2),
+        ] {
+            let corner = y * 4 + x + 1;
+            source += &format!(
+                "f {} {} {} {}\n",
+                corner,
+                corner + 1,
+                corner + 5,
+                corner + 4
+            );
+        }
+        let ring = mesh(&source);
+        let mut loops: Vec<Vec<u32>> = ring.boundary_loops().collect();
+        loops.sort_by_key(|border| border.len());
+        assert_eq!(loops.iter().map(Vec::len).collect::<Vec<_>>(), [4, 12]);
+        for border in &loops {
+            for k in 0..border.len() {
+                let (h, next) = (border[k], border[(k + 1) % border.len()]);
+                assert!(ring.is_boundary(h));
+                assert_eq!(ring.target(h), ring.origin(next));
+            }
+        }
+        let hole = sorted(loops[0].iter().map(|&h| ring.origin(h)));
+        let expected = sorted(
+            [(1.0, 1.0), (2.0, 1.0), (1.0, 2.0), (2.0, 2.0)]
+                .map(|(x, y)| at(&ring, x, y, 0.0))
+                .into_iter(),
+        );
+        assert_eq!(hole, expected);
+    }
+
+    #[test]
+    fn non_manifold_edges() {
+        // three triangles on one edge, and a fourth one flipped against the third
+        let fins = mesh(
+            "v 0 0 0\nv 0 0 1\nv 1 0 0\nv 0 1 0\nv -1 0 0\nv -1 -1 0\n\
+             f 1 2 3\nf 2 1 4\nf 1 2 5\nf 5 1 6\n",
+        );
+        let edges: Vec<&[u32]> = fins.non_manifold_edges().collect();
+        assert_eq!(edges.len(), 2);
+        let fin = edges
+            .iter()
+            .find(|half_edges| half_edges.len() == 3)
+            .unwrap();
+        let faces = sorted(fin.iter().map(|&h| fins.face(h)));
+        assert_eq!(faces, [0, 1, 2]);
+        for &h in fin.iter() {
+            assert!(matches!(fins.opposite(h), Opposite::NonManifold(_)));
+            let neighbors = sorted(fins.face_neighbors(fins.face(h)));
+            assert!(neighbors.len() >= 2, "{neighbors:?}");
+            assert!(!neighbors.contains(&fins.face(h)));
+        }
+        let flipped = edges
+            .iter()
+            .find(|half_edges| half_edges.len() == 2)
+            .unwrap();
+        assert_eq!(fins.origin(flipped[0]), fins.origin(flipped[1]));
+    }
+
+    #[test]
+    fn bowtie() {
+        // two triangles touching at a vertex, and a degenerate one
+        let bowtie = mesh(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv -1 0 0\nv -1 -1 0\n\
+             f 1 2 3\nf 1 4 5\nf 1 2 2\n",
+        );
+        assert_eq!(bowtie.face_count(), 2);
+        assert_eq!(bowtie.triangle(1), 1);
+        let center = at(&bowtie, 0.0, 0.0, 0.0);
+        assert_eq!(bowtie.non_manifold_vertices().collect::<Vec<_>>(), [center]);
+        assert_eq!(bowtie.one_ring(center).count(), 2);
+        let loops: Vec<Vec<u32>> = bowtie.boundary_loops().collect();
+        assert_eq!(loops.iter().map(Vec::len).collect::<Vec<_>>(), [3, 3]);
+        assert_eq!(bowtie.non_manifold_edges().count(), 0);
+    }
+}
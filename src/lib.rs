@@ -1,28 +1,116 @@
+pub mod cache;
+pub mod color;
+pub mod decimate;
 pub mod gl;
+pub mod gltf;
+pub mod halfedge;
+pub mod lod;
 pub mod math;
+pub mod mesh;
 pub mod obj;
+pub mod off;
+pub mod ply;
+pub mod stl;
+pub mod subdivide;
+pub mod uv;
 
 use glfw::{Action, Context, GlfwReceiver, Key};
 use std::cell::RefCell;
+use std::collections::HashMap;
 
 use std::error::Error;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
 
 use gl::ebo::Ebo;
 use gl::shader::{Shader, ShaderProgram};
 use gl::texture::{self, Texture};
 use gl::vao::{BoundVao, Vao};
 use gl::vbo::Vbo;
+use color::ColorScheme;
 use math::matrix::Mat4;
-use math::vector::Vector3;
+use math::vector::{Vector3, Vector4};
+use obj::triangulation::Triangulation;
 
 const SCR_WIDTH: u32 = 800;
 const SCR_HEIGHT: u32 = 600;
+/// Vertical field of view of the camera, in degrees
+const FOV: f32 = 45.0;
+const NEAR: f32 = 0.1;
+const FAR: f32 = 100.0;
+/// Models with more triangles are not subdivided further with the keys
+const MAX_SUBDIVIDED_TRIANGLES: usize = 1 << 20;
+
+/// File formats the viewer can load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Obj,
+    Stl,
+    Ply,
+    Gltf,
+    Off,
+}
+
+impl Format {
+    /// Format of the file at `path`, from its extension or, without a known one, its first bytes
+    pub fn detect(path: &Path) -> io::Result<Format> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("obj") => return Ok(Format::Obj),
+            Some("stl") => return Ok(Format::Stl),
+            Some("ply") => return Ok(Format::Ply),
+            Some("gltf" | "glb") => return Ok(Format::Gltf),
+            Some("off") => return Ok(Format::Off),
+            _ => {}
+        }
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut start = Vec::new();
+        file.take(512).read_to_end(&mut start)?;
+        // only gltf files start with a JSON object
+        Ok(if gltf::is_glb(&start) || start.trim_ascii_start().starts_with(b"{") {
+            Format::Gltf
+        } else if ply::is_ply(&start) {
+            Format::Ply
+        } else if off::is_off(&start) {
+            Format::Off
+        } else if stl::is_stl(&start, size) {
+            Format::Stl
+        } else {
+            Format::Obj
+        })
+    }
+}
 
 pub struct Config {
     path: String,
-    ignore_unimplemented: bool,
+    format: Format,
+    parse_options: obj::ParseOptions,
+    stl_options: stl::StlOptions,
+    /// Fit the model in a unit cube centered on the origin
+    normalize: bool,
+    /// Reuse the model loaded by a previous run, if the file and options did not change
+    cache: bool,
+    /// Where the caches go, next to the models when None
+    cache_dir: Option<PathBuf>,
+    /// Draw a checker texture instead of the default one, to check the texture coordinates
+    checker: bool,
+    /// Colors of the vertices when the model is shown, switched with V
+    color_scheme: ColorScheme,
+    /// Reduce the triangles of the model after loading it
+    decimate: Option<decimate::DecimateOptions>,
+    /// Write the model to this file, in the format of its extension, instead of showing it
+    output: Option<PathBuf>,
+    /// Draw reduced levels of detail of the model when it is small on screen
+    lod: Option<lod::LodOptions>,
+    /// Subdivision of the model when it is shown, changed with [ and ]
+    subdivide: subdivide::SubdivideOptions,
 }
 
 impl Config {
@@ -33,19 +121,172 @@ impl Config {
             Some(arg) => arg,
             None => return Err("No query String".into()),
         };
-        let ignore_unimplemented = match args.next().as_deref() {
-            None => true,
-            Some("--no-ignore-unimplemented") => false,
-            Some(x) => return Err(format!("Unknown argument : {x}")),
+        let mut parse_options = obj::ParseOptions {
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ..Default::default()
+        };
+        let mut stl_options = stl::StlOptions::default();
+        let mut normalize = true;
+        let mut cache = true;
+        let mut cache_dir = None;
+        let mut checker = false;
+        let mut color_scheme = ColorScheme::default();
+        let mut decimate: Option<decimate::DecimateOptions> = None;
+        let mut output = None;
+        let mut lod = Some(lod::LodOptions::default());
+        let mut subdivide = subdivide::SubdivideOptions {
+            levels: 0,
+            ..Default::default()
+        };
+        for arg in args {
+            match arg.as_str() {
+                "--no-ignore-unimplemented" => parse_options.ignore_unimplemented = false,
+                "--keep-going" => parse_options.keep_going = true,
+                "--fan-triangulation" => parse_options.triangulation = Triangulation::Fan,
+                "--no-normalize" => normalize = false,
+                "--no-weld" => stl_options.weld = false,
+                "--no-cache" => cache = false,
+                "--tangents" => parse_options.tangents = true,
+                "--checker" => checker = true,
+                "--no-lod" => lod = None,
+                x => {
+                    if let Some(n) = x.strip_prefix("--freeform-resolution=") {
+                        parse_options.freeform_resolution = n
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(format!("Invalid resolution : {n}"))?
+                    } else if let Some(angle) = x.strip_prefix("--smooth-normals=") {
+                        parse_options.normals = Some(mesh::NormalOptions {
+                            crease_angle: angle
+                                .parse()
+                                .ok()
+                                .filter(|a: &f32| (0.0..=180.0).contains(a))
+                                .ok_or(format!("Invalid crease angle : {angle}"))?,
+                            ..Default::default()
+                        })
+                    } else if let Some(projection) = x.strip_prefix("--uv=") {
+                        parse_options.uv.projection = match projection {
+                            "planar" => uv::Projection::Planar,
+                            "box" => uv::Projection::Box,
+                            "spherical" => uv::Projection::Spherical,
+                            "cylindrical" => uv::Projection::Cylindrical,
+                            _ => return Err(format!("Invalid projection : {projection}")),
+                        }
+                    } else if let Some(axis) = x.strip_prefix("--uv-axis=") {
+                        parse_options.uv.axis = match axis {
+                            "x" => uv::Axis::X,
+                            "y" => uv::Axis::Y,
+                            "z" => uv::Axis::Z,
+                            _ => return Err(format!("Invalid axis : {axis}")),
+                        }
+                    } else if let Some(scale) = x.strip_prefix("--uv-scale=") {
+                        parse_options.uv.scale = scale
+                            .parse()
+                            .ok()
+                            .filter(|s: &f32| s.is_finite() && *s > 0.0)
+                            .ok_or(format!("Invalid scale : {scale}"))?
+                    } else if let Some(scheme) = x.strip_prefix("--colors=") {
+                        color_scheme = match scheme {
+                            "file" => ColorScheme::File,
+                            "face" => ColorScheme::Face,
+                            "random" => ColorScheme::Random,
+                            "group" => ColorScheme::Group,
+                            "object" => ColorScheme::Object,
+                            "material" => ColorScheme::Material,
+                            "curvature" => ColorScheme::Curvature,
+                            "height" => ColorScheme::Height,
+                            _ => return Err(format!("Invalid color scheme : {scheme}")),
+                        }
+                    } else if let Some(n) = x.strip_prefix("--decimate=") {
+                        decimate.get_or_insert_default().target_triangles = n
+                            .parse()
+                            .map_err(|_| format!("Invalid number of triangles : {n}"))?
+                    } else if let Some(error) = x.strip_prefix("--decimate-error=") {
+                        decimate.get_or_insert_default().max_error = error
+                            .parse()
+                            .ok()
+                            .filter(|e: &f32| *e >= 0.0)
+                            .ok_or(format!("Invalid error : {error}"))?
+                    } else if let Some(n) = x.strip_prefix("--lod-levels=") {
+                        if let Some(lod) = &mut lod {
+                            lod.levels = n
+                                .parse()
+                                .map_err(|_| format!("Invalid number of levels : {n}"))?
+                        }
+                    } else if let Some(n) = x.strip_prefix("--subdivide=") {
+                        subdivide.levels = n
+                            .parse()
+                            .map_err(|_| format!("Invalid number of levels : {n}"))?
+                    } else if let Some(scheme) = x.strip_prefix("--subdivision=") {
+                        subdivide.scheme = match scheme {
+                            "loop" => Some(subdivide::Scheme::Loop),
+                            "catmull-clark" => Some(subdivide::Scheme::CatmullClark),
+                            _ => return Err(format!("Invalid subdivision : {scheme}")),
+                        }
+                    } else if let Some(angle) = x.strip_prefix("--crease-angle=") {
+                        subdivide.crease_angle = Some(
+                            angle
+                                .parse()
+                                .ok()
+                                .filter(|a: &f32| (0.0..=180.0).contains(a))
+                                .ok_or(format!("Invalid crease angle : {angle}"))?,
+                        )
+                    } else if let Some(path) = x.strip_prefix("--write=") {
+                        output = Some(PathBuf::from(path));
+                    } else if let Some(dir) = x.strip_prefix("--cache-dir=") {
+                        cache_dir = Some(PathBuf::from(dir));
+                    } else if let Some(n) = x.strip_prefix("--threads=") {
+                        parse_options.threads = n
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(format!("Invalid number of threads : {n}"))?
+                    } else {
+                        return Err(format!("Unknown argument : {x}"));
+                    }
+                }
+            }
+        }
+        let format = Format::detect(Path::new(&path)).map_err(|err| format!("{path} : {err}"))?;
+        if let Some(lod) = &mut lod {
+            lod.normals = parse_options.normals.unwrap_or_default();
+        }
+        // the subdivided surface is smooth but along the creases
+        subdivide.normals = mesh::NormalOptions {
+            crease_angle: subdivide.crease_angle.unwrap_or(180.0),
+            ..Default::default()
         };
         Ok(Config {
             path,
-            ignore_unimplemented,
+            format,
+            parse_options,
+            stl_options,
+            normalize,
+            cache,
+            cache_dir,
+            checker,
+            color_scheme,
+            decimate,
+            output,
+            lod,
+            subdivide,
         })
     }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let mut model = load_model(&config)?;
+    if let Some(options) = &config.decimate {
+        model = decimate::decimate(&model, options);
+    }
+    if let Some(path) = &config.output {
+        if config.subdivide.levels > 0 {
+            model = subdivided(&model, config.subdivide.levels, &config);
+        }
+        return write_model(&model, path);
+    }
+
     let mut glfw = glfw::init_no_callbacks()?;
     glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
     glfw.window_hint(glfw::WindowHint::OpenGlProfile(
@@ -62,8 +303,7 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 
     gl::load_with(|symbol| window.get_proc_address(symbol));
 
-    let file = BufReader::new(File::open(config.path)?);
-    let model = obj::parse_obj(file, config.ignore_unimplemented)?;
+    let mut color_scheme = config.color_scheme;
 
     let mut context = gl::Context::new();
     let mut texture_contexts = texture::get_contexts();
@@ -77,8 +317,6 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     bound_vao.bind_vbo(&vbo);
     bound_vao.bind_ebo(&ebo);
 
-    vbo.borrow_mut().bind_data(&model.vertices);
-    ebo.borrow_mut().bind_data(&model.indices);
     context = bound_vao.unbind();
 
     let vertex_shader_id = Shader::from_path("./src/vertex.glsl", gl::VERTEX_SHADER)?;
@@ -87,11 +325,47 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     let shader_program = ShaderProgram::new(&vertex_shader_id, &fragment_shader_id)?;
     //TODO delete shaders
 
-    let texture = Texture::new();
     let mut txt_ctx_0 = texture_contexts.remove(0);
-    let bound_text = texture.bind(&mut txt_ctx_0, &mut active_texture);
-
-    bound_text.bind_data_from_path("img/test.png", &mut active_texture)?;
+    let mut textures = vec![Texture::new()];
+    let default_texture = textures[0].bind(&mut txt_ctx_0, &mut active_texture);
+    if config.checker {
+        default_texture.bind_data(&uv::checker_texture(512, 8), &mut active_texture);
+    } else {
+        default_texture.bind_data_from_path("img/test.png", &mut active_texture)?;
+    }
+    // the subdivisions and the levels of detail keep the materials of the model, and are
+    // placed like it
+    let mut loaded = HashMap::new();
+    for map in (model.materials.iter()).filter_map(|m| m.diffuse_map.as_ref()) {
+        loaded.entry(map.path.clone()).or_insert_with(|| {
+            let texture = Texture::new();
+            let bound_text = texture.bind(&mut txt_ctx_0, &mut active_texture);
+            if let Err(err) = bound_text.bind_data_from_path(&map.path, &mut active_texture) {
+                eprintln!("Could not load texture {} : {err}", map.path.display());
+                return None;
+            }
+            textures.push(texture);
+            Some(textures.len() - 1)
+        });
+    }
+    let bounds = model.bounds;
+    let normalization = if config.normalize {
+        bounds.normalization()
+    } else {
+        Mat4::identity()
+    };
+    let mut details = HashMap::new();
+    details.insert(0, Detail::new(model, config.lod, color_scheme, &loaded));
+    let mut subdivision = 0;
+    if config.subdivide.levels > 0 {
+        subdivision = config.subdivide.levels;
+        let model = subdivided(&details[&0].levels[0], subdivision, &config);
+        let detail = Detail::new(model, subdivided_lod(&config), color_scheme, &loaded);
+        details.insert(subdivision, detail);
+    }
+    // subdivision and level of detail in the buffers
+    let mut shown = None;
+    let (mut lines, mut points) = (0..0, 0..0);
 
     let mut camera_pos = Vector3 {
         x: 0.0,
@@ -128,11 +402,43 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     let mut last_frame = glfw.get_time() as f32;
     let mut scale = 1.0;
     let mut projection =
-        Mat4::perspective(45.0, (SCR_WIDTH as f32) / (SCR_HEIGHT as f32), 0.1, 100.0);
-    unsafe { gl::Enable(gl::DEPTH_TEST) };
+        Mat4::perspective(FOV, (SCR_WIDTH as f32) / (SCR_HEIGHT as f32), NEAR, FAR);
+    let mut viewport_height = SCR_HEIGHT as f32;
+    unsafe {
+        gl::Enable(gl::DEPTH_TEST);
+        gl::PointSize(3.0);
+    };
     while !window.should_close() {
-        if let Some((width, height)) = process_events(&events, &mut texture_or_color_want) {
-            projection = Mat4::perspective(45.0, (width as f32) / (height as f32), 0.1, 100.0);
+        let previous_scheme = color_scheme;
+        let previous_subdivision = subdivision;
+        if let Some((width, height)) = process_events(
+            &events,
+            &mut texture_or_color_want,
+            &mut color_scheme,
+            &mut subdivision,
+        ) {
+            projection = Mat4::perspective(FOV, (width as f32) / (height as f32), NEAR, FAR);
+            viewport_height = height as f32;
+        }
+        if color_scheme != previous_scheme {
+            for detail in details.values_mut() {
+                for level in &mut detail.levels {
+                    level.set_colors(color_scheme);
+                }
+            }
+            texture_or_color_want = true;
+            shown = None;
+        }
+        if !details.contains_key(&subdivision) {
+            let finest = &details[&previous_subdivision].levels[0];
+            if finest.indices.len() / 3 > MAX_SUBDIVIDED_TRIANGLES {
+                eprintln!("The model has too many triangles to be subdivided further");
+                subdivision = previous_subdivision;
+            } else {
+                let model = subdivided(&details[&0].levels[0], subdivision, &config);
+                let detail = Detail::new(model, subdivided_lod(&config), color_scheme, &loaded);
+                details.insert(subdivision, detail);
+            }
         }
 
         unsafe {
@@ -164,19 +470,53 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
         let camera_target = camera_pos + camera_front;
 
         let view = Mat4::lookat(camera_pos, camera_target, up);
-        let model = Mat4::rotate(&j, -time_value / 6.0) * Mat4::scale(&(ijk * scale));
+        let model =
+            Mat4::rotate(&j, -time_value / 6.0) * Mat4::scale(&(ijk * scale)) * &normalization;
+
+        let (center, radius) = bounding_sphere(&bounds, &model);
+        let size = lod::projected_size(radius, (center - camera_pos).norm(), FOV, viewport_height);
+        let detail = details.get_mut(&subdivision).unwrap();
+        detail.add_reductions(color_scheme, &loaded);
+        let level = detail.selector.select(size);
+        if shown != Some((subdivision, level)) {
+            let model = &detail.levels[level];
+            let (elements, level_lines, level_points) = ebo_elements(model);
+            vbo.borrow_mut().bind_data(&model.vertices);
+            ebo.borrow_mut().bind_data(&elements);
+            (lines, points) = (level_lines, level_points);
+            window.set_title(&title(color_scheme, subdivision, level, model));
+            shown = Some((subdivision, level));
+        }
 
         shader_program.use_program();
         unsafe { shader_program.set_mat(c"view", &view) }.ok_or("Cannot set view uniform")?;
         unsafe { shader_program.set_mat(c"projection", &projection) }
             .ok_or("Cannot set projection uniform")?;
-        unsafe { shader_program.set_texture(c"texture1", &bound_text) };
         let bound_vao = BoundVao::new(&mut vao, context);
         unsafe { shader_program.set_mat(c"model", &model) }.ok_or("Cannot set model uniform")?;
         unsafe { shader_program.set_vec3(c"lightPos", k * 100.0) }.ok_or("Cannot set lightPos uniform")?;
         unsafe { shader_program.set1f(c"TextureOrColor", texture_or_color) }.ok_or("Cannot set lightPos uniform")?;
-
-        bound_vao.draw_elements();
+        unsafe { shader_program.set1f(c"unlit", 0.0) }.ok_or("Cannot set unlit uniform")?;
+
+        for draw in &details[&subdivision].draws[level] {
+            let texture = &textures[draw.texture.unwrap_or(0)];
+            let bound_text = texture.bind(&mut txt_ctx_0, &mut active_texture);
+            unsafe { shader_program.set_texture(c"texture1", &bound_text) };
+            unsafe { shader_program.set_vec3(c"diffuseColor", draw.diffuse) }
+                .ok_or("Cannot set diffuseColor uniform")?;
+            unsafe { shader_program.set1f(c"useTexture", draw.texture.map_or(0.0, |_| 1.0)) }
+                .ok_or("Cannot set useTexture uniform")?;
+            bound_vao.draw_elements_range(gl::TRIANGLES, draw.indices.clone());
+        }
+        if !lines.is_empty() || !points.is_empty() {
+            unsafe { shader_program.set1f(c"unlit", 1.0) }.ok_or("Cannot set unlit uniform")?;
+            unsafe { shader_program.set_vec3(c"diffuseColor", Vector3 { x: 1.0, y: 1.0, z: 1.0 }) }
+                .ok_or("Cannot set diffuseColor uniform")?;
+            unsafe { shader_program.set1f(c"useTexture", 0.0) }
+                .ok_or("Cannot set useTexture uniform")?;
+            bound_vao.draw_elements_range(gl::LINES, lines.clone());
+            bound_vao.draw_elements_range(gl::POINTS, points.clone());
+        }
         context = bound_vao.unbind();
 
         window.swap_buffers();
@@ -185,9 +525,358 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Indices of the triangles, then of the lines, then of the points of the model, all drawn from
+/// the same ebo, with the ranges of the lines and of the points
+fn ebo_elements(model: &obj::Model) -> (Vec<u32>, Range<usize>, Range<usize>) {
+    let mut elements = model.indices.to_vec();
+    let segments = model.line_segments();
+    let lines = elements.len()..elements.len() + segments.len();
+    elements.extend(segments);
+    let points = elements.len()..elements.len() + model.point_indices.len();
+    elements.extend(&model.point_indices);
+    (elements, lines, points)
+}
+
+/// Sphere around `bounds`, moved and scaled by the rotations, translations and uniform scales
+/// of `matrix`
+fn bounding_sphere(bounds: &obj::BoundingBox, matrix: &Mat4<f32>) -> (Vector3<f32>, f32) {
+    let center = bounds.center();
+    let center = matrix
+        * Vector4 {
+            x: center.x,
+            y: center.y,
+            z: center.z,
+            w: 1.0,
+        };
+    let unit = matrix
+        * Vector4 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+            w: 0.0,
+        };
+    let center = Vector3 {
+        x: center.x,
+        y: center.y,
+        z: center.z,
+    };
+    (center, bounds.size().norm() / 2.0 * unit.norm())
+}
+
+/// Window title with the color scheme, the subdivision and the level of detail shown
+fn title(
+    color_scheme: ColorScheme,
+    subdivision: usize,
+    level: usize,
+    model: &obj::Model,
+) -> String {
+    format!(
+        "scop - {color_scheme:?} colors - subdivision {subdivision} - LOD {level} ({} triangles)",
+        model.indices.len() / 3
+    )
+}
+
+/// The model at one level of subdivision, with its levels of detail and their draws
+struct Detail {
+    levels: Vec<obj::Model>,
+    /// Reduced levels being made in the background, the model is drawn alone until they are done
+    reductions: Option<thread::JoinHandle<Vec<obj::Model>>>,
+    lod: lod::LodOptions,
+    selector: lod::LodSelector,
+    draws: Vec<Vec<Draw>>,
+}
+
+impl Detail {
+    /// The model in `color_scheme`, drawn with the textures of `loaded`, whose levels of detail
+    /// are made in the background, unless `lod` is None
+    fn new(
+        mut model: obj::Model,
+        lod: Option<lod::LodOptions>,
+        color_scheme: ColorScheme,
+        loaded: &HashMap<PathBuf, Option<usize>>,
+    ) -> Detail {
+        model.set_colors(color_scheme);
+        let reductions = lod.map(|options| {
+            let model = model.clone();
+            thread::spawn(move || lod::reductions(&model, &options))
+        });
+        let lod = lod.unwrap_or_default();
+        let levels = vec![model];
+        Detail {
+            reductions,
+            lod,
+            selector: lod::LodSelector::new(&levels, &lod),
+            draws: vec![material_draws(&levels[0], |path| {
+                loaded.get(path).copied().flatten()
+            })],
+            levels,
+        }
+    }
+
+    /// Adds the reduced levels once they are made, in `color_scheme`
+    fn add_reductions(
+        &mut self,
+        color_scheme: ColorScheme,
+        loaded: &HashMap<PathBuf, Option<usize>>,
+    ) {
+        if !(self.reductions.as_ref()).is_some_and(|reductions| reductions.is_finished()) {
+            return;
+        }
+        let Ok(reductions) = self.reductions.take().unwrap().join() else {
+            eprintln!("Could not make the levels of detail");
+            return;
+        };
+        for mut level in reductions {
+            level.set_colors(color_scheme);
+            let draws = material_draws(&level, |path| loaded.get(path).copied().flatten());
+            self.draws.push(draws);
+            self.levels.push(level);
+        }
+        self.selector = lod::LodSelector::new(&self.levels, &self.lod);
+    }
+}
+
+/// The model subdivided `levels` times as `config` asks, with tangents if it asks for them
+fn subdivided(model: &obj::Model, levels: usize, config: &Config) -> obj::Model {
+    let options = subdivide::SubdivideOptions {
+        levels,
+        ..config.subdivide
+    };
+    let mut subdivided = subdivide::subdivide(model, &options);
+    if config.parse_options.tangents {
+        subdivided.compute_tangents();
+    }
+    subdivided
+}
+
+/// Levels of detail of the subdivided models, smoothed like them
+fn subdivided_lod(config: &Config) -> Option<lod::LodOptions> {
+    config.lod.map(|lod| lod::LodOptions {
+        normals: config.subdivide.normals,
+        ..lod
+    })
+}
+
+/// Loads the model from its cache, or with the loader of its format, and reports its
+/// diagnostics
+/// A missing, stale or corrupted cache is written again, materials are always loaded from their
+/// files
+fn load_model(config: &Config) -> Result<obj::Model, Box<dyn Error>> {
+    let path = Path::new(&config.path);
+    let cache = if config.cache {
+        // hashed as it is read, like the loaders read it, so huge files are never all in memory
+        let source_hash = cache::fnv1a_reader(BufReader::new(File::open(path)?))?;
+        // the number of threads does not change the model
+        let parse_options = obj::ParseOptions {
+            threads: 1,
+            ..config.parse_options.clone()
+        };
+        let options = format!(
+            "{} {:?} {parse_options:?} {:?}",
+            env!("CARGO_PKG_VERSION"),
+            config.format,
+            config.stl_options
+        );
+        let dir = config.cache_dir.as_deref();
+        Some(cache::Cache::new(path, source_hash, &options, dir))
+    } else {
+        None
+    };
+    let cached = cache.as_ref().and_then(|cache| match cache.load() {
+        Ok(model) => Some(model),
+        Err(cache::CacheError::IOError(err)) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            eprintln!("{} : {err}, loading the model again", cache.path.display());
+            None
+        }
+    });
+    let (mut model, diagnostics) = match cached {
+        Some(cached) => cached,
+        None => {
+            let (model, diagnostics) = parse_model(config, BufReader::new(File::open(path)?))?;
+            if let Some(cache) = &cache
+                && let Err(err) = cache.store(&model, &diagnostics)
+            {
+                eprintln!("Could not write {} : {err}", cache.path.display());
+            }
+            (model, diagnostics)
+        }
+    };
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}");
+    }
+    if config.format == Format::Obj {
+        match model.load_materials(path, config.parse_options.ignore_unimplemented) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprintln!("mtl {diagnostic}");
+                }
+            }
+            Err(err) => eprintln!("Could not load materials : {err}"),
+        }
+    }
+    Ok(model)
+}
+
+/// Writes the model in the format of the extension of `path`, with the colors of the file if it
+/// has some
+fn write_model(model: &obj::Model, path: &Path) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let colors = !model.file_colors.is_empty();
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    match extension.as_deref() {
+        Some("obj") => {
+            let options = obj::WriteOptions {
+                vertex_colors: colors,
+                ..Default::default()
+            };
+            obj::write_obj(model, &mut writer, &options)?
+        }
+        Some("off") => {
+            let options = off::OffWriteOptions {
+                normals: model.file_normals,
+                vertex_colors: colors,
+                texture_coords: model.file_texture_coordinates,
+                ..Default::default()
+            };
+            off::write_off(model, &mut writer, &options)?
+        }
+        Some("glb") => {
+            let options = gltf::GlbWriteOptions {
+                vertex_colors: colors,
+            };
+            gltf::write_glb(model, &mut writer, &options)?
+        }
+        Some("stl") => stl::write_stl(model, &mut writer, &stl::StlWriteOptions { colors })?,
+        _ => return Err(format!("{} : unknown output format", path.display()).into()),
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parses `source` with the loader of its format, the diagnostics come as they are reported
+fn parse_model(
+    config: &Config,
+    source: BufReader<File>,
+) -> Result<(obj::Model, Vec<String>), Box<dyn Error>> {
+    let mut model = match config.format {
+        // the obj parser computes the tangents itself
+        Format::Obj => {
+            let (model, diagnostics) = obj::parse_obj(source, &config.parse_options)?;
+            let diagnostics = diagnostics.iter().map(ToString::to_string).collect();
+            return Ok((model, diagnostics));
+        }
+        Format::Stl => {
+            let options = stl::StlOptions {
+                normals: config.parse_options.normals.unwrap_or(config.stl_options.normals),
+                uv: config.parse_options.uv,
+                ..config.stl_options.clone()
+            };
+            stl::parse_stl(source, &options)?
+        }
+        Format::Ply => {
+            let options = ply::PlyOptions {
+                triangulation: config.parse_options.triangulation,
+                uv: config.parse_options.uv,
+                normals: config.parse_options.normals.unwrap_or_default(),
+            };
+            ply::parse_ply(source, &options)?
+        }
+        Format::Gltf => {
+            let dir = Path::new(&config.path).parent().unwrap_or(Path::new(""));
+            gltf::parse_gltf(source, dir)?
+        }
+        Format::Off => {
+            let options = off::OffOptions {
+                triangulation: config.parse_options.triangulation,
+                normals: config.parse_options.normals.unwrap_or_default(),
+                uv: config.parse_options.uv,
+            };
+            off::parse_off(source, &options)?
+        }
+    };
+    // glTF files may come with their own tangents
+    if config.parse_options.tangents && model.vertices.iter().all(|v| v.tangent.w == 0.0) {
+        model.compute_tangents();
+    }
+    Ok((model, Vec::new()))
+}
+
+/// A range of the model drawn with one material
+struct Draw {
+    indices: Range<usize>,
+    diffuse: Vector3<f32>,
+    /// None when the material has no usable texture
+    texture: Option<usize>,
+}
+
+/// Splits the model in ranges of indices sharing the same material
+/// Faces without a (known) material get a white diffuse color and the default texture
+/// `load_texture` is called once per diffuse map and returns the texture number to use
+fn material_draws(
+    model: &obj::Model,
+    mut load_texture: impl FnMut(&Path) -> Option<usize>,
+) -> Vec<Draw> {
+    let white = Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    let materials: Vec<_> = model
+        .materials
+        .iter()
+        .map(|material| {
+            let texture = material
+                .diffuse_map
+                .as_ref()
+                .and_then(|map| load_texture(&map.path));
+            (material.name.as_str(), material.diffuse, texture)
+        })
+        .collect();
+    let mut draws = Vec::new();
+    let mut drawn = 0;
+    for range in &model.material_ranges {
+        if range.indices.start > drawn {
+            draws.push(Draw {
+                indices: drawn..range.indices.start,
+                diffuse: white,
+                texture: Some(0),
+            });
+        }
+        draws.push(
+            match materials.iter().find(|(name, _, _)| *name == range.material) {
+                Some((_, diffuse, texture)) => Draw {
+                    indices: range.indices.clone(),
+                    diffuse: *diffuse,
+                    texture: *texture,
+                },
+                None => Draw {
+                    indices: range.indices.clone(),
+                    diffuse: white,
+                    texture: Some(0),
+                },
+            },
+        );
+        drawn = range.indices.end;
+    }
+    if drawn < model.indices.len() {
+        draws.push(Draw {
+            indices: drawn..model.indices.len(),
+            diffuse: white,
+            texture: Some(0),
+        });
+    }
+    draws
+}
+
 fn process_events(
     events: &GlfwReceiver<(f64, glfw::WindowEvent)>,
     texture_or_color_want: &mut bool,
+    color_scheme: &mut ColorScheme,
+    subdivision: &mut usize,
 ) -> Option<(i32, i32)> {
     let mut ret = None;
     for (_, event) in glfw::flush_messages(events) {
@@ -199,6 +888,15 @@ fn process_events(
             glfw::WindowEvent::Key(Key::C, _, Action::Press, _) => {
                 *texture_or_color_want = !*texture_or_color_want;
             }
+            glfw::WindowEvent::Key(Key::V, _, Action::Press, _) => {
+                *color_scheme = color_scheme.next();
+            }
+            glfw::WindowEvent::Key(Key::RightBracket, _, Action::Press, _) => {
+                *subdivision += 1;
+            }
+            glfw::WindowEvent::Key(Key::LeftBracket, _, Action::Press, _) => {
+                *subdivision = subdivision.saturating_sub(1);
+            }
             _ => {}
         }
     }
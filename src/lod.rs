@@ -0,0 +1,221 @@
+use crate::decimate::{self, DecimateOptions};
+use crate::mesh::NormalOptions;
+use crate::obj::Model;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodOptions {
+    /// Reduced levels made after the model, at most
+    pub levels: usize,
+    /// Triangles of each level, as a fraction of the triangles of the level before
+    pub ratio: f32,
+    /// Levels with fewer triangles than this are not made
+    pub min_triangles: usize,
+    /// Area on screen, in pixels, each triangle of the chosen level should cover at least
+    pub pixels_per_triangle: f32,
+    /// Fraction of the switching size the model must grow or shrink past before the level
+    /// changes, so a model staying around that size does not switch every frame
+    pub hysteresis: f32,
+    /// Smoothing of the reduced triangles when the normals don't come from the file
+    pub normals: NormalOptions,
+}
+
+impl Default for LodOptions {
+    fn default() -> Self {
+        LodOptions {
+            levels: 4,
+            ratio: 0.25,
+            min_triangles: 512,
+            pixels_per_triangle: 4.0,
+            hysteresis: 0.15,
+            normals: NormalOptions::default(),
+        }
+    }
+}
+
+/// Reductions of the model, each made from the one before with `options.ratio` of its
+/// triangles
+/// They stop early when a level would be under `options.min_triangles`, or when the decimation
+/// can't remove enough triangles to be worth another level
+pub fn reductions(model: &Model, options: &LodOptions) -> Vec<Model> {
+    let mut levels: Vec<Model> = Vec::new();
+    while levels.len() < options.levels {
+        let previous = levels.last().unwrap_or(model);
+        let triangles = previous.indices.len() / 3;
+        let target = (triangles as f32 * options.ratio) as usize;
+        if target < options.min_triangles {
+            break;
+        }
+        let reduced = decimate::decimate(
+            previous,
+            &DecimateOptions {
+                target_triangles: target,
+                normals: options.normals,
+                ..Default::default()
+            },
+        );
+        // the topology or the seams stopped the collapses well before the target
+        if reduced.indices.len() / 3 > (triangles + target) / 2 {
+            break;
+        }
+        levels.push(reduced);
+    }
+    levels
+}
+
+/// The model followed by its `reductions`
+pub fn chain(model: Model, options: &LodOptions) -> Vec<Model> {
+    let reductions = reductions(&model, options);
+    std::iter::once(model).chain(reductions).collect()
+}
+
+/// Height in pixels of a sphere of `radius` whose center is `distance` away from the camera,
+/// seen through `Mat4::perspective(fov, ..)` on a viewport `viewport_height` pixels high
+/// Infinite when the camera is inside the sphere
+pub fn projected_size(radius: f32, distance: f32, fov: f32, viewport_height: f32) -> f32 {
+    if distance <= radius {
+        return f32::INFINITY;
+    }
+    // the sphere is seen under a half angle of asin(radius / distance)
+    let tan_half_angle = radius / (distance * distance - radius * radius).sqrt();
+    tan_half_angle / (fov / 2.0).to_radians().tan() * viewport_height
+}
+
+/// Picks the level of a chain to draw from the size of the model on screen
+#[derive(Debug, Clone)]
+pub struct LodSelector {
+    /// Triangles of each level, from the finest
+    triangles: Vec<usize>,
+    pixels_per_triangle: f32,
+    hysteresis: f32,
+    current: usize,
+}
+
+impl LodSelector {
+    /// Starts on the finest level
+    pub fn new(levels: &[Model], options: &LodOptions) -> Self {
+        LodSelector {
+            triangles: levels.iter().map(|level| level.indices.len() / 3).collect(),
+            pixels_per_triangle: options.pixels_per_triangle,
+            hysteresis: options.hysteresis,
+            current: 0,
+        }
+    }
+    pub fn current(&self) -> usize {
+        self.current
+    }
+    /// Coarsest level with enough triangles for a model `size` pixels high, the finest one when
+    /// none has enough
+    fn ideal(&self, size: f32) -> usize {
+        let wanted = size * size / self.pixels_per_triangle;
+        (self.triangles.iter())
+            .rposition(|&triangles| triangles as f32 >= wanted)
+            .unwrap_or(0)
+    }
+    /// Level to draw the model `size` pixels high
+    /// The current level is kept as long as it is the ideal one for a size within
+    /// `hysteresis` of `size`
+    pub fn select(&mut self, size: f32) -> usize {
+        let finest = self.ideal(size * (1.0 + self.hysteresis));
+        let coarsest = self.ideal(size * (1.0 - self.hysteresis));
+        self.current = self.current.clamp(finest, coarsest);
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{ParseOptions, parse_obj};
+
+    /// Flat grid of `n` by `n` quads
+    fn grid(n: usize) -> Model {
+        let mut source = String::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                source += &format!("v {x} {y} 0\n");
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let corner = y * (n + 1) + x + 1;
+                source += &format!(
+                    "f {} {} {} {}\n",
+                    corner,
+                    corner + 1,
+                    corner + n + 2,
+                    corner + n + 1
+                );
+            }
+        }
+        parse_obj(source.as_bytes(), &ParseOptions::default())
+            .unwrap()
+            .0
+    }
+
+    fn selector(triangles: &[usize]) -> LodSelector {
+        LodSelector {
+            triangles: triangles.to_vec(),
+            pixels_per_triangle: 4.0,
+            hysteresis: 0.1,
+            current: 0,
+        }
+    }
+
+    #[test]
+    fn fewer_triangles_each_level() {
+        let options = LodOptions {
+            min_triangles: 100,
+            ..Default::default()
+        };
+        let levels = chain(grid(32), &options);
+        let triangles: Vec<_> = levels.iter().map(|level| level.indices.len() / 3).collect();
+        assert_eq!(triangles[0], 2048);
+        assert!(triangles.len() >= 3, "{triangles:?}");
+        for pair in triangles.windows(2) {
+            assert!(pair[1] <= pair[0] / 2, "{triangles:?}");
+        }
+        assert!(triangles[triangles.len() - 1] >= 100);
+    }
+
+    #[test]
+    fn small_models_have_one_level() {
+        let levels = chain(grid(4), &LodOptions::default());
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].indices.len(), 96);
+    }
+
+    #[test]
+    fn size_on_screen() {
+        // seen under 45 degrees, the sphere fills the viewport
+        let distance = 1.0 / (22.5f32).to_radians().sin();
+        assert!((projected_size(1.0, distance, 45.0, 600.0) - 600.0).abs() < 0.01);
+        let far = projected_size(1.0, 2.0 * distance, 45.0, 600.0);
+        assert!(far > 250.0 && far < 300.0, "{far}");
+        assert_eq!(projected_size(1.0, 0.5, 45.0, 600.0), f32::INFINITY);
+    }
+
+    #[test]
+    fn coarser_levels_when_smaller() {
+        let mut selector = selector(&[10000, 2500, 625]);
+        // 200 pixels high wants 10000 triangles
+        assert_eq!(selector.select(1000.0), 0);
+        assert_eq!(selector.select(80.0), 1);
+        assert_eq!(selector.select(10.0), 2);
+        assert_eq!(selector.select(f32::INFINITY), 0);
+    }
+
+    #[test]
+    fn no_switch_back_and_forth_at_the_switching_size() {
+        let mut selector = selector(&[10000, 2500, 625]);
+        // the ideal level changes at 100 pixels high
+        assert_eq!(selector.select(120.0), 0);
+        for size in [99.0, 101.0, 95.0, 105.0, 99.0] {
+            assert_eq!(selector.select(size), 0, "{size}");
+        }
+        assert_eq!(selector.select(89.0), 1);
+        for size in [101.0, 99.0, 105.0, 109.0] {
+            assert_eq!(selector.select(size), 1, "{size}");
+        }
+        assert_eq!(selector.select(112.0), 0);
+    }
+}
@@ -94,6 +94,22 @@ pub mod matrix {
             }
             ret
         }
+        /// Rotation of the unit quaternion `q`, whose real part is `q.w`
+        pub fn from_quaternion(q: &super::vector::Vector4<T>) -> Self {
+            let one: T = 1.into();
+            let two: T = 2.into();
+            let mut ret = Self::identity();
+            ret[0][0] = one - two * (q.y * q.y + q.z * q.z);
+            ret[0][1] = two * (q.x * q.y - q.z * q.w);
+            ret[0][2] = two * (q.x * q.z + q.y * q.w);
+            ret[1][0] = two * (q.x * q.y + q.z * q.w);
+            ret[1][1] = one - two * (q.x * q.x + q.z * q.z);
+            ret[1][2] = two * (q.y * q.z - q.x * q.w);
+            ret[2][0] = two * (q.x * q.z - q.y * q.w);
+            ret[2][1] = two * (q.y * q.z + q.x * q.w);
+            ret[2][2] = one - two * (q.x * q.x + q.y * q.y);
+            ret
+        }
         pub fn transpose(mut self) -> Self {
             for y in 0..4 {
                 let comps = self.components.as_mut_slice();
@@ -420,6 +436,9 @@ pub mod vector {
                 z: 0.into(),
             }
         }
+        pub fn dot(&self, rhs: &Self) -> T {
+            self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+        }
         pub fn cross(&self, rhs: &Self) -> Self {
             Vector3 {
                 x: self.y * rhs.z - self.z * rhs.y,
@@ -0,0 +1,455 @@
+use crate::math::vector::{Vector3, Vector4};
+use crate::obj::Vertex;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// How much each face around a vertex counts in its normal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalWeighting {
+    /// Area of the face, big faces win over small ones
+    Area,
+    /// Angle of the face at the vertex, which does not depend on how the faces are triangulated
+    #[default]
+    Angle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalOptions {
+    pub weighting: NormalWeighting,
+    /// Faces meeting at a larger angle, in degrees, are not smoothed together and their common
+    /// vertices are split. 180 smooths everything, 0 makes every face flat
+    pub crease_angle: f32,
+}
+
+impl Default for NormalOptions {
+    fn default() -> Self {
+        NormalOptions {
+            weighting: NormalWeighting::default(),
+            crease_angle: 45.0,
+        }
+    }
+}
+
+/// Gives each listed triangle corner its own version of its vertex
+/// The first version of a vertex replaces it, versions with another `key` are appended to
+/// `vertices` once each and the corners are pointed to them. The returned list has the vertex
+/// each copy was made from
+pub(crate) fn split_vertices<K: Copy + Eq + Hash>(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut [u32],
+    corners: Vec<(usize, Vertex)>,
+    key: impl Fn(&Vertex) -> K,
+) -> Vec<u32> {
+    let mut assigned = vec![None; vertices.len()];
+    let mut copies = HashMap::new();
+    let mut sources = Vec::new();
+    for (corner, vertex) in corners {
+        let index = indices[corner];
+        let bits = key(&vertex);
+        match assigned[index as usize] {
+            None => {
+                assigned[index as usize] = Some(bits);
+                vertices[index as usize] = vertex;
+            }
+            Some(first) if first == bits => {}
+            Some(_) => {
+                indices[corner] = *copies.entry((index, bits)).or_insert_with(|| {
+                    vertices.push(vertex);
+                    sources.push(index);
+                    vertices.len() as u32 - 1
+                });
+            }
+        }
+    }
+    sources
+}
+
+/// `values` followed by the value of the vertex each copy made by `split_vertices` comes from
+pub(crate) fn with_copies<T: Copy>(values: &[T], sources: &[u32]) -> Vec<T> {
+    let copies = sources.iter().map(|&i| values[i as usize]);
+    values.iter().copied().chain(copies).collect()
+}
+
+fn angle(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let lengths = (a.norm2() * b.norm2()).sqrt();
+    if lengths > 0.0 {
+        (a.dot(&b) / lengths).clamp(-1.0, 1.0).acos()
+    } else {
+        0.0
+    }
+}
+
+/// Sets the normals of the triangle corners whose vertex passes `replace`, from the faces around
+/// their position within the crease angle
+/// Faces are found around positions, not vertices, so texture and color seams stay smooth
+/// A vertex whose corners end up with different normals is split, the copies are appended to
+/// `vertices` and the returned list has the vertex each one copies
+pub fn smooth_normals(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut [u32],
+    options: &NormalOptions,
+    replace: impl Fn(u32) -> bool,
+) -> Vec<u32> {
+    let mut face_normals = Vec::with_capacity(indices.len() / 3);
+    let mut weights = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| vertices[triangle[k] as usize].position);
+        let normal = (b - a).cross(&(c - a));
+        let area = normal.norm();
+        face_normals.push(if area > 0.0 { normal / area } else { normal });
+        match options.weighting {
+            NormalWeighting::Area => weights.extend([area; 3]),
+            NormalWeighting::Angle => weights.extend([
+                angle(b - a, c - a),
+                angle(c - b, a - b),
+                angle(a - c, b - c),
+            ]),
+        }
+    }
+
+    // corners sorted by position, so the corners around each position follow each other
+    let mut positions = HashMap::new();
+    let corner_count = indices.len() / 3 * 3;
+    let position_ids: Vec<u32> = indices[..corner_count]
+        .iter()
+        .map(|&index| {
+            let p = vertices[index as usize].position;
+            let next = positions.len() as u32;
+            *positions
+                .entry([p.x, p.y, p.z].map(f32::to_bits))
+                .or_insert(next)
+        })
+        .collect();
+    let mut corners: Vec<usize> = (0..corner_count).collect();
+    corners.sort_by_key(|&corner| position_ids[corner]);
+
+    let min_cos = options.crease_angle.clamp(0.0, 180.0).to_radians().cos();
+    let mut corner_normals = vec![None; corner_count];
+    for around in corners.chunk_by(|&a, &b| position_ids[a] == position_ids[b]) {
+        for &corner in around {
+            if !replace(indices[corner]) {
+                continue;
+            }
+            let face_normal = face_normals[corner / 3];
+            let mut normal = Vector3::zero();
+            for &other in around {
+                let other_normal = face_normals[other / 3];
+                // a small margin keeps coplanar faces together despite rounding
+                if other / 3 == corner / 3 || face_normal.dot(&other_normal) >= min_cos - 1e-6 {
+                    normal += other_normal * weights[other];
+                }
+            }
+            corner_normals[corner] = Some(if normal.norm2() > 0.0 {
+                normal.normalized()
+            } else {
+                face_normal
+            });
+        }
+    }
+
+    let corners = corner_normals
+        .into_iter()
+        .enumerate()
+        .filter_map(|(corner, normal)| {
+            let normal = normal?;
+            let vertex = &vertices[indices[corner] as usize];
+            Some((corner, Vertex { normal, ..*vertex }))
+        })
+        .collect();
+    split_vertices(vertices, indices, corners, |v| {
+        [v.normal.x, v.normal.y, v.normal.z].map(f32::to_bits)
+    })
+}
+
+/// Any unit vector orthogonal to `normal`
+fn orthogonal(normal: Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    };
+    let tangent = axis - normal * normal.dot(&axis);
+    if tangent.norm2() > 0.0 {
+        tangent.normalized()
+    } else {
+        axis
+    }
+}
+
+/// Sets the tangent of the triangle corners from their texture coordinates and normals, like
+/// MikkTSpace : the tangent of each triangle is projected on the plane of the corner normal and
+/// weighted by the corner angle, then summed over the corners of identical vertices whose
+/// texture coordinates have the same orientation. The bitangent sign goes in w
+/// The normals should be set first, the tangents are orthogonal to them
+/// A vertex used with both orientations, on a mirror seam, is split, the copies are appended to
+/// `vertices` and the returned list has the vertex each one copies
+pub fn compute_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) -> Vec<u32> {
+    let corner_count = indices.len() / 3 * 3;
+    // None for the corners of triangles without texture area, which don't say anything about
+    // the tangent
+    let mut corner_tangents = Vec::with_capacity(corner_count);
+    for triangle in indices[..corner_count].chunks_exact(3) {
+        let corners = [0, 1, 2].map(|k| &vertices[triangle[k] as usize]);
+        let [a, b, c] = corners;
+        let (d1, d2) = (b.position - a.position, c.position - a.position);
+        let st1 = (
+            b.texture_coordinates.0 - a.texture_coordinates.0,
+            b.texture_coordinates.1 - a.texture_coordinates.1,
+        );
+        let st2 = (
+            c.texture_coordinates.0 - a.texture_coordinates.0,
+            c.texture_coordinates.1 - a.texture_coordinates.1,
+        );
+        let signed_area = st1.0 * st2.1 - st2.0 * st1.1;
+        let preserving = signed_area > 0.0;
+        // direction of increasing u, flipped back when the texture is mirrored
+        let tangent = (d1 * st2.1 - d2 * st1.1) * if preserving { 1.0 } else { -1.0 };
+        let length = tangent.norm2();
+        if signed_area == 0.0 || !signed_area.is_finite() || length == 0.0 || length.is_nan() {
+            corner_tangents.extend([None; 3]);
+            continue;
+        }
+        for k in 0..3 {
+            let corner = corners[k];
+            let normal = corner.normal;
+            let project = |v: Vector3<f32>| v - normal * normal.dot(&v);
+            let projected = project(tangent);
+            let edges = [
+                corners[(k + 1) % 3].position - corner.position,
+                corners[(k + 2) % 3].position - corner.position,
+            ];
+            let contribution = if projected.norm2() > 0.0 {
+                projected.normalized() * angle(project(edges[0]), project(edges[1]))
+            } else {
+                Vector3::zero()
+            };
+            corner_tangents.push(Some((contribution, preserving)));
+        }
+    }
+
+    // corners of identical vertices, which may be apart in `vertices`, are summed together
+    let mut groups = HashMap::new();
+    let group_ids: Vec<Option<u32>> = (0..corner_count)
+        .map(|corner| {
+            let (_, preserving) = corner_tangents[corner]?;
+            let vertex = &vertices[indices[corner] as usize];
+            let (p, n) = (vertex.position, vertex.normal);
+            let (u, v) = vertex.texture_coordinates;
+            let key = (
+                [p.x, p.y, p.z, n.x, n.y, n.z, u, v].map(f32::to_bits),
+                preserving,
+            );
+            let next = groups.len() as u32;
+            Some(*groups.entry(key).or_insert(next))
+        })
+        .collect();
+    let mut sums = vec![Vector3::zero(); groups.len()];
+    for (corner, group) in group_ids.iter().enumerate() {
+        if let (Some(group), Some((contribution, _))) = (group, corner_tangents[corner]) {
+            sums[*group as usize] += contribution;
+        }
+    }
+
+    let frame = |direction: Vector3<f32>, preserving: bool| Vector4 {
+        x: direction.x,
+        y: direction.y,
+        z: direction.z,
+        w: if preserving { 1.0 } else { -1.0 },
+    };
+    let mut has_tangent = vec![false; vertices.len()];
+    let mut corners = Vec::with_capacity(corner_count);
+    for (corner, group) in group_ids.iter().enumerate() {
+        let (Some(group), Some((_, preserving))) = (group, corner_tangents[corner]) else {
+            continue;
+        };
+        let vertex = &vertices[indices[corner] as usize];
+        let sum = sums[*group as usize];
+        let direction = if sum.norm2() > 0.0 {
+            sum.normalized()
+        } else {
+            orthogonal(vertex.normal)
+        };
+        has_tangent[indices[corner] as usize] = true;
+        let tangent = frame(direction, preserving);
+        corners.push((corner, Vertex { tangent, ..*vertex }));
+    }
+    // vertices only used by degenerate triangles still need a valid frame
+    for corner in 0..corner_count {
+        let vertex = &vertices[indices[corner] as usize];
+        if corner_tangents[corner].is_none() && !has_tangent[indices[corner] as usize] {
+            let tangent = frame(orthogonal(vertex.normal), true);
+            corners.push((corner, Vertex { tangent, ..*vertex }));
+        }
+    }
+    split_vertices(vertices, indices, corners, |v| {
+        [v.tangent.x, v.tangent.y, v.tangent.z, v.tangent.w].map(f32::to_bits)
+    })
+}
+
+/// Vertices of the corners of the polygon triangulated in `triangles`, in order around it : the
+/// ends of the edges of its triangles no other of them has, which go around the polygon once
+/// None when they don't, like for polygons going through a position twice
+/// Vertices are the same corner when `position` gives them the same value
+pub fn polygon_outline(triangles: &[u32], position: impl Fn(u32) -> u32) -> Option<Vec<u32>> {
+    let edges: Vec<(u32, u32, u32)> = (triangles.chunks_exact(3))
+        .flat_map(|t| (0..3).map(move |k| (t[k], t[(k + 1) % 3])))
+        .map(|(a, b)| (position(a), position(b), a))
+        .collect();
+    let border: Vec<(u32, u32, u32)> = (edges.iter().copied())
+        .filter(|&(a, b, _)| !edges.iter().any(|&(c, d, _)| (c, d) == (b, a)))
+        .collect();
+    // n - 2 triangles of a polygon of n corners
+    if border.len() != triangles.len() / 3 + 2 {
+        return None;
+    }
+    let mut corners = Vec::with_capacity(border.len());
+    let mut edge = border[0];
+    for _ in 0..border.len() {
+        corners.push(edge.2);
+        let end = edge.1;
+        let mut next = border.iter().filter(|e| e.0 == end);
+        edge = *next.next()?;
+        if next.next().is_some() {
+            return None;
+        }
+    }
+    (edge == border[0]).then_some(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex([x, y, z]: [f32; 3], (u, v): (f32, f32)) -> Vertex {
+        Vertex {
+            position: Vector3 { x, y, z },
+            color: Vector3::zero(),
+            texture_coordinates: (u, v),
+            normal: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            tangent: Vector4::zero(),
+        }
+    }
+
+    /// The unit cube, its 8 corners shared by the 12 triangles
+    fn cube() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = (0..8)
+            .map(|i| vertex([i & 1, i >> 1 & 1, i >> 2].map(|c| c as f32), (0.0, 0.0)))
+            .collect();
+        let sides = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let indices = sides
+            .iter()
+            .flat_map(|&[a, b, c, d]| [a, b, c, a, c, d])
+            .collect();
+        (vertices, indices)
+    }
+
+    /// Tangent of the single vertex of a quad in the xy plane, with these texture coordinates
+    fn quad_tangent(texture_coordinates: [(f32, f32); 4]) -> Vector4<f32> {
+        let corners = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let mut vertices = corners
+            .into_iter()
+            .zip(texture_coordinates)
+            .map(|(p, uv)| vertex(p, uv))
+            .collect();
+        let mut indices = [0, 1, 2, 0, 2, 3];
+        assert!(compute_tangents(&mut vertices, &mut indices).is_empty());
+        vertices[0].tangent
+    }
+
+    #[test]
+    fn cube_edges_sharper_than_the_crease_angle() {
+        let (mut vertices, mut indices) = cube();
+        let sources = smooth_normals(
+            &mut vertices,
+            &mut indices,
+            &NormalOptions::default(),
+            |_| true,
+        );
+        assert_eq!(vertices.len(), 24);
+        assert_eq!(sources.len(), 16);
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| vertices[triangle[k] as usize].position);
+            let face_normal = (b - a).cross(&(c - a));
+            for &index in triangle {
+                let n = vertices[index as usize].normal;
+                assert_eq!(
+                    [n.x, n.y, n.z],
+                    [face_normal.x, face_normal.y, face_normal.z]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cube_smoothed_past_the_crease_angle() {
+        let (mut vertices, mut indices) = cube();
+        let options = NormalOptions {
+            crease_angle: 180.0,
+            ..NormalOptions::default()
+        };
+        assert!(smooth_normals(&mut vertices, &mut indices, &options, |_| true).is_empty());
+        for vertex in &vertices {
+            let p = vertex.position;
+            let outward = (p - Vector3 {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            })
+            .normalized();
+            assert!(vertex.normal.dot(&outward) > 0.999);
+        }
+    }
+
+    #[test]
+    fn mirrored_texture_flips_the_bitangent() {
+        let tangent = quad_tangent([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        assert_eq!(
+            [tangent.x, tangent.y, tangent.z, tangent.w],
+            [1.0, 0.0, 0.0, 1.0]
+        );
+        let tangent = quad_tangent([(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)]);
+        assert_eq!(
+            [tangent.x, tangent.y, tangent.z, tangent.w],
+            [1.0, 0.0, 0.0, -1.0]
+        );
+    }
+
+    #[test]
+    fn degenerate_texture_coordinates() {
+        for uv in [(0.5, 0.5), (f32::NAN, 0.0)] {
+            let tangent = quad_tangent([uv; 4]);
+            let direction = Vector3 {
+                x: tangent.x,
+                y: tangent.y,
+                z: tangent.z,
+            };
+            assert!(direction.norm().is_finite() && (direction.norm() - 1.0).abs() < 1e-6);
+            assert_eq!(direction.z, 0.0);
+            assert!(tangent.w == 1.0 || tangent.w == -1.0);
+        }
+    }
+}
@@ -1,30 +1,88 @@
-use super::math::vector::{Vector2, Vector3};
+pub mod freeform;
+mod lexer;
+pub mod mtl;
+mod parallel;
+pub mod triangulation;
+mod writer;
+
+pub use writer::{WriteOptions, write_obj};
+
+use super::color::{self, ColorScheme};
+use super::math::matrix::Mat4;
+use super::math::vector::{Vector3, Vector4};
+use super::mesh::{self, NormalOptions};
+use super::uv::{self, UvOptions};
+use freeform::{Basis, CurveType, Element, ElementKind, Tessellation};
+use lexer::Statement;
+use mtl::Material;
+use triangulation::{PolygonIssue, Triangulation};
 use std::collections::HashMap;
 use std::error::Error;
-use std::f32::consts::PI;
 use std::fmt::{self, Display};
 use std::io::{BufRead, Error as IOError};
-
-//Struct with line info + Error type ?
+use std::ops::Range;
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct ParseError {
     pub line: Option<String>,
+    /// 1 based
     pub line_no: usize,
+    /// 0 based byte range of the offending part of the line
+    pub columns: Option<Range<usize>>,
     pub err_type: ErrorType,
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.line_no)?;
+        if let Some(columns) = &self.columns {
+            write!(f, ":{}", columns.start + 1)?;
+        }
         match &self.line {
-            Some(line) => write!(f, "{}:{} : {}", self.line_no, line, self.err_type),
-            None => write!(f, "{}: {}", self.line_no, self.err_type),
+            Some(line) => write!(f, ":{} : {}", line, self.err_type),
+            None => write!(f, ": {}", self.err_type),
         }
     }
 }
 
 impl Error for ParseError {}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    /// The line was skipped, only reported when `ParseOptions.keep_going` is set
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Problem found while parsing that did not stop the parsing
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: ParseError,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.error)
+    }
+}
+
+/// Byte range of `token` in `line`, `token` has to be a subslice of `line`
+fn columns(line: &[u8], token: &[u8]) -> Range<usize> {
+    let start = token.as_ptr() as usize - line.as_ptr() as usize;
+    start..start + token.len()
+}
+
 #[derive(Debug)]
 pub enum ErrorType {
     IOError(IOError),
@@ -34,6 +92,10 @@ pub enum ErrorType {
     InvalidParameter(usize),
     InvalidParameterNumber,
     InvalidLine,
+    InvalidPolygon(PolygonIssue),
+    IgnoredComponent(&'static str),
+    /// The statement needs another one to come first
+    MissingStatement(&'static str),
 }
 
 impl Display for ErrorType {
@@ -46,28 +108,419 @@ impl Display for ErrorType {
             ErrorType::InvalidParameter(p) => write!(f, "Parameter #{p} is invalid"),
             ErrorType::InvalidParameterNumber => write!(f, "Invalid number of parameters"),
             ErrorType::InvalidLine => write!(f, "Invalid line"),
+            ErrorType::InvalidPolygon(issue) => write!(f, "{issue}"),
+            ErrorType::IgnoredComponent(c) => write!(f, "{c} component ignored"),
+            ErrorType::MissingStatement(s) => write!(f, "Expected '{s}' first"),
         }
     }
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Vertex {
     pub position: Vector3<f32>,
     pub color: Vector3<f32>,
     pub texture_coordinates: (f32, f32),
     pub normal: Vector3<f32>,
+    /// Direction of increasing u, with the sign of the bitangent in w, or zero when the tangents
+    /// were not computed
+    pub tangent: Vector4<f32>,
 }
 
-#[derive(Debug)]
+/// Range of `Model.indices` drawn with the material named `material`
+#[derive(Debug, Clone)]
+pub struct MaterialRange {
+    pub material: String,
+    pub indices: Range<usize>,
+}
+
+/// Range of `Model.indices` belonging to one group of one object
+/// `object` is empty for faces that come before any `o` statement, `group` is the rest of the
+/// `g` line, or "default" if there was none
+#[derive(Debug, Clone)]
+pub struct Submesh {
+    pub object: String,
+    pub group: String,
+    pub indices: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Model {
     pub vertices: Box<[Vertex]>,
     pub indices: Box<[u32]>,
+    /// mtl files referenced by the obj, as written in the file
+    pub material_libs: Vec<String>,
+    /// Filled by `load_materials`
+    pub materials: Vec<Material>,
+    pub material_ranges: Vec<MaterialRange>,
+    /// Ordered, non overlapping and covering all of `indices`
+    pub submeshes: Vec<Submesh>,
+    /// Triangles of the polygons of the file with more than 3 corners, as ordered ranges of
+    /// `indices`, empty when the loader does not keep them. The other triangles are faces of
+    /// their own
+    pub polygons: Vec<Range<usize>>,
+    /// Polylines, from `l` statements and free-form curves
+    pub line_indices: Box<[u32]>,
+    /// One range of `line_indices` per polyline
+    pub line_strips: Vec<Range<usize>>,
+    /// From `p` statements
+    pub point_indices: Box<[u32]>,
+    /// Per vertex values that have no place in `Vertex`, like the extra properties of ply files
+    pub attributes: Vec<VertexAttribute>,
+    /// Color given by the file to each vertex, empty when the file has no colors
+    /// `Vertex.color` holds the colors of the current color scheme
+    pub file_colors: Box<[Option<Vector3<f32>>]>,
+    /// Face of the file each vertex belongs to, the first one when several faces share it
+    pub vertex_faces: Box<[Option<u32>]>,
+    /// Number of faces of the file, polygons count as one face
+    pub face_count: usize,
+    /// The file gives normals, to some vertices at least, the others have computed ones
+    pub file_normals: bool,
+    /// The file gives texture coordinates, to some vertices at least, the others are projected
+    pub file_texture_coordinates: bool,
+    /// Bounds of the positions, which are kept as they are in the file
+    pub bounds: BoundingBox,
+}
+
+/// One value per vertex of the model, under the name it has in the file
+#[derive(Debug, Clone)]
+pub struct VertexAttribute {
+    pub name: String,
+    pub values: Box<[f64]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl BoundingBox {
+    /// Smallest box containing all the points, or an empty box at the origin
+    pub fn from_points(points: impl Iterator<Item = Vector3<f32>>) -> Self {
+        points
+            .map(|p| BoundingBox { min: p, max: p })
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(BoundingBox {
+                min: Vector3::zero(),
+                max: Vector3::zero(),
+            })
+    }
+    pub fn union(&self, other: &Self) -> Self {
+        BoundingBox {
+            min: Vector3 {
+                x: self.min.x.min(other.min.x),
+                y: self.min.y.min(other.min.y),
+                z: self.min.z.min(other.min.z),
+            },
+            max: Vector3 {
+                x: self.max.x.max(other.max.x),
+                y: self.max.y.max(other.max.y),
+                z: self.max.z.max(other.max.z),
+            },
+        }
+    }
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+    pub fn largest_axis(&self) -> f32 {
+        let size = self.size();
+        size.x.max(size.y).max(size.z)
+    }
+    /// Transform that centers the box on the origin and makes its largest side 1 long
+    pub fn normalization(&self) -> Mat4<f32> {
+        let largest_axis = self.largest_axis();
+        let scale = if largest_axis > 0.0 {
+            1.0 / largest_axis
+        } else {
+            1.0
+        };
+        Mat4::scale(&Vector3 {
+            x: scale,
+            y: scale,
+            z: scale,
+        }) * Mat4::translate(&-self.center())
+    }
+}
+
+/// Applies `matrix` to the positions, normals and tangents of `vertices`
+pub fn transform_vertices(vertices: &mut [Vertex], matrix: &Mat4<f32>) {
+    let m = &matrix.components;
+    // Normals are transformed by the cofactor matrix of the linear part, which is its inverse
+    // transpose up to a scale factor, so they stay orthogonal to non uniformly scaled faces
+    let cofactor = |row: usize, col: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    // the cofactors are scaled by the determinant, whose sign would flip the normals of mirrors
+    let determinant: f32 = (0..3).map(|col| m[0][col] * cofactor(0, col)).sum();
+    let sign = if determinant < 0.0 { -1.0 } else { 1.0 };
+    let normal_matrix = [0, 1, 2].map(|row| [0, 1, 2].map(|col| sign * cofactor(row, col)));
+    for vertex in vertices {
+        let p = matrix
+            * Vector4 {
+                x: vertex.position.x,
+                y: vertex.position.y,
+                z: vertex.position.z,
+                w: 1.0,
+            };
+        vertex.position = Vector3 {
+            x: p.x / p.w,
+            y: p.y / p.w,
+            z: p.z / p.w,
+        };
+        let n = vertex.normal;
+        let normal = Vector3 {
+            x: normal_matrix[0][0] * n.x + normal_matrix[0][1] * n.y + normal_matrix[0][2] * n.z,
+            y: normal_matrix[1][0] * n.x + normal_matrix[1][1] * n.y + normal_matrix[1][2] * n.z,
+            z: normal_matrix[2][0] * n.x + normal_matrix[2][1] * n.y + normal_matrix[2][2] * n.z,
+        };
+        if normal.norm2() > 0.0 {
+            vertex.normal = normal.normalized();
+        }
+        // tangents follow the surface like positions, mirrors flip the bitangent
+        let t = vertex.tangent;
+        let tangent = Vector3 {
+            x: m[0][0] * t.x + m[0][1] * t.y + m[0][2] * t.z,
+            y: m[1][0] * t.x + m[1][1] * t.y + m[1][2] * t.z,
+            z: m[2][0] * t.x + m[2][1] * t.y + m[2][2] * t.z,
+        };
+        if tangent.norm2() > 0.0 {
+            let tangent = tangent.normalized();
+            vertex.tangent = Vector4 {
+                x: tangent.x,
+                y: tangent.y,
+                z: tangent.z,
+                w: t.w * sign,
+            };
+        }
+    }
+}
+
+impl Model {
+    /// Model made of triangles only, in a single submesh and without materials
+    /// Each triangle is a face, the vertices keep their colors until `set_colors`
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Model {
+        let bounds = BoundingBox::from_points(vertices.iter().map(|v| v.position));
+        let face_count = indices.len() / 3;
+        let mut vertex_faces = vec![None; vertices.len()];
+        for (triangle_no, triangle) in indices.chunks_exact(3).enumerate() {
+            for &index in triangle {
+                vertex_faces[index as usize].get_or_insert(triangle_no as u32);
+            }
+        }
+        let mut submeshes = Vec::new();
+        if !indices.is_empty() {
+            submeshes.push(Submesh {
+                object: String::new(),
+                group: "default".into(),
+                indices: 0..indices.len(),
+            });
+        }
+        Model {
+            vertices: vertices.into(),
+            indices: indices.into(),
+            material_libs: Vec::new(),
+            materials: Vec::new(),
+            material_ranges: Vec::new(),
+            submeshes,
+            polygons: Vec::new(),
+            line_indices: Box::new([]),
+            line_strips: Vec::new(),
+            point_indices: Box::new([]),
+            attributes: Vec::new(),
+            file_colors: Box::new([]),
+            vertex_faces: vertex_faces.into(),
+            face_count,
+            file_normals: false,
+            file_texture_coordinates: false,
+            bounds,
+        }
+    }
+    /// Loads every material library referenced by the model
+    /// The libraries are looked up relative to the directory of `obj_path`
+    /// Returns the warnings of all the libraries
+    pub fn load_materials(
+        &mut self,
+        obj_path: &Path,
+        ignore_unimplemented: bool,
+    ) -> Result<Vec<Diagnostic>, ParseError> {
+        let dir = obj_path.parent().unwrap_or(Path::new(""));
+        let mut diagnostics = Vec::new();
+        for lib in &self.material_libs {
+            let (materials, lib_diagnostics) = mtl::load_mtl(&dir.join(lib), ignore_unimplemented)?;
+            self.materials.extend(materials);
+            diagnostics.extend(lib_diagnostics);
+        }
+        Ok(diagnostics)
+    }
+    pub fn material(&self, name: &str) -> Option<&Material> {
+        self.materials.iter().find(|m| m.name == name)
+    }
+    /// Applies `matrix` to the positions, normals and tangents, and updates the bounds
+    pub fn transform(&mut self, matrix: &Mat4<f32>) {
+        transform_vertices(&mut self.vertices, matrix);
+        self.bounds = BoundingBox::from_points(self.vertices.iter().map(|v| v.position));
+    }
+    /// Sets the colors of the vertices from `scheme`, see `color::scheme_colors`
+    pub fn set_colors(&mut self, scheme: ColorScheme) {
+        let colors = color::scheme_colors(self, scheme);
+        for (vertex, color) in self.vertices.iter_mut().zip(colors) {
+            vertex.color = color;
+        }
+    }
+    /// Gives the vertices appended by a split the per vertex data of the vertex they copy
+    fn copy_vertex_data(&mut self, sources: &[u32]) {
+        for attribute in &mut self.attributes {
+            attribute.values = mesh::with_copies(&attribute.values, sources).into();
+        }
+        if !self.file_colors.is_empty() {
+            self.file_colors = mesh::with_copies(&self.file_colors, sources).into();
+        }
+        self.vertex_faces = mesh::with_copies(&self.vertex_faces, sources).into();
+    }
+    /// Replaces the normals of the triangles with smooth normals, see `mesh::smooth_normals`
+    /// Vertices split along the creases keep their attributes
+    pub fn compute_normals(&mut self, options: &NormalOptions) {
+        let mut vertices = std::mem::take(&mut self.vertices).into_vec();
+        let sources = mesh::smooth_normals(&mut vertices, &mut self.indices, options, |_| true);
+        self.vertices = vertices.into();
+        self.copy_vertex_data(&sources);
+    }
+    /// Replaces the texture coordinates of the vertices passing `replace`, see `uv::project`
+    /// Vertices split along the seams keep their attributes
+    pub fn project_uvs(&mut self, options: &UvOptions, replace: impl Fn(u32) -> bool) {
+        let mut vertices = std::mem::take(&mut self.vertices).into_vec();
+        let sources = uv::project(
+            &mut vertices,
+            &mut self.indices,
+            &self.bounds,
+            options,
+            replace,
+        );
+        self.vertices = vertices.into();
+        self.copy_vertex_data(&sources);
+    }
+    /// Sets the tangents of the triangles, see `mesh::compute_tangents`
+    /// Vertices split on mirror seams keep their attributes
+    pub fn compute_tangents(&mut self) {
+        let mut vertices = std::mem::take(&mut self.vertices).into_vec();
+        let sources = mesh::compute_tangents(&mut vertices, &mut self.indices);
+        self.vertices = vertices.into();
+        self.copy_vertex_data(&sources);
+    }
+    /// Names of the objects of the model, in file order
+    pub fn objects(&self) -> Vec<&str> {
+        let mut objects: Vec<&str> = Vec::new();
+        for submesh in &self.submeshes {
+            if !objects.contains(&submesh.object.as_str()) {
+                objects.push(&submesh.object);
+            }
+        }
+        objects
+    }
+    /// Every polyline as pairs of indices, to be drawn as separate lines
+    pub fn line_segments(&self) -> Vec<u32> {
+        self.line_strips
+            .iter()
+            .flat_map(|strip| self.line_indices[strip.clone()].windows(2).flatten())
+            .copied()
+            .collect()
+    }
+    pub fn object_submeshes<'a>(&'a self, object: &'a str) -> impl Iterator<Item = &'a Submesh> {
+        self.submeshes.iter().filter(move |s| s.object == object)
+    }
 }
 
 struct VertexData {
     position: Vector3<f32>,
     color: Option<Vector3<f32>>,
+    /// Only used by rational curves and surfaces
+    weight: f32,
+}
+
+#[derive(Default)]
+struct ParseState {
+    positions_color: Vec<VertexData>,
+    normals: Vec<Vector3<f32>>,
+    texture_coords: Vec<(f32, f32)>,
+    /// What every vertex of the model is made of, in order of creation
+    vertices: Vec<FaceInfo>,
+    /// Vertex of every distinct corner, so that faces share their vertices
+    vertex_ids: HashMap<(FaceInfo, Shading), u32>,
+    indices: Vec<u32>,
+    material_libs: Vec<String>,
+    material_ranges: Vec<MaterialRange>,
+    submeshes: Vec<Submesh>,
+    polygons: Vec<Range<usize>>,
+    /// Current smoothing group, 0 when off
+    smoothing_group: u32,
+    /// Smoothing group of every face, indexed by face_no
+    face_smoothing: Vec<u32>,
+    /// Sum of the triangle normals of every face, indexed by face_no
+    face_normals: Vec<Vector3<f32>>,
+    /// Sum of the triangle normals around each position, per smoothing group
+    smooth_normals: HashMap<(u32, u32), Vector3<f32>>,
+    line_indices: Vec<u32>,
+    line_strips: Vec<Range<usize>>,
+    point_indices: Vec<u32>,
+    curve_type: Option<CurveType>,
+    degree: (usize, usize),
+    /// Free-form element waiting for its `end`
+    element: Option<Element>,
+    /// Vertices of the tessellated free-form elements, referenced by `FaceInfo.generated`
+    /// They are kept apart so they do not shift the indices of the file
+    generated_positions: Vec<Vector3<f32>>,
+    generated_normals: Vec<Vector3<f32>>,
+    generated_texture_coords: Vec<(f32, f32)>,
+    /// Buffers reused by every face
+    polygon: Vec<FaceInfo>,
+    polygon_positions: Vec<Vector3<f32>>,
+    corners: Vec<u32>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Where the normal of a corner comes from, corners only share a vertex if it is the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Shading {
+    /// `vn` of the file
+    Given,
+    /// Normal of the face `face_no`
+    Flat(usize),
+    /// Normal of the smoothing group around the position
+    Smooth(u32),
+}
+
+/// Sets `corners` to the vertices of the corners of `polygon`, created by the first corner with
+/// the same indices and shading
+fn push_vertices(
+    vertices: &mut Vec<FaceInfo>,
+    vertex_ids: &mut HashMap<(FaceInfo, Shading), u32>,
+    polygon: &[FaceInfo],
+    smoothing_group: u32,
+    corners: &mut Vec<u32>,
+) {
+    corners.clear();
+    for info in polygon {
+        let shading = match (info.normal, smoothing_group) {
+            (Some(_), _) => Shading::Given,
+            (None, 0) => Shading::Flat(info.face_no),
+            (None, group) => Shading::Smooth(group),
+        };
+        // the face only matters through the shading
+        let info_key = FaceInfo {
+            face_no: 0,
+            ..*info
+        };
+        corners.push(*vertex_ids.entry((info_key, shading)).or_insert_with(|| {
+            vertices.push(*info);
+            vertices.len() as u32 - 1
+        }));
+    }
 }
 
 /// returns the 0 based index into an array from a 1 based index
@@ -91,17 +544,41 @@ fn get_index(array_len: usize, index: isize) -> Result<u32, ErrorType> {
     Err(ErrorType::IndexOutOfBound(index))
 }
 
-/// Triangulates a polygonal face by using the fan method
-/// Fast and easy but might fail on Concave shapes
-fn fan_triangulation(indices: Vec<FaceInfo>) -> Vec<FaceInfo> {
-    let mut ret: Vec<FaceInfo> = Vec::new();
-    let (first, rest) = indices.as_slice().split_first().unwrap();
-    for indices in rest.windows(2) {
-        ret.push(*first);
-        ret.push(indices[0]);
-        ret.push(indices[1]);
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Only warn about unsupported entries instead of failing
+    pub ignore_unimplemented: bool,
+    /// Skip invalid lines and report them as diagnostics instead of failing
+    /// IO errors still stop the parsing
+    pub keep_going: bool,
+    pub triangulation: Triangulation,
+    /// Number of segments along each parameter of the tessellated curves and surfaces
+    pub freeform_resolution: usize,
+    /// Threads used to parse the file, 1 parses it on the calling thread
+    /// The model is the same whatever the number of threads
+    pub threads: usize,
+    /// Normals of the faces without `vn`, computed with `mesh::smooth_normals` instead of the
+    /// smoothing groups
+    pub normals: Option<NormalOptions>,
+    /// Compute the tangents of the triangles, see `mesh::compute_tangents`
+    pub tangents: bool,
+    /// Projection giving texture coordinates to the files without `vt`
+    pub uv: UvOptions,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            ignore_unimplemented: true,
+            keep_going: false,
+            triangulation: Triangulation::default(),
+            freeform_resolution: 16,
+            threads: 1,
+            normals: None,
+            tangents: false,
+            uv: UvOptions::default(),
+        }
     }
-    ret
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -117,6 +594,8 @@ struct FaceInfo {
     pub texture: Option<u32>,
     pub normal: Option<u32>,
     pub face_no: usize,
+    /// Indices into the generated vertices of `ParseState`
+    pub generated: bool,
 }
 
 impl FaceInfoRaw {
@@ -124,18 +603,16 @@ impl FaceInfoRaw {
         (self.texture.is_some() == other.texture.is_some())
             && (self.normal.is_some() == other.normal.is_some())
     }
-    pub fn parse(s: &str) -> Option<Self> {
-        let mut nums = s.split("/");
-        let vertex = nums.next()?.parse::<isize>().ok()?;
+    pub fn parse(s: &[u8]) -> Option<Self> {
+        let mut nums = s.split(|&b| b == b'/');
+        let vertex = lexer::parse_int(nums.next()?)?;
         let texture = match nums.next() {
-            None => None,
-            Some("") => None,
-            Some(number) => Some(number.parse::<isize>().ok()?),
+            None | Some(b"") => None,
+            Some(number) => Some(lexer::parse_int(number)?),
         };
         let normal = match nums.next() {
-            None => None,
-            Some("") => None,
-            Some(number) => Some(number.parse::<isize>().ok()?),
+            None | Some(b"") => None,
+            Some(number) => Some(lexer::parse_int(number)?),
         };
         Some(Self {
             vertex,
@@ -161,242 +638,772 @@ impl FaceInfoRaw {
                 .map(|i| get_index(normals_size, i))
                 .transpose()?,
             face_no,
+            generated: false,
         })
     }
 }
 
-fn parse_one_line(
-    index: usize,
-    line: String,
+/// Error about `token`, which has to be a subslice of `line`
+pub(crate) fn line_error(line_no: usize, line: &[u8], token: &[u8], err_type: ErrorType) -> ParseError {
+    line_error_at(line_no, line, columns(line, token), err_type)
+}
+
+fn line_error_at(
+    line_no: usize,
+    line: &[u8],
+    columns: Range<usize>,
+    err_type: ErrorType,
+) -> ParseError {
+    ParseError {
+        line: Some(String::from_utf8_lossy(line).into_owned()),
+        line_no,
+        columns: Some(columns),
+        err_type,
+    }
+}
 
-    positions_color: &mut Vec<VertexData>,
-    normals: &mut Vec<Vector3<f32>>,
-    texture_coords: &mut Vec<(f32, f32)>,
-    indices: &mut Vec<FaceInfo>,
+/// Adds a lexed line to the model
+/// `faces` holds the vertices of lexed faces, with the columns of their token
+fn apply_statement(
+    line_no: usize,
+    line: &[u8],
+    statement: Statement,
+    faces: &[(FaceInfoRaw, Range<usize>)],
+    state: &mut ParseState,
+    options: &ParseOptions,
 ) -> Result<(), ParseError> {
     use ErrorType::*;
+    let ParseState {
+        positions_color,
+        normals,
+        texture_coords,
+        vertices,
+        vertex_ids,
+        indices,
+        material_libs,
+        material_ranges,
+        submeshes,
+        polygons,
+        smoothing_group,
+        face_smoothing,
+        face_normals,
+        smooth_normals,
+        line_indices,
+        line_strips,
+        point_indices,
+        curve_type,
+        degree,
+        element,
+        generated_positions,
+        generated_normals,
+        generated_texture_coords,
+        polygon,
+        polygon_positions,
+        corners,
+        diagnostics,
+    } = state;
     macro_rules! error {
         ($err:expr) => {
-            ParseError {
-                line: Some(line.clone()),
-                line_no: index,
-                err_type: $err,
-            }
+            error!($err, line)
+        };
+        ($err:expr, $token:expr) => {
+            line_error(line_no, line, AsRef::<[u8]>::as_ref($token), $err)
         };
     }
-    if line.starts_with("#") {
-        return Ok(());
+    macro_rules! warning {
+        ($err:expr, $token:expr) => {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                error: error!($err, $token),
+            })
+        };
     }
-    if line.is_empty() {
-        return Ok(());
+    // resolves the indices of the lexed vertices of a face into `polygon`
+    macro_rules! resolve_polygon {
+        ($range:expr, $face_no:expr) => {
+            polygon.clear();
+            for (raw, columns) in &faces[$range] {
+                let info = raw
+                    .get_indices(positions_color.len(), texture_coords.len(), normals.len(), $face_no)
+                    .map_err(|e| line_error_at(line_no, line, columns.clone(), e))?;
+                polygon.push(info);
+            }
+        };
     }
-    let (line_type, rest) = line.split_once(' ').ok_or(error!(InvalidLine))?;
+    let rest = match statement {
+        Statement::Empty => return Ok(()),
+        Statement::Vertex(vertex) => {
+            positions_color.push(vertex);
+            return Ok(());
+        }
+        Statement::TextureCoords { uv, ignored_w } => {
+            texture_coords.push(uv);
+            if let Some(w) = ignored_w {
+                warning!(IgnoredComponent("Texture w"), w);
+            }
+            return Ok(());
+        }
+        Statement::Normal(normal) => {
+            normals.push(normal);
+            return Ok(());
+        }
+        Statement::Face(range) => {
+            let face_no = face_smoothing.len();
+            let all_corners = faces[range.start].1.start..faces[range.end - 1].1.end;
+            resolve_polygon!(range, face_no);
+            polygon_positions.clear();
+            polygon_positions.extend(polygon.iter().map(|f| positions_color[f.vertex as usize].position));
+            let (triangles, issue) = triangulation::triangulate(options.triangulation, polygon_positions);
+            if let Some(issue) = issue {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    error: line_error_at(line_no, line, all_corners, InvalidPolygon(issue)),
+                });
+            }
+            face_smoothing.push(*smoothing_group);
+            face_normals.push(Vector3::zero());
+            push_vertices(vertices, vertex_ids, polygon, *smoothing_group, corners);
+            let start = indices.len();
+            for triangle in &triangles {
+                let [a, b, c] = triangle.map(|i| polygon_positions[i]);
+                let normal = (b - a).cross(&(c - a));
+                face_normals[face_no] += normal;
+                if *smoothing_group != 0 {
+                    for &corner in triangle {
+                        *smooth_normals
+                            .entry((polygon[corner].vertex, *smoothing_group))
+                            .or_insert(Vector3::zero()) += normal;
+                    }
+                }
+                indices.extend(triangle.map(|i| corners[i]));
+            }
+            if polygon.len() > 3 && indices.len() > start {
+                polygons.push(start..indices.len());
+            }
+            return Ok(());
+        }
+        Statement::Line(ref range) | Statement::Points(ref range) => {
+            let face_no = face_smoothing.len();
+            resolve_polygon!(range.clone(), face_no);
+            face_smoothing.push(0);
+            face_normals.push(Vector3::zero());
+            push_vertices(vertices, vertex_ids, polygon, 0, corners);
+            if let Statement::Line(_) = statement {
+                let start = line_indices.len();
+                line_indices.extend_from_slice(corners);
+                line_strips.push(start..line_indices.len());
+            } else {
+                point_indices.extend_from_slice(corners);
+            }
+            return Ok(());
+        }
+        Statement::Other { keyword, rest } => (keyword, rest),
+    };
+    // everything else is rare, and easier to handle as text
+    let (Ok(line_type), Ok(rest)) = (str::from_utf8(rest.0), str::from_utf8(rest.1)) else {
+        return Err(error!(InvalidLine));
+    };
     match line_type {
-        "v" => {
+        "mtllib" => {
+            let args: Vec<_> = rest.split_whitespace().collect();
+            if args.is_empty() {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            material_libs.extend(args.into_iter().map(String::from));
+        }
+        "usemtl" => {
+            let name = rest.trim();
+            if name.is_empty() {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            if let Some(last) = material_ranges.last_mut() {
+                last.indices.end = indices.len();
+            }
+            material_ranges.push(MaterialRange {
+                material: name.into(),
+                indices: indices.len()..indices.len(),
+            });
+        }
+        "g" | "o" => {
+            let name = match rest.trim() {
+                "" if line_type == "g" => "default",
+                "" => return Err(error!(InvalidParameterNumber, rest)),
+                name => name,
+            };
+            let (object, group) = match (line_type, submeshes.last()) {
+                ("o", _) => (name.into(), "default".into()),
+                (_, Some(last)) => (last.object.clone(), name.into()),
+                (_, None) => (String::new(), name.into()),
+            };
+            if let Some(last) = submeshes.last_mut() {
+                last.indices.end = indices.len();
+            }
+            submeshes.push(Submesh {
+                object,
+                group,
+                indices: indices.len()..indices.len(),
+            });
+        }
+        "s" => {
+            let args: Vec<_> = rest.split_whitespace().collect();
+            if args.len() != 1 {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            *smoothing_group = match args[0] {
+                "off" => 0,
+                // a group of its own, apart from the numbered ones
+                "on" => u32::MAX,
+                group => group.parse::<u32>().map_err(|_| error!(InvalidParameter(0), group))?,
+            };
+        }
+        "vp" => {
+            // Parameter space vertices are only used by trimming curves, which are not supported
+            let args: Vec<_> = rest.split_whitespace().collect();
+            if !(1..=3).contains(&args.len()) {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            if let Some(bad) = args.iter().position(|s| s.parse::<f32>().is_err()) {
+                return Err(error!(InvalidParameter(bad), args[bad]));
+            }
+        } // vp u [v] [w]
+        "cstype" => {
+            let args: Vec<_> = rest.split_whitespace().collect();
+            let (rational, basis) = match args.as_slice() {
+                ["rat", basis] => (true, *basis),
+                [basis] => (false, *basis),
+                _ => return Err(error!(InvalidParameterNumber, rest)),
+            };
+            *curve_type = None;
+            let basis = match basis {
+                "bezier" => Basis::Bezier,
+                "bspline" => Basis::BSpline,
+                "bmatrix" | "cardinal" | "taylor" => {
+                    return Err(error!(Unsupported(format!("cstype {basis}")), basis));
+                }
+                _ => return Err(error!(InvalidParameter(args.len() - 1), basis)),
+            };
+            *curve_type = Some(CurveType { rational, basis });
+        } // cstype [rat] type
+        "deg" => {
             let args: Vec<_> = rest
                 .split_whitespace()
                 .enumerate()
-                .map(|(i,s)| s.parse::<f32>().map_err(|_| error!(InvalidParameter(i))))
-                .collect::<Result<_,_>>()?;
-
-            let mut iter = args.iter().cloned();
-            match args.len() {
-                3 => positions_color.push(VertexData {
-                    position: Vector3::from_iterator(&mut iter),
-                    color: None,
-                }), // x y z
-                4 => {
-                    // x y z w
-                    positions_color.push(VertexData {
-                        position: Vector3::from_iterator(&mut iter),
-                        color: None,
-                    }); // x y z w
-                    eprintln!("{index}: w component ignored");
+                .map(|(i, s)| s.parse::<usize>().map_err(|_| error!(InvalidParameter(i), s)))
+                .collect::<Result<_, _>>()?;
+            *degree = match args.as_slice() {
+                [u] => (*u, 0),
+                [u, v] => (*u, *v),
+                _ => return Err(error!(InvalidParameterNumber, rest)),
+            };
+        } // deg degu [degv]
+        "step" | "ctech" | "stech" => {
+            // step is only used by the basis matrix and cardinal types, and the tessellation
+            // resolution comes from the options
+        }
+        "curv" | "curv2" | "surf" => {
+            if element.is_some() {
+                return Err(error!(MissingStatement("end"), line_type));
+            }
+            let Some(element_curve_type) = *curve_type else {
+                return Err(error!(MissingStatement("cstype"), line_type));
+            };
+            let tokens: Vec<_> = rest.split_whitespace().collect();
+            let ranges = if line_type == "surf" { 4 } else { 2 };
+            if line_type == "curv2" {
+                // Kept so its parm and end statements are accepted
+                *element = Some(Element {
+                    kind: ElementKind::Ignored,
+                    curve_type: element_curve_type,
+                    degree: *degree,
+                    control_points: Vec::new(),
+                    parm_u: None,
+                    parm_v: None,
+                });
+                return Err(error!(Unsupported(line_type.into()), line_type));
+            }
+            if tokens.len() < ranges + 2 {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            let range: Vec<_> = tokens[..ranges]
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    s.parse::<f32>()
+                        .ok()
+                        .filter(|p| p.is_finite())
+                        .ok_or_else(|| error!(InvalidParameter(i), s))
+                })
+                .collect::<Result<_, _>>()?;
+            // the surface would be a line, whose normals and texture coordinates divide by zero
+            if let [s0, s1, t0, t1] = *range.as_slice() {
+                if s0 == s1 {
+                    return Err(error!(InvalidParameter(1), tokens[1]));
+                }
+                if t0 == t1 {
+                    return Err(error!(InvalidParameter(3), tokens[3]));
                 }
-                6 => positions_color.push(VertexData {
-                    position: Vector3::from_iterator(&mut iter),
-                    color: Some(Vector3::from_iterator(&mut iter)),
-                }), // x y z r g b
-                _ => return Err(error!(InvalidParameterNumber)),
             }
-        }
-        "vt" => {
-            let args: Vec<_> = rest
-                .split_whitespace()
+            let control_points = tokens[ranges..]
+                .iter()
                 .enumerate()
-                .map(|(i,s)| s.parse::<f32>().map_err(|_| error!(InvalidParameter(i))))
-                .collect::<Result<_,_>>()?;
-            let mut iter = args.iter().cloned();
-            match args.len() {
-                1|2 => texture_coords.push((iter.next().unwrap(), iter.next().unwrap_or(0.0))),
-                3 => {
-                    texture_coords.push((iter.next().unwrap(), iter.next().unwrap()));
-                    eprintln!("{index}:Texture w coordinate ignored");
+                .map(|(i, s)| {
+                    let raw = FaceInfoRaw::parse(s.as_bytes())
+                        .filter(|raw| line_type == "surf" || (raw.texture, raw.normal) == (None, None))
+                        .ok_or_else(|| error!(InvalidParameter(i + ranges), s))?;
+                    raw.get_indices(positions_color.len(), texture_coords.len(), normals.len(), 0)
+                        .map_err(|e| error!(e, s))
+                })
+                .collect::<Result<_, _>>()?;
+            let kind = match *range.as_slice() {
+                [start, end] => ElementKind::Curve { start, end },
+                [s0, s1, t0, t1] => ElementKind::Surface {
+                    s: (s0, s1),
+                    t: (t0, t1),
                 },
-                _ => return Err(error!(InvalidParameterNumber)),
+                _ => unreachable!(),
+            };
+            *element = Some(Element {
+                kind,
+                curve_type: element_curve_type,
+                degree: *degree,
+                control_points,
+                parm_u: None,
+                parm_v: None,
+            });
+        } // curv u0 u1 v1 v2 ... / surf s0 s1 t0 t1 v1/vt1/vn1 ...
+        "parm" => {
+            let Some(element) = element else {
+                return Err(error!(MissingStatement("curv' or 'surf"), line_type));
+            };
+            let tokens: Vec<_> = rest.split_whitespace().collect();
+            if tokens.len() < 3 {
+                return Err(error!(InvalidParameterNumber, rest));
             }
-
-        }, // vt u [v, w]
-        "vn" => {
-            let args: Vec<_> = rest
-                .split_whitespace()
+            let parameters: Vec<f32> = tokens[1..]
+                .iter()
                 .enumerate()
-                .map(|(i,s)| s.parse::<f32>().map_err(|_| error!(InvalidParameter(i))))
-                .collect::<Result<_,_>>()?;
-            if args.len() != 3 {
-                return Err(error!(InvalidParameterNumber));
+                .map(|(i, s)| {
+                    s.parse::<f32>()
+                        .ok()
+                        .filter(|p| p.is_finite())
+                        .ok_or_else(|| error!(InvalidParameter(i + 1), s))
+                })
+                .collect::<Result<_, _>>()?;
+            // an empty parameter range has no curve to evaluate
+            let last = tokens.len() - 1;
+            if parameters[last - 1] <= parameters[0] {
+                return Err(error!(InvalidParameter(last), tokens[last]));
             }
-            let mut iter = args.iter().cloned();
-            normals.push(Vector3::from_iterator(&mut iter));
-        }, //vn x y z  (may not be unit)
-        "f" => {
-            let args : Vec<_> = rest
-                .split_whitespace()
-                .enumerate().map (
-                |(i,s)|
-                    FaceInfoRaw::parse(s)
-                    .ok_or(error!(InvalidParameter(i)))
-                ).collect::<Result<_,_>>()?;
-            if let Some(bad) = args.iter().enumerate().find(|(_i,f)| !f.same_shape(&args[0])) {
-                return Err(error!(InvalidParameter(bad.0)));
-            }
-
-            let mut args: Vec<_> = args.iter().map(|f|f.get_indices(positions_color.len(), texture_coords.len(), normals.len(), indices.last().map_or(0, |f| f.face_no + 1)))
-            .collect::<Result<_,_>>().map_err(|e| error!(e))?;
-            if args.len() < 3 {
-                return Err(error!(InvalidParameterNumber));
+            match tokens[0] {
+                "u" => element.parm_u = Some(parameters),
+                "v" => element.parm_v = Some(parameters),
+                direction => return Err(error!(InvalidParameter(0), direction)),
             }
-            if args.len() > 3 {
-                args = fan_triangulation(args);
+        } // parm u|v p1 p2 ...
+        "trim" | "hole" | "scrv" | "sp" => {
+            if element.is_none() {
+                return Err(error!(MissingStatement("curv' or 'surf"), line_type));
             }
-            indices.extend(args);
-        }
-        ,  // f v1/vt1/vn1 v2/vt2/vn2 v3/vt3/vn3
-        "g" | "o" | "mtllib" | "usemtl" => {
-            return Err(error!(Unsupported(line_type.into())));
+            return Err(error!(Unsupported(line_type.into()), line_type));
         }
-        "s" => {
-            let args: Vec<_> = rest.split_whitespace().collect();
-            if args.len() > 1 {
-                return Err(error!(InvalidParameterNumber));
-            }
-            match args[0] {
-                "off" => (),
-                _ => return Err(error!(Unsupported("s on".into()))),
+        "end" => {
+            let Some(element) = element.take() else {
+                return Err(error!(MissingStatement("curv' or 'surf"), line_type));
+            };
+            let resolution = options.freeform_resolution.max(1);
+            let tessellation = element
+                .tessellate(positions_color, texture_coords, resolution)
+                .map_err(|e| error!(e, line_type))?;
+            let face_no = face_smoothing.len();
+            match tessellation {
+                Tessellation::Polyline(points) => {
+                    let start = line_indices.len();
+                    line_indices.extend(vertices.len() as u32..(vertices.len() + points.len()) as u32);
+                    vertices.extend((0..points.len()).map(|i| FaceInfo {
+                        vertex: (generated_positions.len() + i) as u32,
+                        texture: None,
+                        normal: None,
+                        face_no,
+                        generated: true,
+                    }));
+                    generated_positions.extend(points);
+                    line_strips.push(start..line_indices.len());
+                    face_smoothing.push(0);
+                    face_normals.push(Vector3::zero());
+                }
+                Tessellation::Grid {
+                    positions,
+                    normals,
+                    texture_coords,
+                } => {
+                    let first = vertices.len();
+                    vertices.extend((0..positions.len()).map(|k| FaceInfo {
+                        vertex: (generated_positions.len() + k) as u32,
+                        texture: Some((generated_texture_coords.len() + k) as u32),
+                        normal: Some((generated_normals.len() + k) as u32),
+                        face_no,
+                        generated: true,
+                    }));
+                    let corner = |i: usize, j: usize| (first + j * (resolution + 1) + i) as u32;
+                    for j in 0..resolution {
+                        for i in 0..resolution {
+                            let quad = [corner(i, j), corner(i + 1, j), corner(i + 1, j + 1), corner(i, j + 1)];
+                            indices.extend([0, 1, 2, 0, 2, 3].map(|c| quad[c]));
+                        }
+                    }
+                    generated_positions.extend(positions);
+                    generated_normals.extend(normals);
+                    generated_texture_coords.extend(texture_coords);
+                    face_smoothing.push(0);
+                    face_normals.push(Vector3::zero());
+                }
+                Tessellation::Nothing => {}
             }
-
         }
-        "p" | "l" | "curv" | "curv2D" | "surf" | "mg" | "parm" | "trim" | "hole"
-        | "scrv" | "sp" | "end" | "con" => return Err(error!(Unsupported(line_type.into()))),
-        _ => return Err(error!(InvalidEntry(line_type.into()))),
+        "mg" | "bmat" | "con" => return Err(error!(Unsupported(line_type.into()), line_type)),
+        _ => return Err(error!(InvalidEntry(line_type.into()), line_type)),
     };
     Ok(())
 }
 
-pub fn parse_obj(reader: impl BufRead, ignore_unimplemented: bool) -> Result<Model, ParseError> {
-    let mut positions_color: Vec<VertexData> = Vec::new();
-    let mut normals: Vec<Vector3<f32>> = Vec::new();
-    let mut texture_coords: Vec<(f32, f32)> = Vec::new();
-    let mut indices: Vec<FaceInfo> = Vec::new();
+/// Keeps `result` as a diagnostic when the options allow it
+fn handle_result(
+    result: Result<(), ParseError>,
+    diagnostics: &mut Vec<Diagnostic>,
+    options: &ParseOptions,
+) -> Result<(), ParseError> {
+    if let Err(err) = result {
+        let severity = match &err.err_type {
+            ErrorType::Unsupported(_) if options.ignore_unimplemented => Severity::Warning,
+            _ if options.keep_going => Severity::Error,
+            _ => return Err(err),
+        };
+        diagnostics.push(Diagnostic {
+            severity,
+            error: err,
+        });
+    }
+    Ok(())
+}
+
+fn io_error(line_no: usize, err: IOError) -> ParseError {
+    ParseError {
+        line: None,
+        line_no,
+        columns: None,
+        err_type: ErrorType::IOError(err),
+    }
+}
 
-    //file parsing
-    for (index, line) in reader.lines().enumerate() {
-        let line = line.map_err(|err| ParseError {
-            line: None,
-            line_no: index,
-            err_type: ErrorType::IOError(err),
-        })?;
-        let res = parse_one_line(
-            index,
-            line,
-            &mut positions_color,
-            &mut normals,
-            &mut texture_coords,
-            &mut indices,
-        );
-        if let Some(err) = res.err() {
-            match &err.err_type {
-                ErrorType::Unsupported(_) => {
-                    if ignore_unimplemented {
-                        eprintln!("{err}");
-                    } else {
-                        return Err(err);
-                    }
-                }
-                _ => return Err(err),
+/// Reads the next statement into `buffer`, without its line break
+/// A backslash at the end of a line continues the statement on the next one
+/// Returns false at the end of the file
+fn read_statement(
+    reader: &mut impl BufRead,
+    buffer: &mut Vec<u8>,
+    line_no: &mut usize,
+) -> Result<bool, ParseError> {
+    buffer.clear();
+    let mut read_any = false;
+    loop {
+        let read = reader
+            .read_until(b'\n', buffer)
+            .map_err(|err| io_error(*line_no + 1, err))?;
+        if read == 0 {
+            return Ok(read_any);
+        }
+        read_any = true;
+        *line_no += 1;
+        if buffer.last() == Some(&b'\n') {
+            buffer.pop();
+            if buffer.last() == Some(&b'\r') {
+                buffer.pop();
             }
         }
+        match buffer.last_mut() {
+            Some(last) if *last == b'\\' => *last = b' ',
+            _ => return Ok(true),
+        }
     }
-    //normalization
-    let min_coord = positions_color
-        .iter()
-        .map(|p_c| p_c.position)
-        .reduce(|a, b| Vector3 {
-            x: a.x.min(b.x),
-            y: a.y.min(b.y),
-            z: a.z.min(b.z),
-        })
-        .unwrap_or(Vector3::zero());
-    let max_coord = positions_color
-        .iter()
-        .map(|p_c| p_c.position)
-        .reduce(|a, b| Vector3 {
-            x: a.x.max(b.x),
-            y: a.y.max(b.y),
-            z: a.z.max(b.z),
-        })
-        .unwrap_or(Vector3::zero());
-    let middle_coord = (min_coord + max_coord) / 2.0;
-    let mid_2d = Vector2 {
-        x: middle_coord.x,
-        y: middle_coord.z,
+}
+
+/// Parses a whole obj file
+/// The file is read one statement at a time, and every vertex of the model is created as soon
+/// as its face is read, so memory use only depends on the size of the model
+/// Warnings, and skipped lines when `options.keep_going` is set, are returned along the model
+pub fn parse_obj(
+    mut reader: impl BufRead,
+    options: &ParseOptions,
+) -> Result<(Model, Vec<Diagnostic>), ParseError> {
+    if options.threads > 1 {
+        return parallel::parse_obj_parallel(reader, options);
+    }
+    let mut state = ParseState::default();
+    let mut buffer = Vec::new();
+    let mut faces = Vec::new();
+    let mut line_no = 0;
+    loop {
+        let first_line = line_no + 1;
+        if !read_statement(&mut reader, &mut buffer, &mut line_no)? {
+            break;
+        }
+        faces.clear();
+        let result = lexer::lex_line(first_line, &buffer, &mut faces).and_then(|statement| {
+            apply_statement(first_line, &buffer, statement, &faces, &mut state, options)
+        });
+        handle_result(result, &mut state.diagnostics, options)?;
+    }
+    finish(state, options, line_no)
+}
+
+/// Builds the model once every line has been applied
+fn finish(
+    mut state: ParseState,
+    options: &ParseOptions,
+    line_count: usize,
+) -> Result<(Model, Vec<Diagnostic>), ParseError> {
+    if state.element.is_some() {
+        let err = ParseError {
+            line: None,
+            line_no: line_count,
+            columns: None,
+            err_type: ErrorType::MissingStatement("end"),
+        };
+        if !options.keep_going {
+            return Err(err);
+        }
+        state.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            error: err,
+        });
+    }
+    let ParseState {
+        mut positions_color,
+        mut normals,
+        mut texture_coords,
+        vertices,
+        mut indices,
+        material_libs,
+        mut material_ranges,
+        mut submeshes,
+        polygons,
+        face_smoothing,
+        face_normals,
+        smooth_normals,
+        line_indices,
+        line_strips,
+        point_indices,
+        generated_positions,
+        generated_normals,
+        generated_texture_coords,
+        diagnostics,
+        ..
+    } = state;
+    // the generated vertices go after the ones of the file
+    let has_texture_coords = !texture_coords.is_empty();
+    let offsets = [positions_color.len(), texture_coords.len(), normals.len()].map(|len| len as u32);
+    let rebase = |info: FaceInfo| {
+        if !info.generated {
+            return info;
+        }
+        FaceInfo {
+            vertex: info.vertex + offsets[0],
+            texture: info.texture.map(|i| i + offsets[1]),
+            normal: info.normal.map(|i| i + offsets[2]),
+            ..info
+        }
     };
-    let largest_axis = (min_coord.x - max_coord.x).abs().max(
-        (min_coord.y - max_coord.y)
-            .abs()
-            .max((min_coord.z - max_coord.z).abs()),
+    positions_color.extend(generated_positions.into_iter().map(|position| VertexData {
+        position,
+        color: None,
+        weight: 1.0,
+    }));
+    texture_coords.extend(generated_texture_coords);
+    normals.extend(generated_normals);
+    if let Some(last) = material_ranges.last_mut() {
+        last.indices.end = indices.len();
+    }
+    material_ranges.retain(|range| !range.indices.is_empty());
+    if let Some(last) = submeshes.last_mut() {
+        last.indices.end = indices.len();
+    }
+    let first_named = submeshes.first().map_or(indices.len(), |s| s.indices.start);
+    submeshes.insert(
+        0,
+        Submesh {
+            object: String::new(),
+            group: "default".into(),
+            indices: 0..first_named,
+        },
     );
-    let mut verts_index: HashMap<FaceInfo, u32> = HashMap::with_capacity(indices.len());
-    let mut fixed_indices: Vec<u32> = Vec::with_capacity(indices.len());
-    let mut fixed_verts: Vec<Vertex> = Vec::with_capacity(positions_color.len());
-    let total_indices = indices.len();
-    for indices in indices {
-        if let Some(vert_index) = verts_index.get(&indices) {
-            fixed_indices.push(*vert_index);
-        } else {
-            verts_index.insert(indices, fixed_verts.len() as u32);
-            fixed_indices.push(fixed_verts.len() as u32);
+    submeshes.retain(|submesh| !submesh.indices.is_empty());
+    let bounds = BoundingBox::from_points(positions_color.iter().map(|p_c| p_c.position));
+    //normals of faces without vn
+    //faces outside of any smoothing group are flat, the others share the area weighted normal
+    //of the faces of their group around each position
+    let has_normal: Vec<bool> = vertices.iter().map(|info| info.normal.is_some()).collect();
+    let mut has_texture: Vec<bool> = vertices.iter().map(|info| info.texture.is_some()).collect();
+    let mut vertex_faces: Vec<Option<u32>> = vertices
+        .iter()
+        .map(|info| Some(info.face_no as u32))
+        .collect();
+    let mut file_colors: Vec<Option<Vector3<f32>>> = vertices
+        .iter()
+        .map(|info| positions_color[rebase(*info).vertex as usize].color)
+        .collect();
+    let mut vertices: Vec<Vertex> = vertices
+        .into_iter()
+        .map(|indices| {
+            let indices = rebase(indices);
             let pos_index = indices.vertex;
             let text_index = indices.texture;
             let norm_index = indices.normal;
             let pos_color = &positions_color[pos_index as usize];
             let position = pos_color.position;
-            let pos_2d = Vector2 {
-                x: position.x,
-                y: position.z,
+            let normal = norm_index
+                .map(|i| normals[i as usize])
+                .unwrap_or_else(|| match face_smoothing[indices.face_no] {
+                    0 => face_normals[indices.face_no],
+                    group => smooth_normals[&(pos_index, group)],
+                });
+            Vertex {
+                position,
+                color: Vector3::zero(),
+                texture_coordinates: text_index.map_or((0.0, 0.0), |i| texture_coords[i as usize]),
+                normal: if normal.norm2() > 0.0 {
+                    normal.normalized()
+                } else {
+                    normal
+                },
+                tangent: Vector4::zero(),
+            }
+        })
+        .collect();
+    if let Some(normal_options) = &options.normals {
+        let sources = mesh::smooth_normals(&mut vertices, &mut indices, normal_options, |i| {
+            !has_normal[i as usize]
+        });
+        has_texture = mesh::with_copies(&has_texture, &sources);
+        vertex_faces = mesh::with_copies(&vertex_faces, &sources);
+        file_colors = mesh::with_copies(&file_colors, &sources);
+    }
+    let mut model = Model {
+        vertices: vertices.into(),
+        indices: indices.into(),
+        material_libs,
+        materials: Vec::new(),
+        material_ranges,
+        submeshes,
+        polygons,
+        line_indices: line_indices.into(),
+        line_strips,
+        point_indices: point_indices.into(),
+        attributes: Vec::new(),
+        file_colors: match file_colors.iter().any(Option::is_some) {
+            true => file_colors.into(),
+            false => Box::new([]),
+        },
+        vertex_faces: vertex_faces.into(),
+        face_count: face_smoothing.len(),
+        file_normals: has_normal.contains(&true),
+        file_texture_coordinates: has_texture.contains(&true),
+        bounds,
+    };
+    // surfaces bring their own texture coordinates
+    if !has_texture_coords {
+        model.project_uvs(&options.uv, |i| !has_texture[i as usize]);
+    }
+    if options.tangents {
+        model.compute_tangents();
+    }
+    model.set_colors(ColorScheme::default());
+    Ok((model, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<(Model, Vec<Diagnostic>), ParseError> {
+        parse_obj(source.as_bytes(), &ParseOptions::default())
+    }
+
+    #[test]
+    fn smoothing_on_and_off() {
+        let faces = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 3 4\n";
+        for (statement, smooth) in [
+            ("s on", true),
+            ("s 1", true),
+            ("s off", false),
+            ("s 0", false),
+        ] {
+            let (model, diagnostics) = parse(&format!("{statement}\n{faces}")).unwrap();
+            assert!(diagnostics.is_empty());
+            // the faces share the vertices of their common edge only when they are smoothed
+            let expected = if smooth { 4 } else { 6 };
+            assert_eq!(model.vertices.len(), expected, "{statement}");
+        }
+        assert!(parse(&format!("s maybe\n{faces}")).is_err());
+    }
+
+    #[test]
+    fn faces_share_vertices() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\n\
+            f 1/1/1 2/1/1 3/1/1\nf 1/1/1 3/1/1 4/1/1\nf 1//1 3//1 4//1\nf 1 2 3\n";
+        let (model, _) = parse(source).unwrap();
+        // 4 corners with a texture, 3 without, and the flat face without vn
+        assert_eq!(model.vertices.len(), 10);
+        assert_eq!(&model.indices[..6], &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(model.vertex_faces[0], Some(0));
+    }
+
+    #[test]
+    fn degenerate_surface_ranges() {
+        let surface = |range: &str, parm_u: &str| {
+            format!(
+                "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 1\ncstype bezier\ndeg 1 1\n\
+                surf {range} 1 2 3 4\nparm u {parm_u}\nparm v 0 1\nend\n"
+            )
+        };
+        let (model, _) = parse(&surface("0 1 0 1", "0 1")).unwrap();
+        assert!(!model.indices.is_empty());
+        assert!(model.vertices.iter().all(|v| {
+            let (s, t) = v.texture_coordinates;
+            v.normal.norm2().is_finite() && s.is_finite() && t.is_finite()
+        }));
+        for (range, parm_u) in [("0 0 0 1", "0 1"), ("0 1 1 1", "0 1"), ("0 1 0 1", "1 1")] {
+            let source = surface(range, parm_u);
+            assert!(parse(&source).is_err(), "{range} / {parm_u}");
+            let options = ParseOptions {
+                keep_going: true,
+                ..Default::default()
             };
-            let k = Vector2 { x: 0.0, y: 1.0 };
-
-            let angle = (pos_2d.dot(k) / pos_2d.norm()).acos();
-            let distance_2d = (pos_2d - mid_2d).norm();
-            fixed_verts.push(Vertex {
-                position: (position - middle_coord) / largest_axis,
-                color: Vector3{x:1.0,y:1.0,z:1.0} * ((0.2 * indices.face_no as f32 % 1.0 / 2.5) + (indices.face_no as f32 / total_indices as f32 / 2.0)),
-                texture_coordinates: text_index.map(|i| texture_coords[i as usize]).unwrap_or(
-                    if texture_coords.is_empty() {
-                        (angle + distance_2d, position.y - min_coord.y)
-                    } else {
-                        (0.0, 0.0)
-                    },
-                ),
-                normal: norm_index.map(|i| normals[i as usize]).unwrap_or(
-                    position - middle_coord
-                ).normalized(),
-            });
+            let (model, diagnostics) = parse_obj(source.as_bytes(), &options).unwrap();
+            assert!(!diagnostics.is_empty());
+            assert!(model.vertices.iter().all(|v| v.normal.norm2().is_finite()));
+        }
+    }
+
+    #[test]
+    fn keep_going() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 x\nf 1 2 3\nf 1 2 \\\n9\n\
+            v 1 1 0\nf 2 4 3\n";
+        let err = parse(source).err().unwrap();
+        assert_eq!(err.line_no, 4);
+        for threads in [1, 2] {
+            let options = ParseOptions {
+                keep_going: true,
+                threads,
+                ..Default::default()
+            };
+            let (model, diagnostics) = parse_obj(source.as_bytes(), &options).unwrap();
+            // the continued statement is reported on its first line
+            let lines: Vec<_> = (diagnostics.iter())
+                .map(|d| (d.severity, d.error.line_no))
+                .collect();
+            assert_eq!(lines, [(Severity::Error, 4), (Severity::Error, 6)]);
+            // the lines after them are still read
+            assert_eq!(model.indices.len(), 6, "{threads} threads");
         }
     }
-    Ok(Model {
-        vertices: fixed_verts.into(),
-        indices: fixed_indices.into(),
-    })
 }
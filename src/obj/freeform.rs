@@ -0,0 +1,269 @@
+use super::{ErrorType, FaceInfo, VertexData};
+use crate::math::vector::{Vector3, Vector4};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Basis {
+    Bezier,
+    BSpline,
+}
+
+/// Set by `cstype [rat] type`
+#[derive(Debug, Clone, Copy)]
+pub struct CurveType {
+    pub rational: bool,
+    pub basis: Basis,
+}
+
+pub(super) enum ElementKind {
+    /// `curv u0 u1 v1 v2 ...`
+    Curve { start: f32, end: f32 },
+    /// `surf s0 s1 t0 t1 v1/vt1/vn1 ...`
+    Surface {
+        s: (f32, f32),
+        t: (f32, f32),
+    },
+    /// `curv2`, only used for trimming, which is not supported
+    Ignored,
+}
+
+/// A free-form element, from its curv/surf statement to its `end`
+pub(super) struct Element {
+    pub kind: ElementKind,
+    pub curve_type: CurveType,
+    pub degree: (usize, usize),
+    pub control_points: Vec<FaceInfo>,
+    pub parm_u: Option<Vec<f32>>,
+    pub parm_v: Option<Vec<f32>>,
+}
+
+pub(super) enum Tessellation {
+    Polyline(Vec<Vector3<f32>>),
+    /// `(resolution + 1)²` vertices, u varying fastest
+    Grid {
+        positions: Vec<Vector3<f32>>,
+        normals: Vec<Vector3<f32>>,
+        texture_coords: Vec<(f32, f32)>,
+    },
+    Nothing,
+}
+
+/// Parameters used when the file has no parm statement: uniform, and clamped for B-splines
+fn default_parameters(basis: Basis, degree: usize, count: usize) -> Vec<f32> {
+    match basis {
+        Basis::Bezier => (0..=(count - 1) / degree).map(|i| i as f32).collect(),
+        Basis::BSpline => (0..count + degree + 1)
+            .map(|i| i.saturating_sub(degree).min(count - degree) as f32)
+            .collect(),
+    }
+}
+
+/// Number of control points along one direction, given its parameters
+fn control_point_count(basis: Basis, degree: usize, parameters: &[f32]) -> Option<usize> {
+    match basis {
+        Basis::Bezier => Some(degree * parameters.len().checked_sub(1)? + 1),
+        Basis::BSpline => parameters.len().checked_sub(degree + 1),
+    }
+}
+
+fn valid_parameters(basis: Basis, degree: usize, count: usize, parameters: &[f32]) -> bool {
+    degree >= 1
+        && count > degree
+        && control_point_count(basis, degree, parameters) == Some(count)
+        && parameters.windows(2).all(|w| w[0] <= w[1])
+}
+
+fn lerp(a: Vector4<f32>, b: Vector4<f32>, t: f32) -> Vector4<f32> {
+    a * (1.0 - t) + b * t
+}
+
+/// Evaluates a curve with homogeneous control points at `u`
+fn evaluate(
+    basis: Basis,
+    degree: usize,
+    parameters: &[f32],
+    points: &[Vector4<f32>],
+    u: f32,
+) -> Vector4<f32> {
+    match basis {
+        Basis::Bezier => {
+            // Piecewise Bézier, segment i spans parameters[i]..parameters[i + 1]
+            let segments = parameters.len() - 1;
+            let segment = (0..segments)
+                .find(|&i| u < parameters[i + 1])
+                .unwrap_or(segments - 1);
+            let (start, end) = (parameters[segment], parameters[segment + 1]);
+            let t = if end > start {
+                ((u - start) / (end - start)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            // de Casteljau
+            let mut d = points[segment * degree..=segment * degree + degree].to_vec();
+            for r in 1..=degree {
+                for j in 0..=degree - r {
+                    d[j] = lerp(d[j], d[j + 1], t);
+                }
+            }
+            d[0]
+        }
+        Basis::BSpline => {
+            let count = points.len();
+            let u = u.clamp(parameters[degree], parameters[count]);
+            let span = (degree..count)
+                .rfind(|&k| parameters[k] <= u && parameters[k] < parameters[k + 1])
+                .unwrap_or(degree);
+            // de Boor
+            let mut d: Vec<_> = (0..=degree).map(|j| points[j + span - degree]).collect();
+            for r in 1..=degree {
+                for j in (r..=degree).rev() {
+                    let left = parameters[j + span - degree];
+                    let right = parameters[j + 1 + span - r];
+                    let alpha = if right > left {
+                        (u - left) / (right - left)
+                    } else {
+                        0.0
+                    };
+                    d[j] = lerp(d[j - 1], d[j], alpha);
+                }
+            }
+            d[degree]
+        }
+    }
+}
+
+fn project(p: Vector4<f32>) -> Vector3<f32> {
+    if p.w == 0.0 {
+        return Vector3 {
+            x: p.x,
+            y: p.y,
+            z: p.z,
+        };
+    }
+    Vector3 {
+        x: p.x / p.w,
+        y: p.y / p.w,
+        z: p.z / p.w,
+    }
+}
+
+fn sample(start: f32, end: f32, resolution: usize) -> impl Iterator<Item = f32> {
+    (0..=resolution).map(move |i| start + (end - start) * i as f32 / resolution as f32)
+}
+
+impl Element {
+    /// Homogeneous control points, weighted if the curve type is rational
+    fn homogeneous(&self, points: impl Iterator<Item = (Vector3<f32>, f32)>) -> Vec<Vector4<f32>> {
+        points
+            .map(|(p, w)| {
+                let w = if self.curve_type.rational { w } else { 1.0 };
+                Vector4 {
+                    x: p.x * w,
+                    y: p.y * w,
+                    z: p.z * w,
+                    w,
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluates the element on a regular grid of its parameter space
+    /// `resolution` is the number of segments along each parameter, at least 1
+    pub fn tessellate(
+        &self,
+        positions: &[VertexData],
+        texture_coords: &[(f32, f32)],
+        resolution: usize,
+    ) -> Result<Tessellation, ErrorType> {
+        let basis = self.curve_type.basis;
+        let points = self.homogeneous(self.control_points.iter().map(|cp| {
+            let vertex = &positions[cp.vertex as usize];
+            (vertex.position, vertex.weight)
+        }));
+        match self.kind {
+            ElementKind::Ignored => Ok(Tessellation::Nothing),
+            ElementKind::Curve { start, end } => {
+                let degree = self.degree.0;
+                let parameters = match &self.parm_u {
+                    Some(parm) => parm.clone(),
+                    None if degree >= 1 && points.len() > degree => {
+                        default_parameters(basis, degree, points.len())
+                    }
+                    None => return Err(ErrorType::InvalidParameterNumber),
+                };
+                if !valid_parameters(basis, degree, points.len(), &parameters) {
+                    return Err(ErrorType::InvalidParameterNumber);
+                }
+                Ok(Tessellation::Polyline(
+                    sample(start, end, resolution)
+                        .map(|u| project(evaluate(basis, degree, &parameters, &points, u)))
+                        .collect(),
+                ))
+            }
+            ElementKind::Surface { s, t } => {
+                let (Some(parm_u), Some(parm_v)) = (&self.parm_u, &self.parm_v) else {
+                    return Err(ErrorType::InvalidParameterNumber);
+                };
+                let (degree_u, degree_v) = self.degree;
+                let count_u = control_point_count(basis, degree_u, parm_u)
+                    .ok_or(ErrorType::InvalidParameterNumber)?;
+                let count_v = control_point_count(basis, degree_v, parm_v)
+                    .ok_or(ErrorType::InvalidParameterNumber)?;
+                if count_u * count_v != points.len()
+                    || !valid_parameters(basis, degree_u, count_u, parm_u)
+                    || !valid_parameters(basis, degree_v, count_v, parm_v)
+                {
+                    return Err(ErrorType::InvalidParameterNumber);
+                }
+                let surface_at = |points: &[Vector4<f32>], u: f32, v: f32| {
+                    let column: Vec<_> = points
+                        .chunks_exact(count_u)
+                        .map(|row| evaluate(basis, degree_u, parm_u, row, u))
+                        .collect();
+                    evaluate(basis, degree_v, parm_v, &column, v)
+                };
+                // Texture vertices of the control points define a texture surface
+                // Without them the parameters are used
+                let texture_points = self
+                    .control_points
+                    .iter()
+                    .map(|cp| cp.texture)
+                    .collect::<Option<Vec<_>>>()
+                    .map(|texture| {
+                        self.homogeneous(texture.iter().zip(&self.control_points).map(|(&i, cp)| {
+                            let (u, v) = texture_coords[i as usize];
+                            let w = positions[cp.vertex as usize].weight;
+                            (Vector3 { x: u, y: v, z: 0.0 }, w)
+                        }))
+                    });
+
+                let (mut positions, mut normals, mut texture) = (Vec::new(), Vec::new(), Vec::new());
+                let h_u = (s.1 - s.0) * 1e-3;
+                let h_v = (t.1 - t.0) * 1e-3;
+                for v in sample(t.0, t.1, resolution) {
+                    for u in sample(s.0, s.1, resolution) {
+                        let p = project(surface_at(&points, u, v));
+                        // Finite differences, backwards at the end of the ranges
+                        let h_u = if u + h_u > s.1.max(s.0) { -h_u } else { h_u };
+                        let h_v = if v + h_v > t.1.max(t.0) { -h_v } else { h_v };
+                        let du = (project(surface_at(&points, u + h_u, v)) - p) / h_u;
+                        let dv = (project(surface_at(&points, u, v + h_v)) - p) / h_v;
+                        positions.push(p);
+                        normals.push(du.cross(&dv));
+                        texture.push(match &texture_points {
+                            Some(texture_points) => {
+                                let uv = project(surface_at(texture_points, u, v));
+                                (uv.x, uv.y)
+                            }
+                            None => ((u - s.0) / (s.1 - s.0), (v - t.0) / (t.1 - t.0)),
+                        });
+                    }
+                }
+                Ok(Tessellation::Grid {
+                    positions,
+                    normals,
+                    texture_coords: texture,
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use super::{ErrorType, FaceInfoRaw, ParseError, VertexData, columns, line_error};
+use crate::math::vector::Vector3;
+use std::ops::Range;
+
+/// One line of an obj file, with its numbers already parsed
+/// Lexing a line does not depend on the previous ones, so lines can be lexed in any order
+pub(super) enum Statement<'a> {
+    /// Blank line or comment
+    Empty,
+    Vertex(VertexData),
+    TextureCoords {
+        uv: (f32, f32),
+        /// The w token, which is ignored
+        ignored_w: Option<&'a [u8]>,
+    },
+    Normal(Vector3<f32>),
+    /// `f`, `l` and `p`, as a range of the lexed face vertices
+    Face(Range<usize>),
+    Line(Range<usize>),
+    Points(Range<usize>),
+    /// Everything else, rare enough to be handled as text
+    Other { keyword: &'a [u8], rest: &'a [u8] },
+}
+
+fn is_whitespace(b: &u8) -> bool {
+    b.is_ascii_whitespace()
+}
+
+/// Whitespace separated tokens of a line
+pub(super) fn tokens(line: &[u8]) -> impl Iterator<Item = &[u8]> {
+    line.split(is_whitespace).filter(|token| !token.is_empty())
+}
+
+const POWERS_OF_TEN: [f32; 11] = [1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10];
+
+/// Parses a float the same way `str::parse` does
+/// Plain decimals with up to 7 significant digits, which is what obj exporters write, are
+/// converted with a single exact division, everything else goes through the standard library
+pub(super) fn parse_float(token: &[u8]) -> Option<f32> {
+    let (negative, digits) = match token.first()? {
+        b'-' => (true, &token[1..]),
+        b'+' => (false, &token[1..]),
+        _ => (false, token),
+    };
+    let mut mantissa: u32 = 0;
+    let mut decimals = 0;
+    let mut seen_digit = false;
+    let mut seen_dot = false;
+    for &b in digits {
+        match b {
+            b'0'..=b'9' => {
+                mantissa = match mantissa.checked_mul(10) {
+                    Some(m) if m < 1 << 24 => m + (b - b'0') as u32,
+                    _ => return parse_float_slow(token),
+                };
+                seen_digit = true;
+                decimals += seen_dot as usize;
+            }
+            b'.' if !seen_dot => seen_dot = true,
+            _ => return parse_float_slow(token),
+        }
+    }
+    if !seen_digit || mantissa >= 1 << 24 || decimals >= POWERS_OF_TEN.len() {
+        return parse_float_slow(token);
+    }
+    // both are exact in f32, so the division is correctly rounded
+    let value = mantissa as f32 / POWERS_OF_TEN[decimals];
+    Some(if negative { -value } else { value })
+}
+
+fn parse_float_slow(token: &[u8]) -> Option<f32> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+/// Parses an integer the same way `str::parse` does
+pub(super) fn parse_int(token: &[u8]) -> Option<isize> {
+    let (negative, digits) = match token.first()? {
+        b'-' => (true, &token[1..]),
+        b'+' => (false, &token[1..]),
+        _ => (false, token),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: isize = 0;
+    for &b in digits {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        let digit = (b - b'0') as isize;
+        value = value.checked_mul(10)?;
+        value = if negative {
+            value.checked_sub(digit)?
+        } else {
+            value.checked_add(digit)?
+        };
+    }
+    Some(value)
+}
+
+/// Lexes one line, without its line break
+/// The vertices of `f`, `l` and `p` statements are appended to `faces`, with the columns of
+/// their token
+pub(super) fn lex_line<'a>(
+    line_no: usize,
+    line: &'a [u8],
+    faces: &mut Vec<(FaceInfoRaw, Range<usize>)>,
+) -> Result<Statement<'a>, ParseError> {
+    use ErrorType::*;
+    macro_rules! error {
+        ($err:expr, $token:expr) => {
+            line_error(line_no, line, $token, $err)
+        };
+    }
+    let mut words = tokens(line);
+    let Some(keyword) = words.next() else {
+        return Ok(Statement::Empty);
+    };
+    if keyword.starts_with(b"#") {
+        return Ok(Statement::Empty);
+    }
+    let keyword_end = keyword.as_ptr() as usize - line.as_ptr() as usize + keyword.len();
+    // statements like `end` have no arguments
+    let rest = &line[(keyword_end + 1).min(line.len())..];
+
+    // parses up to N floats, and returns how many there were
+    fn floats<'a, const N: usize>(
+        words: impl Iterator<Item = &'a [u8]>,
+        error: impl Fn(usize, &[u8]) -> ParseError,
+    ) -> Result<([f32; N], usize), ParseError> {
+        let mut values = [0.0; N];
+        let mut count = 0;
+        for (i, word) in words.enumerate() {
+            let value = parse_float(word).ok_or_else(|| error(i, word))?;
+            if i < N {
+                values[i] = value;
+            }
+            count += 1;
+        }
+        Ok((values, count))
+    }
+    let invalid_parameter = |i, word: &[u8]| error!(InvalidParameter(i), word);
+
+    match keyword {
+        b"v" => {
+            let ([x, y, z, a, b, c, ..], count) = floats::<7>(words, invalid_parameter)?;
+            let position = Vector3 { x, y, z };
+            Ok(Statement::Vertex(match count {
+                // x y z
+                3 => VertexData {
+                    position,
+                    color: None,
+                    weight: 1.0,
+                },
+                // x y z w
+                4 => VertexData {
+                    position,
+                    color: None,
+                    weight: a,
+                },
+                // x y z r g b
+                6 => VertexData {
+                    position,
+                    color: Some(Vector3 { x: a, y: b, z: c }),
+                    weight: 1.0,
+                },
+                _ => return Err(error!(InvalidParameterNumber, rest)),
+            }))
+        }
+        b"vt" => {
+            let ([u, v, _, ..], count) = floats::<4>(words, invalid_parameter)?;
+            match count {
+                1 | 2 => Ok(Statement::TextureCoords {
+                    uv: (u, v),
+                    ignored_w: None,
+                }),
+                3 => Ok(Statement::TextureCoords {
+                    uv: (u, v),
+                    ignored_w: tokens(rest).nth(2),
+                }),
+                _ => Err(error!(InvalidParameterNumber, rest)),
+            }
+        } // vt u [v, w]
+        b"vn" => {
+            let ([x, y, z, ..], count) = floats::<4>(words, invalid_parameter)?;
+            if count != 3 {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            Ok(Statement::Normal(Vector3 { x, y, z }))
+        } //vn x y z  (may not be unit)
+        b"f" | b"l" | b"p" => {
+            let start = faces.len();
+            for (i, word) in words.enumerate() {
+                let face = FaceInfoRaw::parse(word)
+                    .filter(|f| match keyword {
+                        b"f" => true,
+                        b"l" => f.normal.is_none(),
+                        _ => f.normal.is_none() && f.texture.is_none(),
+                    })
+                    .ok_or_else(|| error!(InvalidParameter(i), word))?;
+                if let Some((first, _)) = faces.get(start)
+                    && !face.same_shape(first)
+                {
+                    return Err(error!(InvalidParameter(i), word));
+                }
+                faces.push((face, columns(line, word)));
+            }
+            let minimum = match keyword {
+                b"f" => 3,
+                b"l" => 2,
+                _ => 1,
+            };
+            if faces.len() - start < minimum {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            let range = start..faces.len();
+            Ok(match keyword {
+                b"f" => Statement::Face(range),
+                b"l" => Statement::Line(range),
+                _ => Statement::Points(range),
+            })
+        } // f v1/vt1/vn1 v2/vt2/vn2 v3/vt3/vn3
+        _ => Ok(Statement::Other { keyword, rest }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats_like_the_standard_library() {
+        let tokens = [
+            "0",
+            "1",
+            "-1",
+            "+1",
+            "1.5",
+            "-0.25",
+            "+3.",
+            ".5",
+            "-.5",
+            "0.000001",
+            "123456.7",
+            "16777215",
+            "16777216",
+            "16777217",
+            "0.1234567",
+            "0.12345678",
+            "1e3",
+            "-2.5E-2",
+            "1e-45",
+            "3.5e38",
+            "1e39",
+            "-1e39",
+            "00012.500",
+            "-0",
+            "inf",
+            "-inf",
+            "NaN",
+        ];
+        for token in tokens {
+            let expected: f32 = token.parse().unwrap();
+            let value = parse_float(token.as_bytes()).unwrap();
+            assert!(
+                value.to_bits() == expected.to_bits() || (value.is_nan() && expected.is_nan()),
+                "{token} : {value} instead of {expected}"
+            );
+        }
+        for token in [
+            "", "-", "+", ".", "-.", "1.2.3", "1e", "e5", "1,5", "0x10", "--1", "1 ",
+        ] {
+            assert_eq!(parse_float(token.as_bytes()), None, "{token}");
+        }
+    }
+
+    #[test]
+    fn integers_like_the_standard_library() {
+        let max = isize::MAX.to_string();
+        let min = isize::MIN.to_string();
+        for token in ["0", "7", "-7", "+7", "00042", "-0", &max, &min] {
+            assert_eq!(parse_int(token.as_bytes()), token.parse().ok(), "{token}");
+        }
+        let overflow = format!("{max}0");
+        let underflow = format!("{min}0");
+        for token in [
+            "", "-", "+", "1.0", "1e3", "12a", " 1", "--1", &overflow, &underflow,
+        ] {
+            assert_eq!(parse_int(token.as_bytes()), None, "{token}");
+        }
+    }
+
+    fn lex(line: &str) -> (Result<Statement<'_>, ParseError>, Vec<FaceInfoRaw>) {
+        let mut faces = Vec::new();
+        let result = lex_line(3, line.as_bytes(), &mut faces);
+        (result, faces.into_iter().map(|(face, _)| face).collect())
+    }
+
+    #[test]
+    fn vertices() {
+        let vertex = |line| match lex(line).0 {
+            Ok(Statement::Vertex(v)) => {
+                let (p, c) = (v.position, v.color.map(|c| [c.x, c.y, c.z]));
+                ([p.x, p.y, p.z], c, v.weight)
+            }
+            _ => panic!("{line}"),
+        };
+        assert_eq!(vertex("v 1 -2 3e1"), ([1.0, -2.0, 30.0], None, 1.0));
+        assert_eq!(vertex("v\t1 2 3 0.5"), ([1.0, 2.0, 3.0], None, 0.5));
+        assert_eq!(
+            vertex("v 1 2 3 1 0 0.5  "),
+            ([1.0, 2.0, 3.0], Some([1.0, 0.0, 0.5]), 1.0)
+        );
+        match lex("vt 0.5 1 0.25").0 {
+            Ok(Statement::TextureCoords { uv, ignored_w }) => {
+                assert_eq!(uv, (0.5, 1.0));
+                assert_eq!(ignored_w, Some(&b"0.25"[..]));
+            }
+            _ => panic!("vt"),
+        }
+        assert!(matches!(lex("vn 0 0 -1").0, Ok(Statement::Normal(_))));
+    }
+
+    #[test]
+    fn faces() {
+        let (result, faces) = lex("f 1/2/3 -1/-2/-3 4/5/6 7/8/9");
+        assert!(matches!(result, Ok(Statement::Face(range)) if range == (0..4)));
+        assert_eq!(
+            (faces[1].vertex, faces[1].texture, faces[1].normal),
+            (-1, Some(-2), Some(-3))
+        );
+        let (result, faces) = lex("f 1//3 2//3 3//3");
+        assert!(matches!(result, Ok(Statement::Face(_))));
+        assert!(
+            faces
+                .iter()
+                .all(|f| f.texture.is_none() && f.normal == Some(3))
+        );
+        assert!(matches!(lex("l 1/1 2/2").0, Ok(Statement::Line(_))));
+        assert!(matches!(lex("p 1 2 3").0, Ok(Statement::Points(_))));
+    }
+
+    #[test]
+    fn other_statements() {
+        for line in ["", "   ", "# f 1 2 3", "#"] {
+            assert!(matches!(lex(line).0, Ok(Statement::Empty)), "{line}");
+        }
+        match lex("usemtl  red metal").0 {
+            Ok(Statement::Other { keyword, rest }) => {
+                assert_eq!((keyword, rest), (&b"usemtl"[..], &b" red metal"[..]));
+            }
+            _ => panic!("usemtl"),
+        }
+        assert!(matches!(
+            lex("end").0,
+            Ok(Statement::Other { rest: b"", .. })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |line| match lex(line).0 {
+            Err(error) => (error.line_no, error.columns, error.err_type.to_string()),
+            Ok(_) => panic!("{line}"),
+        };
+        let invalid = |i: usize| ErrorType::InvalidParameter(i).to_string();
+        let count = ErrorType::InvalidParameterNumber.to_string();
+        assert_eq!(error("v 1 2"), (3, Some(2..5), count.clone()));
+        assert_eq!(error("v 1 x 3"), (3, Some(4..5), invalid(1)));
+        assert_eq!(error("vt 1 2 3 4"), (3, Some(3..10), count.clone()));
+        assert_eq!(error("vn 0 0"), (3, Some(3..6), count.clone()));
+        // every vertex of a face has the same shape
+        assert_eq!(error("f 1/1 2/2 3"), (3, Some(10..11), invalid(2)));
+        assert_eq!(error("f 1 2"), (3, Some(2..5), count.clone()));
+        assert_eq!(
+            error("f 1 99999999999999999999 3"),
+            (3, Some(4..24), invalid(1))
+        );
+        assert_eq!(error("l 1//1 2//2"), (3, Some(2..6), invalid(0)));
+        assert_eq!(error("p 1/1"), (3, Some(2..5), invalid(0)));
+    }
+}
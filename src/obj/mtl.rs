@@ -0,0 +1,360 @@
+use super::{Diagnostic, ErrorType, ParseError, Severity, columns};
+use crate::math::vector::Vector3;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// A texture referenced by a map_* statement, with the path already resolved
+#[derive(Debug, Clone)]
+pub struct TextureMap {
+    pub path: PathBuf,
+    pub offset: Vector3<f32>,
+    pub scale: Vector3<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+    pub specular_exponent: f32,
+    pub dissolve: f32,
+    pub illum: u32,
+    pub ambient_map: Option<TextureMap>,
+    pub diffuse_map: Option<TextureMap>,
+    pub specular_map: Option<TextureMap>,
+    pub specular_exponent_map: Option<TextureMap>,
+    pub dissolve_map: Option<TextureMap>,
+    pub bump_map: Option<TextureMap>,
+}
+
+impl Material {
+    /// Material with the default values of the mtl spec
+    pub fn new(name: String) -> Self {
+        Material {
+            name,
+            ambient: Vector3 {
+                x: 0.2,
+                y: 0.2,
+                z: 0.2,
+            },
+            diffuse: Vector3 {
+                x: 0.8,
+                y: 0.8,
+                z: 0.8,
+            },
+            specular: Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            specular_exponent: 0.0,
+            dissolve: 1.0,
+            illum: 1,
+            ambient_map: None,
+            diffuse_map: None,
+            specular_map: None,
+            specular_exponent_map: None,
+            dissolve_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+/// Parses a color statement, `Kd r [g b]`
+/// A single value is used for all 3 channels
+fn parse_color(rest: &str) -> Result<Vector3<f32>, ErrorType> {
+    let args: Vec<_> = rest.split_whitespace().collect();
+    if args.first() == Some(&"spectral") || args.first() == Some(&"xyz") {
+        return Err(ErrorType::Unsupported(format!("{} color", args[0])));
+    }
+    let args: Vec<f32> = args
+        .iter()
+        .enumerate()
+        .map(|(i, s)| s.parse::<f32>().map_err(|_| ErrorType::InvalidParameter(i)))
+        .collect::<Result<_, _>>()?;
+    match args.len() {
+        1 => Ok(Vector3 {
+            x: args[0],
+            y: args[0],
+            z: args[0],
+        }),
+        3 => Ok(Vector3::from_iterator(&mut args.into_iter())),
+        _ => Err(ErrorType::InvalidParameterNumber),
+    }
+}
+
+fn parse_float(rest: &str) -> Result<f32, ErrorType> {
+    let args: Vec<_> = rest.split_whitespace().collect();
+    if args.len() != 1 {
+        return Err(ErrorType::InvalidParameterNumber);
+    }
+    args[0]
+        .parse::<f32>()
+        .map_err(|_| ErrorType::InvalidParameter(0))
+}
+
+/// Parses a `map_* [options] filename` statement
+/// Only -o and -s are kept, other options are skipped
+fn parse_map(rest: &str, base_dir: &Path) -> Result<TextureMap, ErrorType> {
+    let mut args = rest.split_whitespace().enumerate().peekable();
+    let mut offset = Vector3::zero();
+    let mut scale = Vector3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    while let Some((i, option)) = args.next_if(|(_, s)| s.starts_with('-')) {
+        match option {
+            "-o" | "-s" | "-t" => {
+                let mut value = Vector3::zero();
+                for axis in 0..3 {
+                    match args.next_if(|(_, s)| s.parse::<f32>().is_ok()) {
+                        Some((_, s)) => value[axis] = s.parse().unwrap(),
+                        None if axis == 0 => return Err(ErrorType::InvalidParameter(i + 1)),
+                        None if option == "-s" => value[axis] = 1.0,
+                        None => break,
+                    }
+                }
+                match option {
+                    "-o" => offset = value,
+                    "-s" => scale = value,
+                    _ => (),
+                }
+            }
+            "-mm" => {
+                args.next().ok_or(ErrorType::InvalidParameterNumber)?;
+                args.next().ok_or(ErrorType::InvalidParameterNumber)?;
+            }
+            "-blendu" | "-blendv" | "-cc" | "-clamp" | "-texres" | "-bm" | "-imfchan"
+            | "-boost" | "-type" => {
+                args.next().ok_or(ErrorType::InvalidParameterNumber)?;
+            }
+            _ => return Err(ErrorType::InvalidParameter(i)),
+        }
+    }
+    let file_name = args.map(|(_, s)| s).collect::<Vec<_>>().join(" ");
+    if file_name.is_empty() {
+        return Err(ErrorType::InvalidParameterNumber);
+    }
+    // mtl files exported on windows tend to use backslashes
+    Ok(TextureMap {
+        path: base_dir.join(file_name.replace('\\', "/")),
+        offset,
+        scale,
+    })
+}
+
+fn parse_one_line(
+    line_no: usize,
+    line: &str,
+    base_dir: &Path,
+    materials: &mut Vec<Material>,
+) -> Result<(), ParseError> {
+    use ErrorType::*;
+    macro_rules! error {
+        ($err:expr) => {
+            error!($err, line)
+        };
+        ($err:expr, $token:expr) => {
+            ParseError {
+                line: Some(line.into()),
+                line_no,
+                columns: Some(columns(line.as_bytes(), $token.as_bytes())),
+                err_type: $err,
+            }
+        };
+    }
+    let trimmed = line.trim();
+    if trimmed.starts_with("#") || trimmed.is_empty() {
+        return Ok(());
+    }
+    let (line_type, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .ok_or(error!(InvalidLine))?;
+    if line_type == "newmtl" {
+        materials.push(Material::new(rest.trim().into()));
+        return Ok(());
+    }
+    let material = materials
+        .last_mut()
+        .ok_or_else(|| error!(InvalidEntry(format!("{line_type} before newmtl")), line_type))?;
+    match line_type {
+        "Ka" => material.ambient = parse_color(rest).map_err(|e| error!(e, rest))?,
+        "Kd" => material.diffuse = parse_color(rest).map_err(|e| error!(e, rest))?,
+        "Ks" => material.specular = parse_color(rest).map_err(|e| error!(e, rest))?,
+        "Ns" => material.specular_exponent = parse_float(rest).map_err(|e| error!(e, rest))?,
+        "d" => {
+            // d -halo factor
+            let rest = rest.trim_start().strip_prefix("-halo").unwrap_or(rest);
+            material.dissolve = parse_float(rest).map_err(|e| error!(e, rest))?;
+        }
+        "Tr" => material.dissolve = 1.0 - parse_float(rest).map_err(|e| error!(e, rest))?,
+        "illum" => {
+            material.illum = rest
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| error!(InvalidParameter(0), rest.trim()))?
+        }
+        "map_Ka" => material.ambient_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?),
+        "map_Kd" => material.diffuse_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?),
+        "map_Ks" => material.specular_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?),
+        "map_Ns" => {
+            material.specular_exponent_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?)
+        }
+        "map_d" => material.dissolve_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?),
+        "map_bump" | "bump" | "map_Bump" => {
+            material.bump_map = Some(parse_map(rest, base_dir).map_err(|e| error!(e, rest))?)
+        }
+        "Ke" | "Ni" | "Tf" | "sharpness" | "disp" | "decal" | "refl" | "map_aat" => {
+            return Err(error!(Unsupported(line_type.into()), line_type));
+        }
+        _ => return Err(error!(InvalidEntry(line_type.into()), line_type)),
+    }
+    Ok(())
+}
+
+/// Parses a material library
+/// Texture paths are resolved relative to `base_dir`, which should be the directory of the mtl
+/// Unsupported statements are returned as warnings when `ignore_unimplemented` is set
+pub fn parse_mtl(
+    reader: impl BufRead,
+    base_dir: &Path,
+    ignore_unimplemented: bool,
+) -> Result<(Vec<Material>, Vec<Diagnostic>), ParseError> {
+    let mut materials = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| ParseError {
+            line: None,
+            line_no: index + 1,
+            columns: None,
+            err_type: ErrorType::IOError(err),
+        })?;
+        if let Err(err) = parse_one_line(index + 1, &line, base_dir, &mut materials) {
+            match &err.err_type {
+                ErrorType::Unsupported(_) if ignore_unimplemented => {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Warning,
+                        error: err,
+                    })
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+    Ok((materials, diagnostics))
+}
+
+pub fn load_mtl(
+    path: &Path,
+    ignore_unimplemented: bool,
+) -> Result<(Vec<Material>, Vec<Diagnostic>), ParseError> {
+    let file = File::open(path).map_err(|err| ParseError {
+        line: Some(path.display().to_string()),
+        line_no: 0,
+        columns: None,
+        err_type: ErrorType::IOError(err),
+    })?;
+    parse_mtl(
+        BufReader::new(file),
+        path.parent().unwrap_or(Path::new("")),
+        ignore_unimplemented,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str, ignore_unimplemented: bool) -> Result<Vec<Material>, ParseError> {
+        parse_mtl(source.as_bytes(), Path::new("dir"), ignore_unimplemented)
+            .map(|(materials, _)| materials)
+    }
+
+    fn rgb(v: Vector3<f32>) -> [f32; 3] {
+        [v.x, v.y, v.z]
+    }
+
+    #[test]
+    fn material_fields() {
+        let materials = parse(
+            "# two materials\nnewmtl red\nKa 0 0 0\nKd 1 0 0\nKs 0.5\nNs 10\nd -halo 0.5\n\
+             illum 2\nmap_Kd -s 2 3 -o 0.5 textures\\red wood.png\nbump -bm 1 bump.png\n\n\
+             newmtl plain\nTr 0.25\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 2);
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!(rgb(red.ambient), [0.0; 3]);
+        assert_eq!(rgb(red.diffuse), [1.0, 0.0, 0.0]);
+        assert_eq!(rgb(red.specular), [0.5; 3]);
+        assert_eq!((red.specular_exponent, red.dissolve), (10.0, 0.5));
+        assert_eq!(red.illum, 2);
+        let map = red.diffuse_map.as_ref().unwrap();
+        assert_eq!(map.path, Path::new("dir/textures/red wood.png"));
+        assert_eq!(rgb(map.scale), [2.0, 3.0, 1.0]);
+        assert_eq!(rgb(map.offset), [0.5, 0.0, 0.0]);
+        let bump = red.bump_map.as_ref().unwrap();
+        assert_eq!(bump.path, Path::new("dir/bump.png"));
+        assert!(red.specular_map.is_none());
+        // the defaults of the spec, but for the dissolve
+        let plain = &materials[1];
+        assert_eq!(rgb(plain.diffuse), [0.8; 3]);
+        assert_eq!((plain.dissolve, plain.illum), (0.75, 1));
+        assert!(plain.diffuse_map.is_none());
+    }
+
+    #[test]
+    fn unsupported_statements() {
+        let source = "newmtl a\nKe 1 1 1\nKd 0 1 0\n";
+        let (materials, diagnostics) = parse_mtl(source.as_bytes(), Path::new(""), true).unwrap();
+        assert_eq!(rgb(materials[0].diffuse), [0.0, 1.0, 0.0]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].error.line_no, 2);
+        assert!(matches!(&diagnostics[0].error.err_type, ErrorType::Unsupported(s) if s == "Ke"));
+        let err = parse(source, false).unwrap_err();
+        assert!(matches!(err.err_type, ErrorType::Unsupported(_)));
+        assert_eq!(err.line_no, 2);
+    }
+
+    #[test]
+    fn malformed_statements() {
+        let error = |source: &str| {
+            let err = parse(source, true).unwrap_err();
+            (err.line_no, err.err_type)
+        };
+        assert!(matches!(
+            error("newmtl a\nKd 1 x 0\n"),
+            (2, ErrorType::InvalidParameter(1))
+        ));
+        assert!(matches!(
+            error("newmtl a\nKd 1 0\n"),
+            (2, ErrorType::InvalidParameterNumber)
+        ));
+        assert!(matches!(
+            error("newmtl a\n\nNs\n"),
+            (3, ErrorType::InvalidLine)
+        ));
+        assert!(matches!(
+            error("newmtl a\nillum -1\n"),
+            (2, ErrorType::InvalidParameter(0))
+        ));
+        assert!(matches!(
+            error("newmtl a\nmap_Kd -o\n"),
+            (2, ErrorType::InvalidParameter(1))
+        ));
+        assert!(matches!(
+            error("Kd 1 0 0\nnewmtl a\n"),
+            (1, ErrorType::InvalidEntry(_))
+        ));
+        assert!(matches!(
+            error("newmtl a\nfoo 1\n"),
+            (2, ErrorType::InvalidEntry(s)) if s == "foo"
+        ));
+    }
+}
@@ -0,0 +1,296 @@
+use super::lexer::{self, Statement};
+use super::{
+    Diagnostic, FaceInfoRaw, Model, ParseError, ParseOptions, ParseState, apply_statement,
+    finish, handle_result, io_error,
+};
+use std::io::Read;
+use std::ops::Range;
+use std::thread;
+
+/// Bytes read at once for every thread
+const CHUNK_SIZE: usize = 4 << 20;
+
+/// A lexed line of a chunk, with its line number relative to the chunk
+enum Entry<'a> {
+    Lexed {
+        line_no: usize,
+        line: &'a [u8],
+        result: Result<Statement<'a>, Box<ParseError>>,
+    },
+    /// Statement continued over several lines, lexed when it is applied
+    Joined { line_no: usize, line: Vec<u8> },
+}
+
+struct LexedChunk<'a> {
+    /// Number of lines of the chunk
+    lines: usize,
+    entries: Vec<Entry<'a>>,
+    faces: Vec<(FaceInfoRaw, Range<usize>)>,
+}
+
+/// `line` without its line break
+fn trim_line_break(line: &[u8]) -> &[u8] {
+    match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => line,
+    }
+}
+
+/// Whether the statement of the line that ends at `newline` goes on after it
+fn is_continued(data: &[u8], newline: usize) -> bool {
+    trim_line_break(&data[..=newline]).ends_with(b"\\")
+}
+
+/// Index right after the first statement ending at or after `from`
+fn next_statement_end(data: &[u8], from: usize) -> Option<usize> {
+    (from..data.len())
+        .filter(|&i| data[i] == b'\n')
+        .find(|&i| !is_continued(data, i))
+        .map(|i| i + 1)
+}
+
+/// Index right after the last complete statement of `data`
+fn last_statement_end(data: &[u8]) -> Option<usize> {
+    (0..data.len())
+        .rev()
+        .filter(|&i| data[i] == b'\n')
+        .find(|&i| !is_continued(data, i))
+        .map(|i| i + 1)
+}
+
+/// Splits `data`, which only holds complete statements, in about `count` chunks
+fn split_chunks(data: &[u8], count: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::with_capacity(count);
+    let mut start = 0;
+    for k in 1..count {
+        let target = (data.len() * k / count).max(start);
+        match next_statement_end(data, target) {
+            Some(end) if end < data.len() => {
+                chunks.push(&data[start..end]);
+                start = end;
+            }
+            _ => break,
+        }
+    }
+    chunks.push(&data[start..]);
+    chunks
+}
+
+fn lex_chunk(chunk: &[u8]) -> LexedChunk<'_> {
+    let mut lexed = LexedChunk {
+        lines: 0,
+        entries: Vec::new(),
+        faces: Vec::new(),
+    };
+    let mut lines = chunk.split_inclusive(|&b| b == b'\n');
+    while let Some(line) = lines.next() {
+        lexed.lines += 1;
+        let line_no = lexed.lines;
+        let line = trim_line_break(line);
+        if line.ends_with(b"\\") {
+            // same joining as `read_statement`
+            let mut joined = line.to_vec();
+            while let Some(last) = joined.last_mut().filter(|last| **last == b'\\') {
+                *last = b' ';
+                let Some(next) = lines.next() else {
+                    break;
+                };
+                lexed.lines += 1;
+                joined.extend_from_slice(trim_line_break(next));
+            }
+            lexed.entries.push(Entry::Joined {
+                line_no,
+                line: joined,
+            });
+            continue;
+        }
+        match lexer::lex_line(line_no, line, &mut lexed.faces) {
+            Ok(Statement::Empty) => {}
+            result => lexed.entries.push(Entry::Lexed {
+                line_no,
+                line,
+                result: result.map_err(Box::new),
+            }),
+        }
+    }
+    lexed
+}
+
+/// Parses the file by lexing line aligned chunks on `options.threads` threads, and applying
+/// them in order, so the result is the same as with a single thread
+pub(super) fn parse_obj_parallel(
+    mut reader: impl Read,
+    options: &ParseOptions,
+) -> Result<(Model, Vec<Diagnostic>), ParseError> {
+    let threads = options.threads.max(1);
+    let mut state = ParseState::default();
+    let mut faces = Vec::new();
+    let mut data = Vec::new();
+    let mut line_no = 0;
+    let mut block_size = CHUNK_SIZE * threads;
+    loop {
+        let wanted = block_size.saturating_sub(data.len());
+        let read = reader
+            .by_ref()
+            .take(wanted as u64)
+            .read_to_end(&mut data)
+            .map_err(|err| io_error(line_no + 1, err))?;
+        let end_of_file = read < wanted;
+        let end = match last_statement_end(&data) {
+            _ if end_of_file => data.len(),
+            Some(end) => end,
+            None => {
+                // a single statement bigger than the block
+                block_size *= 2;
+                continue;
+            }
+        };
+
+        let chunks = split_chunks(&data[..end], threads);
+        let lexed: Vec<LexedChunk> = thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .iter()
+                .map(|chunk| scope.spawn(|| lex_chunk(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Lexing thread panicked"))
+                .collect()
+        });
+
+        for chunk in lexed {
+            for entry in chunk.entries {
+                let result = match entry {
+                    Entry::Lexed {
+                        line_no: chunk_line_no,
+                        line,
+                        result,
+                    } => {
+                        let entry_line_no = line_no + chunk_line_no;
+                        match result {
+                            Ok(statement) => apply_statement(
+                                entry_line_no,
+                                line,
+                                statement,
+                                &chunk.faces,
+                                &mut state,
+                                options,
+                            ),
+                            Err(err) => Err(ParseError {
+                                line_no: entry_line_no,
+                                ..*err
+                            }),
+                        }
+                    }
+                    Entry::Joined {
+                        line_no: chunk_line_no,
+                        line,
+                    } => {
+                        let entry_line_no = line_no + chunk_line_no;
+                        faces.clear();
+                        lexer::lex_line(entry_line_no, &line, &mut faces).and_then(|statement| {
+                            apply_statement(entry_line_no, &line, statement, &faces, &mut state, options)
+                        })
+                    }
+                };
+                handle_result(result, &mut state.diagnostics, options)?;
+            }
+            line_no += chunk.lines;
+        }
+        data.drain(..end);
+        if end_of_file {
+            break;
+        }
+    }
+    finish(state, options, line_no)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::parse_obj;
+
+    /// Obj with continued faces, invalid lines and every kind of statement, over `blocks` blocks
+    fn source(blocks: usize) -> String {
+        let mut source = String::from("mtllib missing.mtl\n");
+        for block in 0..blocks {
+            let base = block * 4;
+            let (a, b, c, d) = (base + 1, base + 2, base + 3, base + 4);
+            source += &format!(
+                "o part{block}\nv {block} 0 0\nv {block}.5 1 0\nv {block} 1 1.25\nv 0 0 {block}\n\
+                 vt 0.{block} 1\nvn 0 0 1\ns {}\n\
+                 f {a}/1/1 {b}/1/1 \\\n  {c}/1/1\nf {a} {c} \\\n{d}\n\
+                 # comment {block}\nl {a} \\\n{d}\np {b}\nf {a} {b} 999\nv 1 nope 3\n",
+                block % 3,
+            );
+        }
+        source
+    }
+
+    fn parse(source: &str, threads: usize) -> (String, String) {
+        let options = ParseOptions {
+            keep_going: true,
+            threads,
+            ..Default::default()
+        };
+        let (model, diagnostics) = parse_obj(source.as_bytes(), &options).unwrap();
+        let model = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?}",
+            model.vertices,
+            model.indices,
+            model.submeshes,
+            model.line_indices,
+            model.point_indices,
+            model.vertex_faces
+        );
+        let diagnostics = format!("{diagnostics:?}");
+        (model, diagnostics)
+    }
+
+    /// Whether the chunks wanted by `split_chunks(data, 4)` start in the middle of a line, one
+    /// of them inside a continued statement
+    fn tricky_boundaries(data: &[u8]) -> bool {
+        let targets: Vec<_> = (1..4).map(|k| data.len() * k / 4).collect();
+        let continued = |target: usize| {
+            let line_start = data[..target].iter().rposition(|&b| b == b'\n').unwrap() + 1;
+            data[line_start..next_statement_end(data, line_start).unwrap()]
+                .windows(2)
+                .any(|w| w == b"\\\n")
+        };
+        targets.iter().all(|&target| data[target - 1] != b'\n')
+            && targets.iter().any(|&target| continued(target))
+    }
+
+    /// Source whose 4 chunks start in awkward places
+    fn tricky_source() -> String {
+        (40..80)
+            .map(source)
+            .find(|source| tricky_boundaries(source.as_bytes()))
+            .unwrap()
+    }
+
+    #[test]
+    fn chunks_end_with_statements() {
+        let source = tricky_source();
+        let data = source.as_bytes();
+        let chunks = split_chunks(data, 4);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks {
+            assert!(chunk.ends_with(b"\n") && !chunk.ends_with(b"\\\n"));
+        }
+    }
+
+    #[test]
+    fn same_model_with_any_number_of_threads() {
+        let source = tricky_source();
+        let (model, diagnostics) = parse(&source, 1);
+        assert!(diagnostics.contains("IndexOutOfBound"));
+        for threads in [2, 3, 4, 7] {
+            assert_eq!(
+                parse(&source, threads),
+                (model.clone(), diagnostics.clone())
+            );
+        }
+    }
+}
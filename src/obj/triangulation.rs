@@ -0,0 +1,277 @@
+use crate::math::vector::{Vector2, Vector3};
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Triangulation {
+    /// Fast and easy but might fail on Concave shapes
+    Fan,
+    /// Projects the polygon on its best fitting plane and clips ears from it
+    /// Handles concave polygons and detects broken ones
+    #[default]
+    EarClipping,
+}
+
+/// Problems found while triangulating a polygon
+/// The polygon is still triangulated, but the result might look wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolygonIssue {
+    /// Zero area, collinear or repeated vertices
+    Degenerate,
+    /// Two edges of the polygon cross each other
+    SelfIntersecting,
+}
+
+impl Display for PolygonIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolygonIssue::Degenerate => write!(f, "Degenerate polygon"),
+            PolygonIssue::SelfIntersecting => write!(f, "Self intersecting polygon"),
+        }
+    }
+}
+
+/// Triangulates a polygonal face by using the fan method
+/// Triangles are returned as indices into the polygon
+pub fn fan(len: usize) -> Vec<[usize; 3]> {
+    (1..len.saturating_sub(1)).map(|i| [0, i, i + 1]).collect()
+}
+
+/// Triangulates the polygon whose vertices are `polygon`, in order
+/// Triangles are returned as indices into `polygon`, with the winding of the polygon
+pub fn triangulate(
+    method: Triangulation,
+    polygon: &[Vector3<f32>],
+) -> (Vec<[usize; 3]>, Option<PolygonIssue>) {
+    match method {
+        Triangulation::Fan => (fan(polygon.len()), None),
+        Triangulation::EarClipping => ear_clipping(polygon),
+    }
+}
+
+/// Normal of the best fitting plane, using Newell's method
+/// Its length is twice the area of the polygon
+pub fn newell_normal(polygon: &[Vector3<f32>]) -> Vector3<f64> {
+    let mut normal = Vector3::<f64>::zero();
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (a, b) = (to_f64(*a), to_f64(b));
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+    normal
+}
+
+fn to_f64(v: Vector3<f32>) -> Vector3<f64> {
+    Vector3 {
+        x: v.x as f64,
+        y: v.y as f64,
+        z: v.z as f64,
+    }
+}
+
+fn dot(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+/// Twice the signed area of abc, positive when counter clockwise
+fn orient(a: Vector2<f64>, b: Vector2<f64>, c: Vector2<f64>) -> f64 {
+    (b - a).cross(c - a)
+}
+
+/// Projects the polygon on the plane orthogonal to `normal`
+/// Counter clockwise polygons around `normal` stay counter clockwise
+fn project(polygon: &[Vector3<f32>], normal: &Vector3<f64>) -> Vec<Vector2<f64>> {
+    let axis = if normal.x.abs() <= normal.y.abs() && normal.x.abs() <= normal.z.abs() {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    } else if normal.y.abs() <= normal.z.abs() {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        }
+    };
+    let u = axis.cross(normal).normalized();
+    let v = normal.cross(&u).normalized();
+    let origin = to_f64(polygon[0]);
+    polygon
+        .iter()
+        .map(|p| {
+            let p = to_f64(*p) - origin;
+            Vector2 {
+                x: dot(&p, &u),
+                y: dot(&p, &v),
+            }
+        })
+        .collect()
+}
+
+/// Whether two edges of the polygon properly cross each other
+/// Edges sharing a vertex are not tested against each other
+fn self_intersects(points: &[Vector2<f64>], epsilon: f64) -> bool {
+    let len = points.len();
+    for i in 0..len {
+        let (a, b) = (points[i], points[(i + 1) % len]);
+        for j in i + 2..len {
+            if (j + 1) % len == i {
+                continue;
+            }
+            let (c, d) = (points[j], points[(j + 1) % len]);
+            let (o1, o2) = (orient(a, b, c), orient(a, b, d));
+            let (o3, o4) = (orient(c, d, a), orient(c, d, b));
+            if ((o1 > epsilon && o2 < -epsilon) || (o1 < -epsilon && o2 > epsilon))
+                && ((o3 > epsilon && o4 < -epsilon) || (o3 < -epsilon && o4 > epsilon))
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn inside_triangle(
+    p: Vector2<f64>,
+    [a, b, c]: [Vector2<f64>; 3],
+    epsilon: f64,
+) -> bool {
+    orient(a, b, p) >= -epsilon && orient(b, c, p) >= -epsilon && orient(c, a, p) >= -epsilon
+}
+
+fn ear_clipping(polygon: &[Vector3<f32>]) -> (Vec<[usize; 3]>, Option<PolygonIssue>) {
+    if polygon.len() < 3 {
+        return (Vec::new(), Some(PolygonIssue::Degenerate));
+    }
+    let normal = newell_normal(polygon);
+    let (min, max) = polygon.iter().fold(
+        (polygon[0], polygon[0]),
+        |(min, max), p| {
+            (
+                Vector3 {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                    z: min.z.min(p.z),
+                },
+                Vector3 {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                    z: max.z.max(p.z),
+                },
+            )
+        },
+    );
+    // Tolerances relative to the size of the polygon, f32 inputs are not precise anyway
+    let size2 = to_f64(max - min).norm2();
+    let epsilon = size2 * 1e-10;
+    if normal.norm() <= size2 * 1e-7 {
+        return (fan(polygon.len()), Some(PolygonIssue::Degenerate));
+    }
+    let points = project(polygon, &normal);
+    let mut issue = None;
+    if points.iter().enumerate().any(|(i, p)| {
+        let edge = *p - points[(i + 1) % points.len()];
+        edge.dot(edge) <= size2 * 1e-12
+    }) {
+        issue = Some(PolygonIssue::Degenerate);
+    }
+    if polygon.len() == 3 {
+        return (vec![[0, 1, 2]], issue);
+    }
+    if self_intersects(&points, epsilon) {
+        issue = Some(PolygonIssue::SelfIntersecting);
+    }
+
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    let mut i = 0;
+    let mut tries = 0;
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let corners = [(i + len - 1) % len, i, (i + 1) % len].map(|c| remaining[c]);
+        let triangle = corners.map(|c| points[c]);
+        let is_ear = orient(triangle[0], triangle[1], triangle[2]) > epsilon
+            && !remaining.iter().any(|&other| {
+                !corners.contains(&other)
+                    && corners.iter().all(|&c| {
+                        let d = points[c] - points[other];
+                        d.dot(d) > 0.0
+                    })
+                    && inside_triangle(points[other], triangle, epsilon)
+            });
+        if is_ear {
+            triangles.push(corners);
+            remaining.remove(i);
+            i %= remaining.len();
+            tries = 0;
+        } else {
+            i = (i + 1) % len;
+            tries += 1;
+            if tries >= len {
+                // No ear left, the polygon is broken: fan whatever remains
+                issue.get_or_insert(PolygonIssue::Degenerate);
+                break;
+            }
+        }
+    }
+    triangles.extend(
+        fan(remaining.len())
+            .into_iter()
+            .map(|triangle| triangle.map(|c| remaining[c])),
+    );
+    (triangles, issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32) -> Vector3<f32> {
+        Vector3 { x, y, z: 0.0 }
+    }
+
+    #[test]
+    fn fewer_than_three_vertices() {
+        for len in 0..3 {
+            let polygon = vec![point(0.0, 0.0); len];
+            for method in [Triangulation::Fan, Triangulation::EarClipping] {
+                assert!(triangulate(method, &polygon).0.is_empty());
+            }
+        }
+        let (triangles, issue) = triangulate(Triangulation::EarClipping, &[]);
+        assert!(triangles.is_empty());
+        assert_eq!(issue, Some(PolygonIssue::Degenerate));
+    }
+
+    #[test]
+    fn concave_polygon() {
+        // an L shape, concave at (1, 1)
+        let polygon = [
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+        ]
+        .map(|(x, y)| point(x, y));
+        let (triangles, issue) = triangulate(Triangulation::EarClipping, &polygon);
+        assert_eq!(issue, None);
+        assert_eq!(triangles.len(), 4);
+        let area: f32 = (triangles.iter())
+            .map(|t| {
+                let [a, b, c] = t.map(|i| polygon[i]);
+                (b - a).cross(&(c - a)).z / 2.0
+            })
+            .sum();
+        assert_eq!(area, 3.0);
+    }
+}
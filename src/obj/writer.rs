@@ -0,0 +1,311 @@
+use super::Model;
+use crate::mesh;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// Number of decimals written for every float
+    pub precision: usize,
+    /// Write identical positions, texture coordinates and normals only once
+    pub dedup: bool,
+    /// Use negative indices, relative to the end of each list
+    pub relative_indices: bool,
+    /// Write the colors of the file as `v x y z r g b`, on the vertices that have one
+    pub vertex_colors: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            precision: 6,
+            dedup: true,
+            relative_indices: false,
+            vertex_colors: false,
+        }
+    }
+}
+
+/// List of the lines of one entry type, optionnaly deduplicated
+struct Entries {
+    lines: Vec<String>,
+    known: Option<HashMap<String, usize>>,
+}
+
+impl Entries {
+    fn new(dedup: bool) -> Self {
+        Entries {
+            lines: Vec::new(),
+            known: dedup.then(HashMap::new),
+        }
+    }
+    /// Returns the 0 based index of `line`
+    fn insert(&mut self, line: String) -> usize {
+        match &mut self.known {
+            Some(known) => *known.entry(line).or_insert_with_key(|line| {
+                self.lines.push(line.clone());
+                self.lines.len() - 1
+            }),
+            None => {
+                self.lines.push(line);
+                self.lines.len() - 1
+            }
+        }
+    }
+    /// Index as written in the file
+    fn reference(&self, index: usize, relative: bool) -> isize {
+        if relative {
+            index as isize - self.lines.len() as isize
+        } else {
+            index as isize + 1
+        }
+    }
+}
+
+/// Writes the model as an obj file
+/// Faces are written in order, with the o/g/usemtl statements of the submeshes and materials
+/// The polygons of the model are written as one face, the other faces as triangles
+/// Polylines and points follow, as `l` and `p` statements
+/// `vt` and `vn` are only written when the file of the model had them
+pub fn write_obj(model: &Model, mut writer: impl Write, options: &WriteOptions) -> io::Result<()> {
+    let p = options.precision;
+    let mut positions = Entries::new(options.dedup);
+    let mut texture_coords = Entries::new(options.dedup);
+    let mut normals = Entries::new(options.dedup);
+    // points only use positions, lines skip normals
+    let mut used_by = vec![(false, false); model.vertices.len()];
+    for &index in model.indices.iter() {
+        used_by[index as usize] = (true, true);
+    }
+    for &index in model.line_indices.iter() {
+        used_by[index as usize].0 = true;
+    }
+    let mut vertex_entries = Vec::with_capacity(model.vertices.len());
+    for (i, vertex) in model.vertices.iter().enumerate() {
+        let (uses_texture, uses_normal) = used_by[i];
+        let pos = vertex.position;
+        // `vertex.color` is the one of the color scheme shown
+        let color = (options.vertex_colors)
+            .then(|| model.file_colors.get(i).copied().flatten())
+            .flatten();
+        let position = match color {
+            Some(color) => format!(
+                "v {:.p$} {:.p$} {:.p$} {:.p$} {:.p$} {:.p$}",
+                pos.x, pos.y, pos.z, color.x, color.y, color.z
+            ),
+            None => format!("v {:.p$} {:.p$} {:.p$}", pos.x, pos.y, pos.z),
+        };
+        let (u, v) = vertex.texture_coordinates;
+        let normal = vertex.normal;
+        vertex_entries.push((
+            positions.insert(position),
+            (model.file_texture_coordinates && uses_texture)
+                .then(|| texture_coords.insert(format!("vt {u:.p$} {v:.p$}"))),
+            (model.file_normals && uses_normal).then(|| {
+                normals.insert(format!(
+                    "vn {:.p$} {:.p$} {:.p$}",
+                    normal.x, normal.y, normal.z
+                ))
+            }),
+        ));
+    }
+
+    writeln!(writer, "# Written by scop")?;
+    for lib in &model.material_libs {
+        writeln!(writer, "mtllib {lib}")?;
+    }
+    for line in positions
+        .lines
+        .iter()
+        .chain(&texture_coords.lines)
+        .chain(&normals.lines)
+    {
+        writeln!(writer, "{line}")?;
+    }
+
+    let relative = options.relative_indices;
+    let mut object = "";
+    let mut submeshes = model.submeshes.iter().peekable();
+    let mut material_ranges = model.material_ranges.iter().peekable();
+    let mut polygons = model.polygons.iter().peekable();
+    let mut start = 0;
+    while start < model.indices.len() {
+        if let Some(submesh) = submeshes.next_if(|s| s.indices.start == start) {
+            if submesh.object != object && !submesh.object.is_empty() {
+                writeln!(writer, "o {}", submesh.object)?;
+            }
+            object = &submesh.object;
+            writeln!(writer, "g {}", submesh.group)?;
+        }
+        if let Some(range) = material_ranges.next_if(|r| r.indices.start == start) {
+            writeln!(writer, "usemtl {}", range.material)?;
+        }
+        // polygons whose triangles don't rebuild them are written as triangles
+        let polygon = polygons.next_if(|polygon| polygon.start == start);
+        let outline = polygon
+            .and_then(|polygon| mesh::polygon_outline(&model.indices[polygon.clone()], |v| v));
+        let (end, corners) = match (polygon, outline) {
+            (Some(polygon), Some(mut corners)) => {
+                // ear clipping cuts the ear of the first corner first, written from that corner
+                // the polygon is triangulated the same way when it is read again
+                let first = model.indices[polygon.start + 1];
+                let at = corners.iter().position(|&v| v == first).unwrap_or(0);
+                corners.rotate_left(at);
+                (polygon.end, corners)
+            }
+            _ => (start + 3, model.indices[start..start + 3].to_vec()),
+        };
+        write!(writer, "f")?;
+        for index in corners {
+            let (position, texture, normal) = vertex_entries[index as usize];
+            write!(writer, " {}", positions.reference(position, relative))?;
+            match (texture, normal) {
+                (Some(texture), Some(normal)) => write!(
+                    writer,
+                    "/{}/{}",
+                    texture_coords.reference(texture, relative),
+                    normals.reference(normal, relative)
+                )?,
+                (Some(texture), None) => {
+                    write!(writer, "/{}", texture_coords.reference(texture, relative))?
+                }
+                (None, Some(normal)) => {
+                    write!(writer, "//{}", normals.reference(normal, relative))?
+                }
+                (None, None) => {}
+            }
+        }
+        writeln!(writer)?;
+        start = end;
+    }
+    for strip in &model.line_strips {
+        write!(writer, "l")?;
+        for &index in &model.line_indices[strip.clone()] {
+            let (position, texture, _) = vertex_entries[index as usize];
+            write!(writer, " {}", positions.reference(position, relative))?;
+            if let Some(texture) = texture {
+                write!(writer, "/{}", texture_coords.reference(texture, relative))?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    if !model.point_indices.is_empty() {
+        write!(writer, "p")?;
+        for &index in &model.point_indices {
+            let (position, _, _) = vertex_entries[index as usize];
+            write!(writer, " {}", positions.reference(position, relative))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::ColorScheme;
+    use crate::obj::{ParseOptions, parse_obj};
+
+    fn parse(source: &[u8]) -> Model {
+        let (model, diagnostics) = parse_obj(source, &ParseOptions::default()).unwrap();
+        assert!(diagnostics.is_empty());
+        model
+    }
+
+    fn write(model: &Model) -> String {
+        let mut output = Vec::new();
+        write_obj(model, &mut output, &WriteOptions::default()).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Position, texture coordinates and normal of every corner of the triangles
+    fn corners(model: &Model) -> Vec<[f32; 8]> {
+        (model.indices.iter())
+            .map(|&i| {
+                let v = &model.vertices[i as usize];
+                let (p, (s, t), n) = (v.position, v.texture_coordinates, v.normal);
+                [p.x, p.y, p.z, s, t, n.x, n.y, n.z]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_write_read() {
+        let source = b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\nvn 0 -1 0\n\
+            o box\ng front\nf 1/1/1 2/2/1 3/3/1 4/4/1\ng bottom\nf 1/1/2 5/2/2 2/3/2\n\
+            l 1 5\np 3\n";
+        let model = parse(source);
+        let written = write(&model);
+        let back = parse(written.as_bytes());
+        assert_eq!(corners(&back), corners(&model));
+        let ranges = |m: &Model| {
+            (m.submeshes.iter())
+                .map(|s| (s.object.clone(), s.group.clone(), s.indices.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(&back), ranges(&model));
+        assert_eq!(back.line_strips.len(), 1);
+        assert_eq!(back.point_indices.len(), 1);
+        assert!(back.file_normals && back.file_texture_coordinates);
+        // everything is written once
+        assert_eq!(written.matches("\nvt ").count(), 4);
+        assert_eq!(written.matches("\nvn ").count(), 2);
+    }
+
+    #[test]
+    fn no_made_up_vt_or_vn() {
+        let source = b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+        let model = parse(source);
+        let written = write(&model);
+        assert!(!written.contains("vt ") && !written.contains("vn "));
+        let faces: Vec<_> = (written.lines())
+            .filter(|line| line.starts_with("f "))
+            .collect();
+        assert_eq!(faces, ["f 1 2 3 4"]);
+        let back = parse(written.as_bytes());
+        assert_eq!(corners(&back), corners(&model));
+        assert!(!back.file_normals && !back.file_texture_coordinates);
+    }
+
+    #[test]
+    fn polygons_stay_polygons() {
+        let source = b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\nv 3 1 0\nv 2 2 0\n\
+            f 1 2 3 4\nf 2 5 6 7 3\nf 4 3 7\n";
+        let model = parse(source);
+        let written = write(&model);
+        let faces: Vec<usize> = (written.lines())
+            .filter_map(|line| line.strip_prefix("f "))
+            .map(|face| face.split(' ').count())
+            .collect();
+        assert_eq!(faces, [4, 5, 3]);
+        let back = parse(written.as_bytes());
+        assert_eq!(back.polygons, model.polygons);
+        assert_eq!(corners(&back), corners(&model));
+    }
+
+    #[test]
+    fn colors_of_the_file() {
+        let source = b"v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 1 1 0\nf 1 2 3\n";
+        let mut model = parse(source);
+        model.set_colors(ColorScheme::Height);
+        let mut output = Vec::new();
+        let options = WriteOptions {
+            vertex_colors: true,
+            ..Default::default()
+        };
+        write_obj(&model, &mut output, &options).unwrap();
+        let written = String::from_utf8(output).unwrap();
+        let positions: Vec<&str> = (written.lines())
+            .filter(|line| line.starts_with("v "))
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                "v 0.000000 0.000000 0.000000 1.000000 0.000000 0.000000",
+                "v 1.000000 0.000000 0.000000 0.000000 1.000000 0.000000",
+                "v 1.000000 1.000000 0.000000",
+            ]
+        );
+    }
+}
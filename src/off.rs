@@ -0,0 +1,501 @@
+mod writer;
+
+pub use writer::{OffWriteOptions, write_off};
+
+use crate::color::ColorScheme;
+use crate::math::vector::{Vector3, Vector4};
+use crate::mesh::NormalOptions;
+use crate::obj::triangulation::{self, Triangulation};
+use crate::obj::{ErrorType, Model, ParseError, Vertex, line_error};
+use crate::uv::UvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{Error as IOError, Read};
+
+#[derive(Debug)]
+pub enum OffError {
+    IOError(IOError),
+    /// Invalid line of the header, a vertex or a face
+    Parse(ParseError),
+    /// The file ends before `element` #`index`
+    Truncated {
+        element: &'static str,
+        index: usize,
+    },
+}
+
+impl Display for OffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OffError::IOError(e) => write!(f, "{e}"),
+            OffError::Parse(e) => write!(f, "{e}"),
+            OffError::Truncated { element, index } => {
+                write!(f, "File ends before {element} #{index}")
+            }
+        }
+    }
+}
+
+impl Error for OffError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct OffOptions {
+    pub triangulation: Triangulation,
+    /// Smoothing of the files without normals, edges sharper than the crease angle stay sharp
+    pub normals: NormalOptions,
+    /// Projection giving texture coordinates to the files without them
+    pub uv: UvOptions,
+}
+
+/// Optional vertex components announced by the `[ST][C][N][4][n]OFF` keyword
+#[derive(Debug, Clone, Copy, Default)]
+struct Keyword {
+    texture_coords: bool,
+    colors: bool,
+    normals: bool,
+    /// Positions have a 4th homogeneous coordinate
+    homogeneous: bool,
+    /// The dimension follows the keyword
+    dimension: bool,
+}
+
+impl Keyword {
+    fn parse(token: &str) -> Option<Keyword> {
+        let mut keyword = Keyword::default();
+        let mut rest = token.strip_suffix("OFF")?;
+        for (prefix, flag) in [
+            ("ST", &mut keyword.texture_coords),
+            ("C", &mut keyword.colors),
+            ("N", &mut keyword.normals),
+            ("4", &mut keyword.homogeneous),
+            ("n", &mut keyword.dimension),
+        ] {
+            if let Some(stripped) = rest.strip_prefix(prefix) {
+                *flag = true;
+                rest = stripped;
+            }
+        }
+        rest.is_empty().then_some(keyword)
+    }
+}
+
+/// Non empty lines, without their comments
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str, Vec<&str>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let content = line.split('#').next().unwrap_or_default();
+        let tokens: Vec<_> = content.split_whitespace().collect();
+        (!tokens.is_empty()).then_some((i + 1, line, tokens))
+    })
+}
+
+/// Whether `start`, the first bytes of a file, looks like an off file
+pub fn is_off(start: &[u8]) -> bool {
+    // the start may end in the middle of a character
+    let text = match std::str::from_utf8(start) {
+        Ok(text) => text,
+        Err(err) => std::str::from_utf8(&start[..err.valid_up_to()]).unwrap(),
+    };
+    lines(text)
+        .next()
+        .is_some_and(|(_, _, tokens)| Keyword::parse(tokens[0]).is_some())
+}
+
+/// Color channels, from 0 to 255 if any of them is above 1, from 0 to 1 otherwise
+/// Alpha is ignored
+fn parse_color(tokens: &[&str]) -> Option<Vector3<f32>> {
+    let channels: Vec<f32> = tokens[..3]
+        .iter()
+        .map(|t| t.parse().ok())
+        .collect::<Option<_>>()?;
+    let scale = if channels.iter().any(|&c| c > 1.0) {
+        255.0
+    } else {
+        1.0
+    };
+    Some(Vector3 {
+        x: channels[0] / scale,
+        y: channels[1] / scale,
+        z: channels[2] / scale,
+    })
+}
+
+struct Face {
+    indices: Vec<u32>,
+    color: Option<Vector3<f32>>,
+}
+
+/// Parses an ascii off file, with the optional normals, colors and texture coordinates of
+/// COFF, NOFF, STOFF and their combinations
+/// Polygons are triangulated, faces of 2 and 1 vertices become lines and points
+pub fn parse_off(mut reader: impl Read, options: &OffOptions) -> Result<Model, OffError> {
+    use ErrorType::*;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(OffError::IOError)?;
+    let text = std::str::from_utf8(&data).map_err(|err| {
+        let line_no = data[..err.valid_up_to()].split(|&b| b == b'\n').count();
+        OffError::Parse(ParseError {
+            line: None,
+            line_no,
+            columns: None,
+            err_type: InvalidLine,
+        })
+    })?;
+    let mut lines = lines(text);
+    let truncated = |element, index| OffError::Truncated { element, index };
+
+    // the keyword, then the optional dimension and the counts, possibly on the same lines
+    let Some((line_no, line, tokens)) = lines.next() else {
+        return Err(truncated("header", 0));
+    };
+    let keyword = Keyword::parse(tokens[0])
+        .ok_or_else(|| line_error(line_no, line.as_bytes(), tokens[0].as_bytes(), InvalidLine))
+        .map_err(OffError::Parse)?;
+    let mut header = tokens[1..]
+        .iter()
+        .map(|&t| (line_no, line, t))
+        .collect::<Vec<_>>();
+    let needed = 2 + keyword.dimension as usize;
+    while header.len() < needed {
+        let (line_no, line, tokens) = lines.next().ok_or(truncated("header", 0))?;
+        header.extend(tokens.into_iter().map(|t| (line_no, line, t)));
+    }
+    let number = |(line_no, line, token): (usize, &str, &str), i: usize| {
+        token
+            .parse::<usize>()
+            .map_err(|_| {
+                line_error(
+                    line_no,
+                    line.as_bytes(),
+                    token.as_bytes(),
+                    InvalidParameter(i),
+                )
+            })
+            .map_err(OffError::Parse)
+    };
+    if let Some(&(line_no, line, token)) = header.first()
+        && token == "BINARY"
+    {
+        let err = Unsupported("binary off".into());
+        return Err(OffError::Parse(line_error(
+            line_no,
+            line.as_bytes(),
+            token.as_bytes(),
+            err,
+        )));
+    }
+    let mut header = header.into_iter().enumerate();
+    if keyword.dimension {
+        let (i, token) = header.next().unwrap();
+        if number(token, i)? != 3 {
+            let err = Unsupported(format!("{}-dimensional vertices", token.2));
+            return Err(OffError::Parse(line_error(
+                token.0,
+                token.1.as_bytes(),
+                token.2.as_bytes(),
+                err,
+            )));
+        }
+    }
+    let (i, token) = header.next().unwrap();
+    let vertex_count = number(token, i)?;
+    let (i, token) = header.next().unwrap();
+    let face_count = number(token, i)?;
+    // the edge count is not used
+
+    let mut positions = Vec::with_capacity(vertex_count.min(1 << 20));
+    let mut normals = keyword.normals.then(Vec::new);
+    let mut colors = keyword.colors.then(Vec::new);
+    let mut texture_coords = keyword.texture_coords.then(Vec::new);
+    let fixed = 3
+        + keyword.homogeneous as usize
+        + 3 * keyword.normals as usize
+        + 2 * keyword.texture_coords as usize;
+    for index in 0..vertex_count {
+        let (line_no, line, tokens) = lines.next().ok_or(truncated("vertex", index))?;
+        let error = |token: &str, err| {
+            OffError::Parse(line_error(line_no, line.as_bytes(), token.as_bytes(), err))
+        };
+        let color_count = tokens.len().saturating_sub(fixed);
+        let valid = match keyword.colors {
+            true => tokens.len() >= fixed && matches!(color_count, 3 | 4),
+            false => tokens.len() == fixed,
+        };
+        if !valid {
+            return Err(error(line, InvalidParameterNumber));
+        }
+        let floats = |start: usize, count: usize| {
+            (start..start + count)
+                .map(|i| {
+                    tokens[i]
+                        .parse::<f32>()
+                        .map_err(|_| error(tokens[i], InvalidParameter(i)))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let vector = |v: &[f32]| Vector3 {
+            x: v[0],
+            y: v[1],
+            z: v[2],
+        };
+        let mut offset = 3;
+        let mut position = vector(&floats(0, 3)?);
+        if keyword.homogeneous {
+            position /= floats(3, 1)?[0];
+            offset += 1;
+        }
+        positions.push(position);
+        if let Some(normals) = &mut normals {
+            normals.push(vector(&floats(offset, 3)?));
+            offset += 3;
+        }
+        if let Some(colors) = &mut colors {
+            let channels = &tokens[offset..offset + color_count];
+            colors.push(
+                parse_color(channels)
+                    .ok_or_else(|| error(channels[0], InvalidParameter(offset)))?,
+            );
+            offset += color_count;
+        }
+        if let Some(texture_coords) = &mut texture_coords {
+            let uv = floats(offset, 2)?;
+            texture_coords.push((uv[0], uv[1]));
+        }
+    }
+
+    let mut faces = Vec::with_capacity(face_count.min(1 << 20));
+    for index in 0..face_count {
+        let (line_no, line, tokens) = lines.next().ok_or(truncated("face", index))?;
+        let error = |token: &str, err| {
+            OffError::Parse(line_error(line_no, line.as_bytes(), token.as_bytes(), err))
+        };
+        let count: usize = tokens[0]
+            .parse()
+            .map_err(|_| error(tokens[0], InvalidParameter(0)))?;
+        let Some(rest) = tokens.get(1 + count..) else {
+            return Err(error(line, InvalidParameterNumber));
+        };
+        let indices = tokens[1..1 + count]
+            .iter()
+            .map(|&token| match token.parse::<isize>() {
+                Ok(i) if (0..positions.len() as isize).contains(&i) => Ok(i as u32),
+                Ok(i) => Err(error(token, IndexOutOfBound(i))),
+                Err(_) => Err(error(token, InvalidParameter(0))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // a single value is an index in a color map, which is not supported
+        let color = match rest.len() {
+            0 | 1 => None,
+            3 | 4 => {
+                Some(parse_color(rest).ok_or_else(|| error(rest[0], InvalidParameter(1 + count)))?)
+            }
+            _ => return Err(error(line, InvalidParameterNumber)),
+        };
+        faces.push(Face { indices, color });
+    }
+
+    let mut indices = Vec::new();
+    let mut line_indices = Vec::new();
+    let mut line_strips = Vec::new();
+    let mut point_indices = Vec::new();
+    let mut polygons = Vec::new();
+    // first face using each vertex, for its color
+    let mut first_face = vec![None; positions.len()];
+    // face of each triangle
+    let mut triangle_faces = Vec::new();
+    // triangle, line and point corners of the colored faces, with the color of their face
+    let mut split_corners = Vec::new();
+    let mut polygon_positions = Vec::new();
+    for (face_no, face) in faces.iter().enumerate() {
+        let starts = [indices.len(), line_indices.len(), point_indices.len()];
+        match face.indices.len() {
+            0 => {}
+            1 => point_indices.push(face.indices[0]),
+            2 => {
+                line_strips.push(line_indices.len()..line_indices.len() + 2);
+                line_indices.extend(&face.indices);
+            }
+            _ => {
+                polygon_positions.clear();
+                polygon_positions.extend(face.indices.iter().map(|&i| positions[i as usize]));
+                let (triangles, _) =
+                    triangulation::triangulate(options.triangulation, &polygon_positions);
+                for triangle in triangles {
+                    indices.extend(triangle.map(|corner| face.indices[corner]));
+                    triangle_faces.push(face_no as u32);
+                }
+                if face.indices.len() > 3 && indices.len() > starts[0] {
+                    polygons.push(starts[0]..indices.len());
+                }
+            }
+        }
+        for &index in &face.indices {
+            first_face[index as usize].get_or_insert(face_no as u32);
+        }
+        if let Some(color) = face.color {
+            let ends = [indices.len(), line_indices.len(), point_indices.len()];
+            let ranges = [0, 1, 2].map(|k| starts[k]..ends[k]);
+            split_corners.push((ranges, face_no as u32, color));
+        }
+    }
+
+    let mut vertices: Vec<_> = positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| Vertex {
+            position,
+            color: Vector3::zero(),
+            texture_coordinates: texture_coords.as_ref().map_or((0.0, 0.0), |uv| uv[i]),
+            normal: normals.as_ref().map_or(Vector3::zero(), |normals| {
+                if normals[i].norm2() > 0.0 {
+                    normals[i].normalized()
+                } else {
+                    normals[i]
+                }
+            }),
+            tangent: Vector4::zero(),
+        })
+        .collect();
+    // face colors win over vertex colors, the vertices of colored faces get a copy per color
+    let mut file_colors: Vec<_> = match &colors {
+        Some(colors) => colors.iter().copied().map(Some).collect(),
+        None => vec![None; positions.len()],
+    };
+    let mut copies = HashMap::new();
+    for ([triangles, lines, points], face_no, color) in split_corners {
+        let corners = indices[triangles].iter_mut();
+        let corners = corners.chain(&mut line_indices[lines]);
+        for index in corners.chain(&mut point_indices[points]) {
+            let key = (*index, [color.x, color.y, color.z].map(f32::to_bits));
+            *index = *copies.entry(key).or_insert_with(|| {
+                vertices.push(Vertex {
+                    color,
+                    ..vertices[*index as usize]
+                });
+                file_colors.push(Some(color));
+                first_face.push(Some(face_no));
+                vertices.len() as u32 - 1
+            });
+        }
+    }
+
+    // the copies took the corners of the colored faces, so the triangles come first
+    let mut vertex_faces = vec![None; vertices.len()];
+    for (triangle, &face_no) in indices.chunks_exact(3).zip(&triangle_faces) {
+        for &index in triangle {
+            vertex_faces[index as usize].get_or_insert(face_no);
+        }
+    }
+    for (face, first) in vertex_faces.iter_mut().zip(first_face) {
+        *face = face.or(first);
+    }
+
+    let point_cloud = faces.is_empty();
+    let mut model = Model::new(vertices, indices);
+    if file_colors.iter().any(Option::is_some) {
+        model.file_colors = file_colors.into();
+    }
+    model.vertex_faces = vertex_faces.into();
+    model.face_count = faces.len();
+    model.polygons = polygons;
+    model.file_normals = keyword.normals;
+    model.file_texture_coordinates = keyword.texture_coords;
+    if normals.is_none() {
+        model.compute_normals(&options.normals);
+    }
+    model.set_colors(ColorScheme::default());
+    model.line_indices = line_indices.into();
+    model.line_strips = line_strips;
+    model.point_indices = match point_cloud {
+        true => (0..model.vertices.len() as u32).collect(),
+        false => point_indices.into(),
+    };
+    if texture_coords.is_none() {
+        model.project_uvs(&options.uv, |_| true);
+    }
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The unit cube, with a colored point and a colored line next to it
+    const CUBE: &str = "OFF\n10 8 0\n\
+        0 0 0\n1 0 0\n1 1 0\n0 1 0\n0 0 1\n1 0 1\n1 1 1\n0 1 1\n2 2 2\n3 3 3\n\
+        4 3 2 1 0\n4 4 5 6 7\n4 0 1 5 4\n4 2 3 7 6\n4 1 2 6 5\n4 3 0 4 7\n\
+        1 8 1 0 0\n2 8 9 0 1 0\n";
+
+    fn parse(crease_angle: f32) -> Model {
+        let options = OffOptions {
+            normals: NormalOptions {
+                crease_angle,
+                ..NormalOptions::default()
+            },
+            ..OffOptions::default()
+        };
+        parse_off(CUBE.as_bytes(), &options).unwrap()
+    }
+
+    /// Distinct positions and normals of the triangle corners, without the copies made by the
+    /// texture coordinate seams
+    fn shading(model: &Model) -> Vec<([u32; 3], [u32; 3])> {
+        let bits = |v: Vector3<f32>| [v.x, v.y, v.z].map(f32::to_bits);
+        let mut corners: Vec<_> = model
+            .indices
+            .iter()
+            .map(|&i| &model.vertices[i as usize])
+            .map(|v| (bits(v.position), bits(v.normal)))
+            .collect();
+        corners.sort();
+        corners.dedup();
+        corners
+    }
+
+    #[test]
+    fn edges_sharper_than_the_crease_angle() {
+        let model = parse(45.0);
+        assert_eq!(shading(&model).len(), 24);
+        for &i in model.indices.iter() {
+            let n = model.vertices[i as usize].normal;
+            let mut components = [n.x.abs(), n.y.abs(), n.z.abs()];
+            components.sort_by(f32::total_cmp);
+            assert_eq!(components, [0.0, 0.0, 1.0]);
+        }
+        let model = parse(180.0);
+        assert_eq!(shading(&model).len(), 8);
+        for &i in model.indices.iter() {
+            let vertex = &model.vertices[i as usize];
+            let center = Vector3 {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            };
+            let outward = vertex.position - center;
+            assert!(vertex.normal.dot(&outward.normalized()) > 0.999);
+        }
+    }
+
+    #[test]
+    fn points_and_lines_keep_their_face_color() {
+        let model = parse(45.0);
+        let color = |i: u32| {
+            let c = model.vertices[i as usize].color;
+            [c.x, c.y, c.z]
+        };
+        assert_eq!(model.point_indices.len(), 1);
+        assert_eq!(color(model.point_indices[0]), [1.0, 0.0, 0.0]);
+        assert_eq!(model.line_indices.len(), 2);
+        for &i in model.line_indices.iter() {
+            assert_eq!(color(i), [0.0, 1.0, 0.0]);
+        }
+        let faces = |indices: &[u32]| -> Vec<_> {
+            indices
+                .iter()
+                .map(|&i| model.vertex_faces[i as usize])
+                .collect()
+        };
+        assert_eq!(faces(&model.point_indices), [Some(6)]);
+        assert_eq!(faces(&model.line_indices), [Some(7); 2]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::obj::Model;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone)]
+pub struct OffWriteOptions {
+    /// Number of decimals written for every float
+    pub precision: usize,
+    /// Write the vertex normals, as a NOFF file
+    pub normals: bool,
+    /// Write the vertex colors, as a COFF file
+    pub vertex_colors: bool,
+    /// Write the texture coordinates, as a STOFF file
+    pub texture_coords: bool,
+}
+
+impl Default for OffWriteOptions {
+    fn default() -> Self {
+        OffWriteOptions {
+            precision: 6,
+            normals: false,
+            vertex_colors: false,
+            texture_coords: false,
+        }
+    }
+}
+
+/// Writes the model as an ascii off file, with one line per vertex of the model
+/// Triangles are written as faces, polylines as faces of 2 vertices and points as faces of 1
+/// vertex. Submeshes and materials are lost
+pub fn write_off(
+    model: &Model,
+    mut writer: impl Write,
+    options: &OffWriteOptions,
+) -> io::Result<()> {
+    let p = options.precision;
+    let keyword = [
+        (options.texture_coords, "ST"),
+        (options.vertex_colors, "C"),
+        (options.normals, "N"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, prefix)| *prefix)
+    .collect::<String>();
+    let segments: usize = model
+        .line_strips
+        .iter()
+        .map(|s| s.len().saturating_sub(1))
+        .sum();
+    let faces = model.indices.len() / 3 + segments + model.point_indices.len();
+    writeln!(writer, "{keyword}OFF")?;
+    writeln!(writer, "# Written by scop")?;
+    writeln!(writer, "{} {faces} 0", model.vertices.len())?;
+
+    for vertex in &model.vertices {
+        let (position, normal, color) = (vertex.position, vertex.normal, vertex.color);
+        write!(
+            writer,
+            "{:.p$} {:.p$} {:.p$}",
+            position.x, position.y, position.z
+        )?;
+        if options.normals {
+            write!(
+                writer,
+                " {:.p$} {:.p$} {:.p$}",
+                normal.x, normal.y, normal.z
+            )?;
+        }
+        if options.vertex_colors {
+            write!(writer, " {:.p$} {:.p$} {:.p$}", color.x, color.y, color.z)?;
+        }
+        if options.texture_coords {
+            let (u, v) = vertex.texture_coordinates;
+            write!(writer, " {u:.p$} {v:.p$}")?;
+        }
+        writeln!(writer)?;
+    }
+    for triangle in model.indices.chunks_exact(3) {
+        writeln!(writer, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?;
+    }
+    for strip in &model.line_strips {
+        for segment in model.line_indices[strip.clone()].windows(2) {
+            writeln!(writer, "2 {} {}", segment[0], segment[1])?;
+        }
+    }
+    for index in &model.point_indices {
+        writeln!(writer, "1 {index}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,607 @@
+use crate::color::ColorScheme;
+use crate::math::vector::{Vector3, Vector4};
+use crate::mesh::NormalOptions;
+use crate::obj::triangulation::{self, Triangulation};
+use crate::obj::{ErrorType, Model, ParseError, Vertex, VertexAttribute, line_error};
+use crate::uv::UvOptions;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{Error as IOError, Read};
+
+#[derive(Debug)]
+pub enum PlyError {
+    IOError(IOError),
+    /// Invalid line of the header or of an ascii body
+    Parse(ParseError),
+    /// The body ends before element `index` of `element`
+    Truncated { element: String, index: usize },
+    MissingProperty(&'static str),
+    /// Face `face` uses a vertex that does not exist
+    InvalidIndex { face: usize, index: f64 },
+}
+
+impl Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::IOError(e) => write!(f, "{e}"),
+            PlyError::Parse(e) => write!(f, "{e}"),
+            PlyError::Truncated { element, index } => {
+                write!(f, "File ends before {element} #{index}")
+            }
+            PlyError::MissingProperty(p) => write!(f, "Missing property '{p}'"),
+            PlyError::InvalidIndex { face, index } => {
+                write!(f, "Face #{face} uses vertex {index}, which does not exist")
+            }
+        }
+    }
+}
+
+impl Error for PlyError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlyOptions {
+    pub triangulation: Triangulation,
+    /// Projection giving texture coordinates to the files without them
+    pub uv: UvOptions,
+    /// Smoothing of the faces of the files without normals
+    pub normals: NormalOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<ScalarType> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+    /// Value of a full color channel
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+    fn read(self, bytes: &[u8], encoding: Encoding) -> f64 {
+        macro_rules! read {
+            ($t:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                (if encoding == Encoding::BigEndian {
+                    <$t>::from_be_bytes(bytes)
+                } else {
+                    <$t>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+        match self {
+            ScalarType::I8 => read!(i8),
+            ScalarType::U8 => read!(u8),
+            ScalarType::I16 => read!(i16),
+            ScalarType::U16 => read!(u16),
+            ScalarType::I32 => read!(i32),
+            ScalarType::U32 => read!(u32),
+            ScalarType::F32 => read!(f32),
+            ScalarType::F64 => read!(f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+struct ElementDef {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Values of one property for every instance of an element
+enum Column {
+    Scalar(Vec<f64>),
+    /// Items of instance i are `items[starts[i]..starts[i + 1]]`
+    List { starts: Vec<usize>, items: Vec<f64> },
+}
+
+impl Column {
+    fn new(kind: PropertyType) -> Column {
+        match kind {
+            PropertyType::Scalar(_) => Column::Scalar(Vec::new()),
+            PropertyType::List { .. } => Column::List {
+                starts: vec![0],
+                items: Vec::new(),
+            },
+        }
+    }
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<ElementDef>,
+    /// Number of lines of the header, the ascii body starts on the next one
+    lines: usize,
+    /// Byte offset of the body
+    size: usize,
+}
+
+/// Whether `start` is the beginning of a ply file
+pub fn is_ply(start: &[u8]) -> bool {
+    start.starts_with(b"ply\n") || start.starts_with(b"ply\r\n")
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, ParseError> {
+    use ErrorType::*;
+    let mut encoding = None;
+    let mut elements: Vec<ElementDef> = Vec::new();
+    let mut offset = 0;
+    let mut line_no = 0;
+    for raw_line in data.split_inclusive(|&b| b == b'\n') {
+        offset += raw_line.len();
+        line_no += 1;
+        let line = raw_line.trim_ascii_end();
+        macro_rules! error {
+            ($err:expr, $token:expr) => {
+                line_error(line_no, line, $token.as_bytes(), $err)
+            };
+        }
+        let Ok(text) = std::str::from_utf8(line) else {
+            return Err(line_error(line_no, line, line, InvalidLine));
+        };
+        let tokens: Vec<_> = text.split_whitespace().collect();
+        if line_no == 1 {
+            if tokens != ["ply"] {
+                return Err(error!(InvalidLine, text));
+            }
+            continue;
+        }
+        let Some(&keyword) = tokens.first() else {
+            continue;
+        };
+        let args = &tokens[1..];
+        match keyword {
+            "format" => {
+                let [format, _version] = args else {
+                    return Err(error!(InvalidParameterNumber, text));
+                };
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return Err(error!(InvalidParameter(0), format)),
+                });
+            } // format ascii|binary_little_endian|binary_big_endian 1.0
+            "comment" | "obj_info" => {}
+            "element" => {
+                let [name, count] = args else {
+                    return Err(error!(InvalidParameterNumber, text));
+                };
+                let count = count.parse().map_err(|_| error!(InvalidParameter(1), count))?;
+                elements.push(ElementDef {
+                    name: name.to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            } // element name count
+            "property" => {
+                let Some(element) = elements.last_mut() else {
+                    return Err(error!(MissingStatement("element"), keyword));
+                };
+                let scalar = |i: usize| {
+                    ScalarType::parse(args[i]).ok_or_else(|| error!(InvalidParameter(i), args[i]))
+                };
+                let (kind, name) = match args {
+                    ["list", _, _, name] => (
+                        PropertyType::List {
+                            count: scalar(1)?,
+                            item: scalar(2)?,
+                        },
+                        name,
+                    ),
+                    [_, name] => (PropertyType::Scalar(scalar(0)?), name),
+                    _ => return Err(error!(InvalidParameterNumber, text)),
+                };
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind,
+                });
+            } // property type name / property list count_type item_type name
+            "end_header" => {
+                let Some(encoding) = encoding else {
+                    return Err(error!(MissingStatement("format"), keyword));
+                };
+                return Ok(Header {
+                    encoding,
+                    elements,
+                    lines: line_no,
+                    size: offset,
+                });
+            }
+            _ => return Err(error!(InvalidEntry(keyword.into()), keyword)),
+        }
+    }
+    Err(ParseError {
+        line: None,
+        line_no,
+        columns: None,
+        err_type: MissingStatement("end_header"),
+    })
+}
+
+fn read_binary(body: &[u8], header: &Header) -> Result<Vec<Vec<Column>>, PlyError> {
+    let mut position = 0;
+    let mut read = |kind: ScalarType| {
+        let bytes = body.get(position..position + kind.size())?;
+        position += kind.size();
+        Some(kind.read(bytes, header.encoding))
+    };
+    let mut elements = Vec::with_capacity(header.elements.len());
+    for element in &header.elements {
+        let mut columns: Vec<_> = element.properties.iter().map(|p| Column::new(p.kind)).collect();
+        for index in 0..element.count {
+            let truncated = || PlyError::Truncated {
+                element: element.name.clone(),
+                index,
+            };
+            for (property, column) in element.properties.iter().zip(&mut columns) {
+                match (property.kind, column) {
+                    (PropertyType::Scalar(kind), Column::Scalar(values)) => {
+                        values.push(read(kind).ok_or_else(truncated)?)
+                    }
+                    (PropertyType::List { count, item }, Column::List { starts, items }) => {
+                        let count = read(count).ok_or_else(truncated)? as usize;
+                        for _ in 0..count {
+                            items.push(read(item).ok_or_else(truncated)?);
+                        }
+                        starts.push(items.len());
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        elements.push(columns);
+    }
+    Ok(elements)
+}
+
+/// Every instance of an element is on its own line
+fn read_ascii(body: &[u8], header: &Header) -> Result<Vec<Vec<Column>>, PlyError> {
+    use ErrorType::*;
+    let mut lines = body
+        .split(|&b| b == b'\n')
+        .enumerate()
+        .map(|(i, line)| (header.lines + i + 1, line.trim_ascii_end()))
+        .filter(|(_, line)| !line.trim_ascii_start().is_empty());
+    let mut elements = Vec::with_capacity(header.elements.len());
+    for element in &header.elements {
+        let mut columns: Vec<_> = element.properties.iter().map(|p| Column::new(p.kind)).collect();
+        for index in 0..element.count {
+            let Some((line_no, line)) = lines.next() else {
+                return Err(PlyError::Truncated {
+                    element: element.name.clone(),
+                    index,
+                });
+            };
+            macro_rules! error {
+                ($err:expr, $token:expr) => {
+                    PlyError::Parse(line_error(line_no, line, $token, $err))
+                };
+            }
+            let mut tokens = line
+                .split(u8::is_ascii_whitespace)
+                .filter(|token| !token.is_empty())
+                .enumerate();
+            let mut next = || {
+                let (i, token) = tokens.next().ok_or_else(|| error!(InvalidParameterNumber, line))?;
+                std::str::from_utf8(token)
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .ok_or_else(|| error!(InvalidParameter(i), token))
+            };
+            for column in &mut columns {
+                match column {
+                    Column::Scalar(values) => values.push(next()?),
+                    Column::List { starts, items } => {
+                        let count = next()? as usize;
+                        for _ in 0..count {
+                            items.push(next()?);
+                        }
+                        starts.push(items.len());
+                    }
+                }
+            }
+            if tokens.next().is_some() {
+                return Err(error!(InvalidParameterNumber, line));
+            }
+        }
+        elements.push(columns);
+    }
+    Ok(elements)
+}
+
+/// Parses an ascii or binary ply
+/// The `vertex` element gives the vertices of the model, its properties that are not mapped on
+/// `Vertex` are kept in `Model.attributes`. The `face` element is triangulated, and without
+/// faces the vertices are drawn as points
+pub fn parse_ply(mut reader: impl Read, options: &PlyOptions) -> Result<Model, PlyError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(PlyError::IOError)?;
+    let header = parse_header(&data).map_err(PlyError::Parse)?;
+    let body = &data[header.size..];
+    let mut elements = match header.encoding {
+        Encoding::Ascii => read_ascii(body, &header)?,
+        _ => read_binary(body, &header)?,
+    };
+    let find = |name: &str| header.elements.iter().position(|e| e.name == name);
+    let Some(vertex_element) = find("vertex") else {
+        return Ok(Model::new(Vec::new(), Vec::new()));
+    };
+    // scalar properties of the vertices, taken out as they are mapped on `Vertex`
+    let mut columns: Vec<_> = header.elements[vertex_element]
+        .properties
+        .iter()
+        .zip(std::mem::take(&mut elements[vertex_element]))
+        .filter_map(|(property, column)| match (property.kind, column) {
+            (PropertyType::Scalar(kind), Column::Scalar(values)) => {
+                Some((property.name.as_str(), kind, values))
+            }
+            _ => None,
+        })
+        .map(Some)
+        .collect();
+    fn take<const N: usize>(
+        columns: &mut [Option<(&str, ScalarType, Vec<f64>)>],
+        names: [&str; N],
+    ) -> Option<[(ScalarType, Vec<f64>); N]> {
+        let found = names.map(|name| columns.iter().position(|c| c.as_ref().is_some_and(|c| c.0 == name)));
+        let found: [usize; N] = found.iter().copied().collect::<Option<Vec<_>>>()?.try_into().ok()?;
+        Some(found.map(|i| {
+            let (_, kind, values) = columns[i].take().unwrap();
+            (kind, values)
+        }))
+    }
+    let to_vectors = |[(kx, x), (ky, y), (kz, z)]: [(ScalarType, Vec<f64>); 3], scale: bool| {
+        let scale = |kind: ScalarType| if scale { kind.color_scale() } else { 1.0 };
+        (0..x.len())
+            .map(|i| Vector3 {
+                x: (x[i] / scale(kx)) as f32,
+                y: (y[i] / scale(ky)) as f32,
+                z: (z[i] / scale(kz)) as f32,
+            })
+            .collect::<Vec<_>>()
+    };
+    let positions = to_vectors(
+        take(&mut columns, ["x", "y", "z"]).ok_or(PlyError::MissingProperty("x, y, z"))?,
+        false,
+    );
+    let normals = take(&mut columns, ["nx", "ny", "nz"]).map(|n| to_vectors(n, false));
+    let colors = take(&mut columns, ["red", "green", "blue"]).map(|c| to_vectors(c, true));
+    let texture_coords = take(&mut columns, ["s", "t"])
+        .or_else(|| take(&mut columns, ["u", "v"]))
+        .map(|[(_, u), (_, v)]| {
+            u.into_iter().zip(v).map(|(u, v)| (u as f32, v as f32)).collect::<Vec<_>>()
+        });
+    let attributes: Vec<_> = columns
+        .into_iter()
+        .flatten()
+        .map(|(name, _, values)| VertexAttribute {
+            name: name.into(),
+            values: values.into(),
+        })
+        .collect();
+
+    let mut indices = Vec::new();
+    let mut line_indices = Vec::new();
+    let mut line_strips = Vec::new();
+    let mut point_indices = Vec::new();
+    let mut polygons = Vec::new();
+    // first face using each vertex, for its color
+    let mut first_face = vec![None; positions.len()];
+    let mut face_count = 0;
+    if let Some(face_element) = find("face") {
+        let properties = &header.elements[face_element].properties;
+        let list = properties
+            .iter()
+            .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+            .filter(|&i| matches!(properties[i].kind, PropertyType::List { .. }))
+            .ok_or(PlyError::MissingProperty("vertex_indices"))?;
+        let Column::List { starts, items } = &elements[face_element][list] else {
+            unreachable!()
+        };
+        face_count = starts.len() - 1;
+        let mut polygon = Vec::new();
+        let mut polygon_positions = Vec::new();
+        for (face, range) in starts.windows(2).enumerate() {
+            polygon.clear();
+            for &index in &items[range[0]..range[1]] {
+                if index < 0.0 || index as usize >= positions.len() || index.fract() != 0.0 {
+                    return Err(PlyError::InvalidIndex { face, index });
+                }
+                polygon.push(index as u32);
+            }
+            match polygon.len() {
+                0 => {}
+                1 => point_indices.push(polygon[0]),
+                2 => {
+                    line_strips.push(line_indices.len()..line_indices.len() + 2);
+                    line_indices.extend(&polygon);
+                }
+                _ => {
+                    polygon_positions.clear();
+                    polygon_positions.extend(polygon.iter().map(|&i| positions[i as usize]));
+                    let (triangles, _) =
+                        triangulation::triangulate(options.triangulation, &polygon_positions);
+                    let start = indices.len();
+                    for triangle in triangles {
+                        indices.extend(triangle.map(|corner| polygon[corner]));
+                    }
+                    if polygon.len() > 3 && indices.len() > start {
+                        polygons.push(start..indices.len());
+                    }
+                }
+            }
+            for &index in &polygon {
+                first_face[index as usize].get_or_insert(face);
+            }
+        }
+    }
+
+    let has_normals = normals.is_some();
+    let normals = normals.unwrap_or_else(|| vec![Vector3::zero(); positions.len()]);
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| Vertex {
+            position,
+            color: Vector3::zero(),
+            texture_coordinates: texture_coords.as_ref().map_or((0.0, 0.0), |uv| uv[i]),
+            normal: if normals[i].norm2() > 0.0 {
+                normals[i].normalized()
+            } else {
+                normals[i]
+            },
+            tangent: Vector4::zero(),
+        })
+        .collect();
+    let point_cloud = face_count == 0;
+    let mut model = Model::new(vertices, indices);
+    if let Some(colors) = colors {
+        model.file_colors = colors.into_iter().map(Some).collect();
+    }
+    model.vertex_faces = first_face
+        .into_iter()
+        .map(|f| f.map(|f| f as u32))
+        .collect();
+    model.face_count = face_count;
+    model.polygons = polygons;
+    model.attributes = attributes;
+    model.file_normals = has_normals;
+    model.file_texture_coordinates = texture_coords.is_some();
+    if !has_normals {
+        model.compute_normals(&options.normals);
+    }
+    model.set_colors(ColorScheme::default());
+    model.line_indices = line_indices.into();
+    model.line_strips = line_strips;
+    model.point_indices = match point_cloud {
+        true => (0..model.vertices.len() as u32).collect(),
+        false => point_indices.into(),
+    };
+    if texture_coords.is_none() {
+        model.project_uvs(&options.uv, |_| true);
+    }
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(faces: &str, face_count: usize) -> Model {
+        let source = format!(
+            "ply\nformat ascii 1.0\nelement vertex 5\nproperty float x\nproperty float y\n\
+             property float z\nelement face {face_count}\nproperty list uchar int vertex_indices\n\
+             end_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n2 2 2\n{faces}"
+        );
+        parse_ply(source.as_bytes(), &PlyOptions::default()).unwrap()
+    }
+
+    fn position(model: &Model, index: u32) -> [f32; 3] {
+        let p = model.vertices[index as usize].position;
+        [p.x, p.y, p.z]
+    }
+
+    #[test]
+    fn short_faces_are_points_and_lines() {
+        let model = parse("0\n1 4\n2 0 2\n3 0 1 2\n", 4);
+        assert_eq!(model.indices.len(), 3);
+        assert_eq!(model.point_indices.len(), 1);
+        assert_eq!(position(&model, model.point_indices[0]), [2.0, 2.0, 2.0]);
+        assert_eq!(model.line_strips.len(), 1);
+        assert_eq!(model.line_strips[0], 0..2);
+        let line = model.line_indices.iter().map(|&i| position(&model, i));
+        assert_eq!(line.collect::<Vec<_>>(), [[0.0; 3], [1.0, 1.0, 0.0]]);
+        assert_eq!(model.face_count, 4);
+    }
+
+    #[test]
+    fn no_faces_is_a_point_cloud() {
+        let model = parse("", 0);
+        assert!(model.indices.is_empty());
+        assert_eq!(model.point_indices.len(), 5);
+        let model = parse("0\n0\n", 2);
+        assert!(model.indices.is_empty() && model.point_indices.is_empty());
+    }
+
+    #[test]
+    fn creases_of_the_normal_options() {
+        // two faces folded at a right angle along the x axis
+        let source = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\n\
+             property float y\nproperty float z\nelement face 2\n\
+             property list uchar int vertex_indices\nend_header\n\
+             0 0 0\n1 0 0\n0 1 0\n0 0 1\n3 0 1 2\n3 0 3 1\n";
+        let shading = |crease_angle: f32| {
+            let options = PlyOptions {
+                normals: NormalOptions {
+                    crease_angle,
+                    ..NormalOptions::default()
+                },
+                ..PlyOptions::default()
+            };
+            let model = parse_ply(source.as_bytes(), &options).unwrap();
+            let mut corners: Vec<_> = (model.indices.iter())
+                .map(|&i| &model.vertices[i as usize])
+                .map(|v| [v.position, v.normal].map(|p| [p.x, p.y, p.z].map(f32::to_bits)))
+                .collect();
+            corners.sort();
+            corners.dedup();
+            corners.len()
+        };
+        assert_eq!(shading(45.0), 6);
+        assert_eq!(shading(180.0), 4);
+    }
+}
@@ -0,0 +1,465 @@
+mod writer;
+
+pub use writer::{StlWriteOptions, write_stl};
+
+use crate::color::ColorScheme;
+use crate::math::vector::{Vector3, Vector4};
+use crate::mesh::{self, NormalOptions};
+use crate::obj::{ErrorType, Model, ParseError, Submesh, Vertex, line_error};
+use crate::uv::UvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{Error as IOError, Read};
+use std::ops::Range;
+
+/// 80 bytes of header and the triangle count
+const HEADER_SIZE: usize = 84;
+/// Normal, 3 corners and the attribute byte count
+const FACET_SIZE: usize = 50;
+
+#[derive(Debug)]
+pub enum StlError {
+    IOError(IOError),
+    /// Binary file shorter than what its triangle count needs
+    Truncated {
+        triangles: u32,
+        size: usize,
+    },
+    /// Invalid line of an ascii file
+    Parse(ParseError),
+}
+
+impl Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::IOError(e) => write!(f, "{e}"),
+            StlError::Truncated { triangles, size } => write!(
+                f,
+                "Binary stl of {size} bytes is too short for its {triangles} triangles"
+            ),
+            StlError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for StlError {}
+
+#[derive(Debug, Clone)]
+pub struct StlOptions {
+    /// Merge the corners shared by several facets into a single vertex, with the smooth normal
+    /// of the facets around it. Without it every facet is flat
+    pub weld: bool,
+    /// Smoothing of the welded facets, edges sharper than the crease angle stay sharp
+    pub normals: NormalOptions,
+    /// Projection giving texture coordinates to the facets, stl files have none
+    pub uv: UvOptions,
+}
+
+impl Default for StlOptions {
+    fn default() -> Self {
+        StlOptions {
+            weld: true,
+            normals: NormalOptions::default(),
+            uv: UvOptions::default(),
+        }
+    }
+}
+
+struct Facet {
+    /// As written in the file, often zero
+    normal: Vector3<f32>,
+    corners: [Vector3<f32>; 3],
+    color: Option<Vector3<f32>>,
+}
+
+/// Whether `start`, the first bytes of a file of `size` bytes, looks like an ascii or binary stl
+pub fn is_stl(start: &[u8], size: u64) -> bool {
+    is_ascii(start) || binary_size(start) == Some(size)
+}
+
+fn is_ascii(start: &[u8]) -> bool {
+    let start = start.trim_ascii_start();
+    start.starts_with(b"solid") && start.get(5).is_none_or(|b| b.is_ascii_whitespace())
+}
+
+/// Size of a binary stl, according to its triangle count
+fn binary_size(data: &[u8]) -> Option<u64> {
+    let count = data.get(80..HEADER_SIZE)?;
+    let count = u32::from_le_bytes(count.try_into().unwrap());
+    Some(HEADER_SIZE as u64 + FACET_SIZE as u64 * count as u64)
+}
+
+/// Parses an ascii or binary stl
+/// Binary files can start with `solid` too, so the size of the file and its content are checked
+/// before parsing it as ascii
+pub fn parse_stl(mut reader: impl Read, options: &StlOptions) -> Result<Model, StlError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(StlError::IOError)?;
+    let text = if binary_size(&data) == Some(data.len() as u64) || !is_ascii(&data) {
+        None
+    } else {
+        std::str::from_utf8(&data).ok()
+    };
+    let (facets, solids) = match text {
+        Some(text) => parse_ascii(text).map_err(StlError::Parse)?,
+        None => {
+            let facets = parse_binary(&data)?;
+            let solids = vec![(String::new(), 0..facets.len())];
+            (facets, solids)
+        }
+    };
+    Ok(build_model(&facets, solids, options))
+}
+
+/// Color of the attribute bytes of a facet
+/// VisCAM and SolidView set the top bit for a BGR color, Materialise Magics clears it for a RGB
+/// color and sets it for the default color of the header
+fn facet_color(attribute: u16, magics_color: Option<Vector3<f32>>) -> Option<Vector3<f32>> {
+    let channel = |shift: u16| ((attribute >> shift) & 0x1f) as f32 / 31.0;
+    let valid = attribute & 0x8000 != 0;
+    match magics_color {
+        Some(default) if valid => Some(default),
+        Some(_) => Some(Vector3 {
+            x: channel(0),
+            y: channel(5),
+            z: channel(10),
+        }),
+        None if valid => Some(Vector3 {
+            x: channel(10),
+            y: channel(5),
+            z: channel(0),
+        }),
+        None => None,
+    }
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<Facet>, StlError> {
+    let truncated = |triangles| StlError::Truncated {
+        triangles,
+        size: data.len(),
+    };
+    let Some(size) = binary_size(data) else {
+        return Err(truncated(0));
+    };
+    let count = ((size - HEADER_SIZE as u64) / FACET_SIZE as u64) as u32;
+    if (data.len() as u64) < size {
+        return Err(truncated(count));
+    }
+    // Materialise Magics writes `COLOR=` and RGBA bytes in the header
+    let header = &data[..80];
+    let magics_color = header
+        .windows(10)
+        .find_map(|w| w.strip_prefix(b"COLOR="))
+        .map(|rgba| Vector3 {
+            x: rgba[0] as f32 / 255.0,
+            y: rgba[1] as f32 / 255.0,
+            z: rgba[2] as f32 / 255.0,
+        });
+    let vector = |bytes: &[u8]| {
+        let [x, y, z] = [0, 4, 8].map(|i| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()));
+        Vector3 { x, y, z }
+    };
+    Ok(data[HEADER_SIZE..size as usize]
+        .chunks_exact(FACET_SIZE)
+        .map(|facet| Facet {
+            normal: vector(&facet[0..12]),
+            corners: [12, 24, 36].map(|i| vector(&facet[i..i + 12])),
+            color: facet_color(u16::from_le_bytes([facet[48], facet[49]]), magics_color),
+        })
+        .collect())
+}
+
+/// Facets of an ascii stl, and the range of facets of each solid with its name
+/// Loops of more than 3 vertices are split in a fan
+#[allow(clippy::type_complexity)]
+fn parse_ascii(text: &str) -> Result<(Vec<Facet>, Vec<(String, Range<usize>)>), ParseError> {
+    use ErrorType::*;
+    let mut facets = Vec::new();
+    let mut solids: Vec<(String, Range<usize>)> = Vec::new();
+    let mut in_solid = false;
+    let mut normal = None;
+    let mut in_loop = false;
+    let mut corners = Vec::new();
+    let mut line_count = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        line_count = line_no;
+        macro_rules! error {
+            ($err:expr, $token:expr) => {
+                line_error(line_no, line.as_bytes(), $token.as_bytes(), $err)
+            };
+        }
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest =
+            line[keyword.as_ptr() as usize - line.as_ptr() as usize + keyword.len()..].trim();
+        let args: Vec<_> = tokens.collect();
+        // the 3 floats of `args` starting at `first`
+        let floats = |first: usize| -> Result<Vector3<f32>, ParseError> {
+            if args.len() != first + 3 {
+                return Err(error!(InvalidParameterNumber, rest));
+            }
+            let mut values = [0.0; 3];
+            for (i, arg) in args.iter().enumerate().skip(first) {
+                values[i - first] = arg.parse().map_err(|_| error!(InvalidParameter(i), arg))?;
+            }
+            let [x, y, z] = values;
+            Ok(Vector3 { x, y, z })
+        };
+        match keyword {
+            "solid" => {
+                if in_solid {
+                    return Err(error!(MissingStatement("endsolid"), keyword));
+                }
+                in_solid = true;
+                solids.push((rest.into(), facets.len()..facets.len()));
+            } // solid [name]
+            "facet" => {
+                if !in_solid {
+                    return Err(error!(MissingStatement("solid"), keyword));
+                }
+                if normal.is_some() {
+                    return Err(error!(MissingStatement("endfacet"), keyword));
+                }
+                match args.first() {
+                    Some(&"normal") => normal = Some(floats(1)?),
+                    Some(other) => return Err(error!(InvalidParameter(0), other)),
+                    None => return Err(error!(InvalidParameterNumber, rest)),
+                }
+            } // facet normal nx ny nz
+            "outer" => {
+                if normal.is_none() {
+                    return Err(error!(MissingStatement("facet"), keyword));
+                }
+                if in_loop {
+                    return Err(error!(MissingStatement("endloop"), keyword));
+                }
+                if args != ["loop"] {
+                    return Err(error!(InvalidParameterNumber, rest));
+                }
+                in_loop = true;
+            } // outer loop
+            "vertex" => {
+                if !in_loop {
+                    return Err(error!(MissingStatement("outer loop"), keyword));
+                }
+                corners.push(floats(0)?);
+            } // vertex x y z
+            "endloop" => {
+                if !in_loop {
+                    return Err(error!(MissingStatement("outer loop"), keyword));
+                }
+                in_loop = false;
+            }
+            "endfacet" => {
+                let Some(facet_normal) = normal.take() else {
+                    return Err(error!(MissingStatement("facet"), keyword));
+                };
+                if in_loop {
+                    return Err(error!(MissingStatement("endloop"), keyword));
+                }
+                if corners.len() < 3 {
+                    return Err(error!(InvalidParameterNumber, keyword));
+                }
+                facets.extend((2..corners.len()).map(|k| Facet {
+                    normal: facet_normal,
+                    corners: [corners[0], corners[k - 1], corners[k]],
+                    color: None,
+                }));
+                corners.clear();
+            }
+            "endsolid" => {
+                if !in_solid {
+                    return Err(error!(MissingStatement("solid"), keyword));
+                }
+                if normal.is_some() {
+                    return Err(error!(MissingStatement("endfacet"), keyword));
+                }
+                in_solid = false;
+                if let Some((_, range)) = solids.last_mut() {
+                    range.end = facets.len();
+                }
+            } // endsolid [name]
+            _ => return Err(error!(InvalidEntry(keyword.into()), keyword)),
+        }
+    }
+    if normal.is_some() {
+        return Err(ParseError {
+            line: None,
+            line_no: line_count,
+            columns: None,
+            err_type: MissingStatement("endfacet"),
+        });
+    }
+    // a missing endsolid is tolerated
+    if in_solid && let Some((_, range)) = solids.last_mut() {
+        range.end = facets.len();
+    }
+    Ok((facets, solids))
+}
+
+fn build_model(
+    facets: &[Facet],
+    solids: Vec<(String, Range<usize>)>,
+    options: &StlOptions,
+) -> Model {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity(facets.len() * 3);
+    // first facet using each vertex, for its color
+    let mut first_facet = Vec::new();
+    let mut welded: HashMap<([u32; 3], Option<[u32; 3]>), u32> = HashMap::new();
+    let bits = |v: Vector3<f32>| [v.x, v.y, v.z].map(f32::to_bits);
+    for (facet_no, facet) in facets.iter().enumerate() {
+        let [a, b, c] = facet.corners;
+        let winding = (b - a).cross(&(c - a));
+        let normal = if facet.normal.norm2() > 0.0 && facet.normal.norm2().is_finite() {
+            facet.normal.normalized()
+        } else if winding.norm2() > 0.0 {
+            winding.normalized()
+        } else {
+            winding
+        };
+        for position in facet.corners {
+            let mut new_vertex = || {
+                vertices.push(Vertex {
+                    position,
+                    color: Vector3::zero(),
+                    texture_coordinates: (0.0, 0.0),
+                    normal,
+                    tangent: Vector4::zero(),
+                });
+                first_facet.push(facet_no);
+                (vertices.len() - 1) as u32
+            };
+            let index = if options.weld {
+                let key = (bits(position), facet.color.map(bits));
+                *welded.entry(key).or_insert_with(new_vertex)
+            } else {
+                new_vertex()
+            };
+            indices.push(index);
+        }
+    }
+    if options.weld {
+        // the facets around a welded vertex are only smoothed together within the crease angle
+        let sources = mesh::smooth_normals(&mut vertices, &mut indices, &options.normals, |_| true);
+        first_facet = mesh::with_copies(&first_facet, &sources);
+    }
+    let mut model = Model::new(vertices, indices);
+    // facets are the triangles of the model, only their colors are missing
+    if facets.iter().any(|facet| facet.color.is_some()) {
+        model.file_colors = first_facet.iter().map(|&f| facets[f].color).collect();
+    }
+    model.file_normals = true;
+    model.set_colors(ColorScheme::default());
+    model.project_uvs(&options.uv, |_| true);
+    model.submeshes = solids
+        .into_iter()
+        .filter(|(_, range)| !range.is_empty())
+        .map(|(name, range)| Submesh {
+            object: name,
+            group: "default".into(),
+            indices: range.start * 3..range.end * 3,
+        })
+        .collect();
+    model
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ascii stl of the unit cube, two facets per side
+    fn cube() -> String {
+        let mut stl = String::from("solid cube\n");
+        for axis in 0..3 {
+            for side in [0.0, 1.0] {
+                // corners of the side, counterclockwise seen from outside
+                let mut square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+                if side == 0.0 {
+                    square.reverse();
+                }
+                let corner = |(u, v): (f32, f32)| {
+                    let mut p = [0.0; 3];
+                    p[axis] = side;
+                    p[(axis + 1) % 3] = u;
+                    p[(axis + 2) % 3] = v;
+                    format!("vertex {} {} {}\n", p[0], p[1], p[2])
+                };
+                for triangle in [[0, 1, 2], [0, 2, 3]] {
+                    stl += "facet normal 0 0 0\nouter loop\n";
+                    for i in triangle {
+                        stl += &corner(square[i]);
+                    }
+                    stl += "endloop\nendfacet\n";
+                }
+            }
+        }
+        stl + "endsolid cube\n"
+    }
+
+    fn parse(options: &StlOptions) -> Model {
+        parse_stl(cube().as_bytes(), options).unwrap()
+    }
+
+    /// Number of vertices, without the copies made by the texture coordinate seams
+    fn shading_vertices(model: &Model) -> usize {
+        let bits = |v: Vector3<f32>| [v.x, v.y, v.z].map(f32::to_bits);
+        (model.vertices.iter())
+            .map(|v| (bits(v.position), bits(v.normal)))
+            .collect::<std::collections::HashSet<_>>()
+            .len()
+    }
+
+    #[test]
+    fn welded_cube_keeps_its_edges() {
+        let model = parse(&StlOptions::default());
+        assert_eq!(model.indices.len(), 36);
+        assert_eq!(shading_vertices(&model), 24);
+        for triangle in model.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| model.vertices[triangle[k] as usize].position);
+            let facet = (b - a).cross(&(c - a)).normalized();
+            for &i in triangle {
+                let normal = model.vertices[i as usize].normal;
+                assert!((normal - facet).norm() < 1e-6, "{normal:?} on {facet:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn welded_cube_smoothed_past_the_crease_angle() {
+        let options = StlOptions {
+            normals: NormalOptions {
+                crease_angle: 180.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let model = parse(&options);
+        assert_eq!(shading_vertices(&model), 8);
+        for vertex in &model.vertices {
+            // pointing away from the center, along the diagonal
+            let outward = (vertex.position * 2.0
+                - Vector3 {
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                })
+            .normalized();
+            assert!((vertex.normal - outward).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn unwelded_facets_are_flat() {
+        let options = StlOptions {
+            weld: false,
+            ..Default::default()
+        };
+        let model = parse(&options);
+        assert_eq!(model.vertices.len(), 36);
+    }
+}
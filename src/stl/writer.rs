@@ -0,0 +1,147 @@
+use super::{FACET_SIZE, HEADER_SIZE};
+use crate::math::vector::Vector3;
+use crate::obj::Model;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Default)]
+pub struct StlWriteOptions {
+    /// Write the average vertex color of each facet in its attribute bytes, the VisCAM and
+    /// SolidView way
+    pub colors: bool,
+}
+
+/// 15 bits BGR color with the top bit set
+fn facet_attribute(color: Vector3<f32>) -> u16 {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 31.0).round() as u16;
+    0x8000 | channel(color.x) << 10 | channel(color.y) << 5 | channel(color.z)
+}
+
+/// Writes the triangles of the model as a binary stl
+/// Stl has no shared vertices, so only the positions are kept, with the flat normal of each facet.
+/// Lines and points are dropped
+pub fn write_stl(
+    model: &Model,
+    mut writer: impl Write,
+    options: &StlWriteOptions,
+) -> io::Result<()> {
+    let count = u32::try_from(model.indices.len() / 3).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Too many triangles for a stl file",
+        )
+    })?;
+    // binary files must not start with `solid`
+    let mut header = [b' '; HEADER_SIZE];
+    header[..17].copy_from_slice(b"Binary stl (scop)");
+    header[80..].copy_from_slice(&count.to_le_bytes());
+    writer.write_all(&header)?;
+
+    let mut facet = [0; FACET_SIZE];
+    for triangle in model.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &model.vertices[triangle[i] as usize]);
+        let normal = (b.position - a.position).cross(&(c.position - a.position));
+        let normal = if normal.norm2() > 0.0 {
+            normal.normalized()
+        } else {
+            normal
+        };
+        let vectors = [&normal, &a.position, &b.position, &c.position];
+        for (i, v) in vectors.iter().enumerate() {
+            for (j, component) in [v.x, v.y, v.z].iter().enumerate() {
+                let offset = i * 12 + j * 4;
+                facet[offset..offset + 4].copy_from_slice(&component.to_le_bytes());
+            }
+        }
+        let attribute = if options.colors {
+            facet_attribute((a.color + b.color + c.color) / 3.0)
+        } else {
+            0
+        };
+        facet[48..].copy_from_slice(&attribute.to_le_bytes());
+        writer.write_all(&facet)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vector::Vector4;
+    use crate::obj::Vertex;
+    use crate::stl::{StlOptions, parse_stl};
+
+    fn array(v: Vector3<f32>) -> [f32; 3] {
+        [v.x, v.y, v.z].map(|f| (f * 1e5).round() / 1e5)
+    }
+
+    /// A square and a tilted triangle, each triangle with its own corners, of a color that 5 bits
+    /// keep
+    fn model() -> Model {
+        let triangles = [
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+            [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+            [[2.0, 0.0, 0.0], [3.0, 0.0, 1.0], [2.0, 1.0, 1.0]],
+        ];
+        let colors = [
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [3.0 / 31.0, 16.0 / 31.0, 1.0],
+        ];
+        let vertices = triangles
+            .iter()
+            .zip(colors)
+            .flat_map(|(triangle, [r, g, b])| {
+                triangle.map(|[x, y, z]| Vertex {
+                    position: Vector3 { x, y, z },
+                    color: Vector3 { x: r, y: g, z: b },
+                    texture_coordinates: (0.0, 0.0),
+                    normal: Vector3::zero(),
+                    tangent: Vector4::zero(),
+                })
+            })
+            .collect();
+        Model::new(vertices, (0..9).collect())
+    }
+
+    fn round_trip(model: &Model, colors: bool) -> Model {
+        let mut stl = Vec::new();
+        write_stl(model, &mut stl, &StlWriteOptions { colors }).unwrap();
+        let options = StlOptions {
+            weld: false,
+            ..StlOptions::default()
+        };
+        parse_stl(stl.as_slice(), &options).unwrap()
+    }
+
+    #[test]
+    fn same_facets() {
+        let model = model();
+        let copy = round_trip(&model, true);
+        assert_eq!(*copy.indices, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(copy.submeshes.len(), 1);
+        assert_eq!(copy.submeshes[0].indices, 0..9);
+        let normals = [[0.0, 0.0, 1.0]; 2].into_iter();
+        let normals = normals.chain([[-0.57735, -0.57735, 0.57735]]);
+        for ((triangle, copied), normal) in model
+            .indices
+            .chunks_exact(3)
+            .zip(copy.indices.chunks_exact(3))
+            .zip(normals)
+        {
+            for (&i, &j) in triangle.iter().zip(copied) {
+                let (vertex, copied) = (&model.vertices[i as usize], &copy.vertices[j as usize]);
+                assert_eq!(array(copied.position), array(vertex.position));
+                assert_eq!(array(copied.normal), normal);
+                assert_eq!(array(copied.color), array(vertex.color));
+            }
+        }
+    }
+
+    #[test]
+    fn colors_only_with_the_option() {
+        let copy = round_trip(&model(), false);
+        assert!(copy.file_colors.is_empty());
+        let copy = round_trip(&model(), true);
+        assert!(copy.file_colors.iter().all(Option::is_some));
+    }
+}
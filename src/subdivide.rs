@@ -0,0 +1,681 @@
+use crate::math::vector::{Vector3, Vector4};
+use crate::mesh::{self, NormalOptions};
+use crate::obj::{MaterialRange, Model, Submesh, Vertex, VertexAttribute};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::TAU;
+use std::ops::Range;
+
+/// How the faces are split and the positions smoothed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Each triangle in 4, for triangle meshes
+    Loop,
+    /// Each face of n corners in n quads, for quad and polygon meshes
+    CatmullClark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubdivideOptions {
+    /// Times the faces are split
+    pub levels: usize,
+    /// None for Catmull-Clark on the models with polygons, and Loop on the others
+    pub scheme: Option<Scheme>,
+    /// Edges between faces meeting at a larger angle, in degrees, stay sharp. Without it only
+    /// the borders do
+    pub crease_angle: Option<f32>,
+    /// Smoothing of the subdivided faces
+    pub normals: NormalOptions,
+}
+
+impl Default for SubdivideOptions {
+    fn default() -> Self {
+        SubdivideOptions {
+            levels: 1,
+            scheme: None,
+            crease_angle: None,
+            normals: NormalOptions::default(),
+        }
+    }
+}
+
+/// Faces over welded positions, whose corners are wedges : a position with the values
+/// interpolated over the faces, which are the texture coordinates, the file color and the
+/// attributes of the model. Vertices of the model with the same position and values are one
+/// wedge, so faces split by their normals are smoothed together
+struct Mesh {
+    positions: Vec<Vector3<f32>>,
+    wedge_positions: Vec<u32>,
+    /// `stride` values per wedge
+    values: Vec<f64>,
+    stride: usize,
+    /// Wedges of the corners of every face, face after face
+    corners: Vec<u32>,
+    /// Start of every face in `corners`, followed by the end of the last one
+    starts: Vec<usize>,
+    /// Face of the model each face comes from
+    parents: Vec<u32>,
+    /// Edges that stay sharp, as their positions with the smallest first
+    sharp: HashSet<(u32, u32)>,
+}
+
+/// Edges of a mesh, with the faces on each of them
+struct Edges {
+    /// Positions of each edge, the smallest first
+    ends: Vec<(u32, u32)>,
+    /// First two faces of each edge, with the number of faces it has
+    faces: Vec<([u32; 2], u32)>,
+    /// Edge from every corner to the next corner of its face
+    corner_edges: Vec<u32>,
+}
+
+impl Mesh {
+    /// Mesh of the polygons and triangles of the model, or of its triangles only when
+    /// `polygons` is false, with the range of `model.indices` of each face
+    fn new(model: &Model, polygons: bool) -> (Mesh, Vec<Range<usize>>) {
+        let mut position_ids = HashMap::new();
+        let mut positions = Vec::new();
+        let vertex_positions: Vec<u32> = (model.vertices.iter())
+            .map(|vertex| {
+                let p = vertex.position;
+                *position_ids
+                    .entry([p.x, p.y, p.z].map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(p);
+                        positions.len() as u32 - 1
+                    })
+            })
+            .collect();
+
+        let colors = !model.file_colors.is_empty();
+        let stride = 2 + if colors { 3 } else { 0 } + model.attributes.len();
+        let mut mesh = Mesh {
+            positions,
+            wedge_positions: Vec::new(),
+            values: Vec::new(),
+            stride,
+            corners: Vec::new(),
+            starts: vec![0],
+            parents: Vec::new(),
+            sharp: HashSet::new(),
+        };
+        let mut wedge_ids = HashMap::new();
+        let mut values = Vec::with_capacity(stride);
+        let vertex_wedges: Vec<u32> = (0..model.vertices.len())
+            .map(|v| {
+                let (s, t) = model.vertices[v].texture_coordinates;
+                values.clear();
+                values.extend([s, t].map(f64::from));
+                if colors {
+                    // NaN stays NaN once interpolated, and goes back to no color
+                    let color = model.file_colors[v].map_or([f32::NAN; 3], |c| [c.x, c.y, c.z]);
+                    values.extend(color.map(f64::from));
+                }
+                values.extend(model.attributes.iter().map(|a| a.values[v]));
+                let key = (
+                    vertex_positions[v],
+                    values.iter().map(|x| x.to_bits()).collect::<Vec<_>>(),
+                );
+                *wedge_ids.entry(key).or_insert_with(|| {
+                    mesh.wedge_positions.push(vertex_positions[v]);
+                    mesh.values.extend(&values);
+                    mesh.wedge_positions.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let mut ranges = Vec::new();
+        let mut push_face = |mesh: &mut Mesh, corners: &[u32], range: Range<usize>| {
+            mesh.corners
+                .extend(corners.iter().map(|&v| vertex_wedges[v as usize]));
+            mesh.starts.push(mesh.corners.len());
+            mesh.parents.push(ranges.len() as u32);
+            ranges.push(range);
+        };
+        let polygons = if polygons { &model.polygons[..] } else { &[] };
+        let mut next = 0;
+        let mut start = 0;
+        while start < model.indices.len() {
+            while next < polygons.len() && polygons[next].start < start {
+                next += 1;
+            }
+            let face = match polygons.get(next) {
+                Some(polygon) if polygon.start == start && polygon.end > start => polygon.clone(),
+                _ => start..start + 3,
+            };
+            let triangles = &model.indices[face.clone()];
+            let outline = (face.len() > 3)
+                .then(|| mesh::polygon_outline(triangles, |v| vertex_positions[v as usize]))
+                .flatten();
+            match outline {
+                Some(corners) => push_face(&mut mesh, &corners, face.clone()),
+                // triangles, and polygons that can't be rebuilt from their triangles
+                _ => {
+                    for (k, triangle) in triangles.chunks_exact(3).enumerate() {
+                        let at = face.start + 3 * k;
+                        push_face(&mut mesh, triangle, at..at + 3);
+                    }
+                }
+            }
+            start = face.end;
+        }
+        (mesh, ranges)
+    }
+
+    fn face_count(&self) -> usize {
+        self.parents.len()
+    }
+
+    fn face(&self, f: usize) -> &[u32] {
+        &self.corners[self.starts[f]..self.starts[f + 1]]
+    }
+
+    fn position(&self, wedge: u32) -> Vector3<f32> {
+        self.positions[self.wedge_positions[wedge as usize] as usize]
+    }
+
+    fn edges(&self) -> Edges {
+        let mut ids = HashMap::new();
+        let mut edges = Edges {
+            ends: Vec::new(),
+            faces: Vec::new(),
+            corner_edges: Vec::with_capacity(self.corners.len()),
+        };
+        for f in 0..self.face_count() {
+            let face = self.face(f);
+            for k in 0..face.len() {
+                let [a, b] =
+                    [face[k], face[(k + 1) % face.len()]].map(|w| self.wedge_positions[w as usize]);
+                let key = (a.min(b), a.max(b));
+                let id = *ids.entry(key).or_insert_with(|| {
+                    edges.ends.push(key);
+                    edges.faces.push(([0; 2], 0));
+                    edges.ends.len() as u32 - 1
+                });
+                let (faces, count) = &mut edges.faces[id as usize];
+                if *count < 2 {
+                    faces[*count as usize] = f as u32;
+                }
+                *count += 1;
+                edges.corner_edges.push(id);
+            }
+        }
+        edges
+    }
+
+    /// Whether the edge is between two faces, without being sharp
+    fn smooth(&self, edges: &Edges, e: usize) -> bool {
+        edges.faces[e].1 == 2 && !self.sharp.contains(&edges.ends[e])
+    }
+
+    /// Makes the edges between faces meeting at more than `angle` degrees sharp
+    fn tag_creases(&mut self, angle: f32) {
+        // Newell normals, for polygons that are not flat
+        let normals: Vec<Vector3<f32>> = (0..self.face_count())
+            .map(|f| {
+                let face = self.face(f);
+                (0..face.len())
+                    .map(|k| {
+                        let [a, b] =
+                            [face[k], face[(k + 1) % face.len()]].map(|w| self.position(w));
+                        a.cross(&b)
+                    })
+                    .fold(Vector3::zero(), |sum, n| sum + n)
+            })
+            .collect();
+        let cos_crease = angle.to_radians().cos();
+        let edges = self.edges();
+        for (e, &([f, g], count)) in edges.faces.iter().enumerate() {
+            let [n, m] = [f, g].map(|f| normals[f as usize]);
+            let lengths = (n.norm2() * m.norm2()).sqrt();
+            if count == 2 && lengths > 0.0 && n.dot(&m) / lengths < cos_crease {
+                self.sharp.insert(edges.ends[e]);
+            }
+        }
+    }
+
+    /// Position of the corner of triangle `f` that is neither `a` nor `b`
+    fn opposite(&self, f: u32, (a, b): (u32, u32)) -> Vector3<f32> {
+        let corner = (self.face(f as usize).iter())
+            .map(|&w| self.wedge_positions[w as usize])
+            .find(|&p| p != a && p != b)
+            .unwrap_or(a);
+        self.positions[corner as usize]
+    }
+
+    /// Wedge at `position` with the average of the values of the wedges `from` of `mesh`, made
+    /// once for each position and wedges, face points having positions of their own
+    fn wedge(
+        &mut self,
+        ids: &mut HashMap<(u32, u32, u32), u32>,
+        mesh: &Mesh,
+        position: u32,
+        from: &[u32],
+    ) -> u32 {
+        let key = match *from {
+            [w] => (position, w, w),
+            [a, b] => (position, a.min(b), a.max(b)),
+            _ => (position, u32::MAX, u32::MAX),
+        };
+        *ids.entry(key).or_insert_with(|| {
+            self.wedge_positions.push(position);
+            mesh.average(from, &mut self.values);
+            self.wedge_positions.len() as u32 - 1
+        })
+    }
+
+    /// Appends the average of the values of `wedges` to `values`
+    fn average(&self, wedges: &[u32], values: &mut Vec<f64>) {
+        for i in 0..self.stride {
+            let sum: f64 = (wedges.iter())
+                .map(|&w| self.values[w as usize * self.stride + i])
+                .sum();
+            values.push(sum / wedges.len() as f64);
+        }
+    }
+
+    /// The mesh split once with `scheme` : new positions in the middle of the edges, and of
+    /// the faces for Catmull-Clark, and the old positions moved towards their neighbors, except
+    /// along the sharp edges and borders, which only move along themselves, and at the corners
+    /// where more than two of them meet, which stay
+    fn subdivided(&self, scheme: Scheme) -> Mesh {
+        let edges = self.edges();
+        let face_points: Vec<Vector3<f32>> = match scheme {
+            Scheme::Loop => Vec::new(),
+            Scheme::CatmullClark => (0..self.face_count())
+                .map(|f| {
+                    let face = self.face(f);
+                    let sum = (face.iter().map(|&w| self.position(w)))
+                        .fold(Vector3::zero(), |sum, p| sum + p);
+                    sum / face.len() as f32
+                })
+                .collect(),
+        };
+        let edge_points: Vec<Vector3<f32>> = (0..edges.ends.len())
+            .map(|e| {
+                let (a, b) = edges.ends[e];
+                let ends = self.positions[a as usize] + self.positions[b as usize];
+                if !self.smooth(&edges, e) {
+                    return ends / 2.0;
+                }
+                let [f, g] = edges.faces[e].0;
+                match scheme {
+                    Scheme::Loop => {
+                        let opposite = self.opposite(f, (a, b)) + self.opposite(g, (a, b));
+                        ends * 0.375 + opposite * 0.125
+                    }
+                    Scheme::CatmullClark => {
+                        (ends + face_points[f as usize] + face_points[g as usize]) / 4.0
+                    }
+                }
+            })
+            .collect();
+
+        // around each position : its edges and the sum of their other ends, the same for its
+        // sharp edges, and the sum of the face points of its faces with their number
+        let count = self.positions.len();
+        let mut valence = vec![0; count];
+        let mut ring = vec![Vector3::zero(); count];
+        let mut sharp = vec![0; count];
+        let mut sharp_ring = vec![Vector3::zero(); count];
+        for (e, &(a, b)) in edges.ends.iter().enumerate() {
+            let smooth = self.smooth(&edges, e);
+            for (v, other) in [(a, b), (b, a)] {
+                valence[v as usize] += 1;
+                ring[v as usize] += self.positions[other as usize];
+                if !smooth {
+                    sharp[v as usize] += 1;
+                    sharp_ring[v as usize] += self.positions[other as usize];
+                }
+            }
+        }
+        let mut faces = vec![0; count];
+        let mut face_ring = vec![Vector3::zero(); count];
+        for (f, &point) in face_points.iter().enumerate() {
+            for &w in self.face(f) {
+                faces[self.wedge_positions[w as usize] as usize] += 1;
+                face_ring[self.wedge_positions[w as usize] as usize] += point;
+            }
+        }
+        let vertex_points = (0..count).map(|v| {
+            let p = self.positions[v];
+            let n = valence[v] as f32;
+            match sharp[v] {
+                // a single sharp edge fades away at its end
+                0 | 1 if valence[v] > 0 => match scheme {
+                    Scheme::Loop => {
+                        let beta = (0.625 - (0.375 + (TAU / n).cos() / 4.0).powi(2)) / n;
+                        p * (1.0 - n * beta) + ring[v] * beta
+                    }
+                    Scheme::CatmullClark if faces[v] > 0 => {
+                        let faces = face_ring[v] / faces[v] as f32;
+                        let midpoints = (p * n + ring[v]) / (2.0 * n);
+                        (faces + midpoints * 2.0 + p * (n - 3.0)) / n
+                    }
+                    Scheme::CatmullClark => p,
+                },
+                // the corners of single faces stay, like the others
+                2 if valence[v] > 2 => p * 0.75 + sharp_ring[v] * 0.125,
+                _ => p,
+            }
+        });
+
+        let edge_base = count as u32;
+        let face_base = edge_base + edges.ends.len() as u32;
+        let mut next = Mesh {
+            positions: vertex_points
+                .chain(edge_points)
+                .chain(face_points)
+                .collect(),
+            wedge_positions: Vec::new(),
+            values: Vec::new(),
+            stride: self.stride,
+            corners: Vec::new(),
+            starts: vec![0],
+            parents: Vec::new(),
+            sharp: HashSet::new(),
+        };
+        for (e, &(a, b)) in edges.ends.iter().enumerate() {
+            if self.sharp.contains(&(a, b)) {
+                let middle = edge_base + e as u32;
+                next.sharp.extend([(a, middle), (b, middle)]);
+            }
+        }
+        let mut wedge_ids = HashMap::new();
+        for f in 0..self.face_count() {
+            let face = self.face(f);
+            let k = face.len();
+            let start = self.starts[f];
+            let corners: Vec<u32> = (0..k)
+                .map(|i| {
+                    let position = self.wedge_positions[face[i] as usize];
+                    next.wedge(&mut wedge_ids, self, position, &face[i..=i])
+                })
+                .collect();
+            let middles: Vec<u32> = (0..k)
+                .map(|i| {
+                    let position = edge_base + edges.corner_edges[start + i];
+                    next.wedge(
+                        &mut wedge_ids,
+                        self,
+                        position,
+                        &[face[i], face[(i + 1) % k]],
+                    )
+                })
+                .collect();
+            let center = (scheme == Scheme::CatmullClark)
+                .then(|| next.wedge(&mut wedge_ids, self, face_base + f as u32, face));
+            let mut push = |child: &[u32]| {
+                next.corners.extend(child);
+                next.starts.push(next.corners.len());
+                next.parents.push(self.parents[f]);
+            };
+            match center {
+                Some(center) => {
+                    for i in 0..k {
+                        push(&[corners[i], middles[i], center, middles[(i + k - 1) % k]]);
+                    }
+                }
+                None => {
+                    let ([c0, c1, c2], [m0, m1, m2]) = (
+                        [corners[0], corners[1], corners[2]],
+                        [middles[0], middles[1], middles[2]],
+                    );
+                    push(&[c0, m0, m2]);
+                    push(&[m0, c1, m1]);
+                    push(&[m2, m1, c2]);
+                    push(&[m0, m1, m2]);
+                }
+            }
+        }
+        next
+    }
+
+    /// Model of the faces, whose triangles take the place of the faces of `model` they come
+    /// from, `ranges` being the range of `model.indices` of each of those
+    /// Lines and points are kept as they are
+    fn into_model(self, model: &Model, ranges: &[Range<usize>], options: &NormalOptions) -> Model {
+        let colors = !model.file_colors.is_empty();
+        let color_values = if colors { 3 } else { 0 };
+        let values = |w: usize| &self.values[w * self.stride..(w + 1) * self.stride];
+        let mut vertices: Vec<Vertex> = (0..self.wedge_positions.len())
+            .map(|w| Vertex {
+                position: self.position(w as u32),
+                color: Vector3::zero(),
+                texture_coordinates: (values(w)[0] as f32, values(w)[1] as f32),
+                normal: Vector3::zero(),
+                tangent: Vector4::zero(),
+            })
+            .collect();
+        let mut file_colors: Vec<Option<Vector3<f32>>> = match colors {
+            true => (0..vertices.len())
+                .map(|w| {
+                    let [x, y, z] = [2, 3, 4].map(|i| values(w)[i] as f32);
+                    (!x.is_nan()).then_some(Vector3 { x, y, z })
+                })
+                .collect(),
+            false => Vec::new(),
+        };
+        let mut attributes: Vec<Vec<f64>> = (0..model.attributes.len())
+            .map(|i| {
+                (0..vertices.len())
+                    .map(|w| values(w)[2 + color_values + i])
+                    .collect()
+            })
+            .collect();
+
+        let mut vertex_faces = vec![None; vertices.len()];
+        let mut indices = Vec::new();
+        let mut polygons = Vec::new();
+        // where the triangles of each face of the model start
+        let mut face_starts = vec![0; ranges.len()];
+        for f in 0..self.face_count() {
+            let face = self.face(f);
+            let parent = self.parents[f] as usize;
+            if f == 0 || self.parents[f - 1] as usize != parent {
+                face_starts[parent] = indices.len();
+            }
+            let file_face = (model.indices.get(ranges[parent].start))
+                .and_then(|&v| model.vertex_faces[v as usize]);
+            for &w in face {
+                vertex_faces[w as usize] = vertex_faces[w as usize].or(file_face);
+            }
+            let start = indices.len();
+            for k in 1..face.len() - 1 {
+                indices.extend([face[0], face[k], face[k + 1]]);
+            }
+            if face.len() > 3 {
+                polygons.push(start..indices.len());
+            }
+        }
+        let new_index = |index: usize| match ranges.partition_point(|r| r.end <= index) {
+            f if f < ranges.len() => face_starts[f],
+            _ => indices.len(),
+        };
+        let range = |range: &Range<usize>| new_index(range.start)..new_index(range.end);
+        let material_ranges = (model.material_ranges.iter())
+            .map(|r| MaterialRange {
+                material: r.material.clone(),
+                indices: range(&r.indices),
+            })
+            .collect();
+        let submeshes = (model.submeshes.iter())
+            .map(|s| Submesh {
+                indices: range(&s.indices),
+                ..s.clone()
+            })
+            .filter(|s| !s.indices.is_empty())
+            .collect();
+
+        // the vertices of the lines and points come after those of the faces
+        let mut copies = HashMap::new();
+        let mut copy = |v: u32| {
+            *copies.entry(v).or_insert_with(|| {
+                let vertex = &model.vertices[v as usize];
+                vertices.push(Vertex {
+                    position: vertex.position,
+                    ..*vertex
+                });
+                if colors {
+                    file_colors.push(model.file_colors[v as usize]);
+                }
+                for (values, attribute) in attributes.iter_mut().zip(&model.attributes) {
+                    values.push(attribute.values[v as usize]);
+                }
+                vertex_faces.push(model.vertex_faces[v as usize]);
+                vertices.len() as u32 - 1
+            })
+        };
+        let line_indices = model.line_indices.iter().map(|&v| copy(v)).collect();
+        let point_indices = model.point_indices.iter().map(|&v| copy(v)).collect();
+
+        let mut subdivided = Model::new(vertices, indices);
+        subdivided.material_libs = model.material_libs.clone();
+        subdivided.materials = model.materials.clone();
+        subdivided.material_ranges = material_ranges;
+        subdivided.submeshes = submeshes;
+        subdivided.polygons = polygons;
+        subdivided.line_indices = line_indices;
+        subdivided.line_strips = model.line_strips.clone();
+        subdivided.point_indices = point_indices;
+        subdivided.attributes = (model.attributes.iter().zip(attributes))
+            .map(|(attribute, values)| VertexAttribute {
+                name: attribute.name.clone(),
+                values: values.into(),
+            })
+            .collect();
+        subdivided.file_colors = file_colors.into();
+        subdivided.vertex_faces = vertex_faces.into();
+        subdivided.face_count = model.face_count;
+        subdivided.file_texture_coordinates = model.file_texture_coordinates;
+        subdivided.compute_normals(options);
+        subdivided
+    }
+}
+
+/// Smooths the model by splitting its faces `options.levels` times, with Catmull-Clark or Loop
+/// subdivision. The polygons of the file are split as they are, before their triangulation
+/// Texture coordinates, file colors and attributes are interpolated linearly over the faces,
+/// the normals are computed again with `options.normals`
+pub fn subdivide(model: &Model, options: &SubdivideOptions) -> Model {
+    let scheme = options.scheme.unwrap_or(match model.polygons.is_empty() {
+        true => Scheme::Loop,
+        false => Scheme::CatmullClark,
+    });
+    let (mut mesh, ranges) = Mesh::new(model, scheme == Scheme::CatmullClark);
+    if let Some(angle) = options.crease_angle {
+        mesh.tag_creases(angle);
+    }
+    for _ in 0..options.levels {
+        mesh = mesh.subdivided(scheme);
+    }
+    mesh.into_model(model, &ranges, &options.normals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::{ParseOptions, parse_obj};
+
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                        g bottom\nf 1 4 3 2\nf 1 2 6 5\nf 2 3 7 6\n\
+                        g top\nf 3 4 8 7\nf 4 1 5 8\nf 5 6 7 8\n";
+
+    fn parse(source: &str) -> Model {
+        parse_obj(source.as_bytes(), &ParseOptions::default())
+            .unwrap()
+            .0
+    }
+
+    fn has_position(model: &Model, [x, y, z]: [f32; 3]) -> bool {
+        (model.vertices.iter()).any(|v| {
+            let p = v.position;
+            (p.x - x).abs() < 1e-5 && (p.y - y).abs() < 1e-5 && (p.z - z).abs() < 1e-5
+        })
+    }
+
+    fn positions(model: &Model) -> HashSet<[u32; 3]> {
+        (model.vertices.iter())
+            .map(|v| [v.position.x, v.position.y, v.position.z].map(f32::to_bits))
+            .collect()
+    }
+
+    #[test]
+    fn polygons_of_the_file() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3\nf 1 2 3 4 5\n");
+        assert_eq!(model.polygons, vec![3..12]);
+        let subdivided = subdivide(&model, &SubdivideOptions::default());
+        // 3 quads for the triangle, 5 for the pentagon
+        assert_eq!(subdivided.polygons.len(), 8);
+        assert_eq!(subdivided.indices.len(), 8 * 6);
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let subdivided = subdivide(&parse(CUBE), &SubdivideOptions::default());
+        assert_eq!(subdivided.indices.len(), 24 * 6);
+        // corners go 5/9 of the way, edge points 3/4, and the face points stay
+        assert!(has_position(&subdivided, [5.0 / 9.0; 3]));
+        assert!(has_position(&subdivided, [0.75, 0.75, 0.0]));
+        assert!(has_position(&subdivided, [0.0, 0.0, 1.0]));
+        let ranges: Vec<_> = (subdivided.submeshes.iter())
+            .map(|s| (s.group.as_str(), s.indices.clone()))
+            .collect();
+        assert_eq!(ranges, [("bottom", 0..72), ("top", 72..144)]);
+        // all the corners of the faces meet, the cube is still closed
+        assert_eq!(positions(&subdivided).len(), 8 + 12 + 6);
+    }
+
+    #[test]
+    fn creases_stay_sharp() {
+        let options = SubdivideOptions {
+            levels: 2,
+            crease_angle: Some(45.0),
+            ..Default::default()
+        };
+        let subdivided = subdivide(&parse(CUBE), &options);
+        assert_eq!(subdivided.indices.len(), 96 * 6);
+        for vertex in &subdivided.vertices {
+            let p = vertex.position;
+            assert_eq!(p.x.abs().max(p.y.abs()).max(p.z.abs()), 1.0);
+        }
+        assert!(has_position(&subdivided, [1.0; 3]));
+        // the crease splits the normals
+        assert_eq!(subdivided.vertices.len(), 6 * 25);
+    }
+
+    #[test]
+    fn loop_octahedron() {
+        let model = parse(
+            "v 1 0 0\nv -1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1\n\
+             f 1 3 5\nf 3 2 5\nf 2 4 5\nf 4 1 5\nf 3 1 6\nf 2 3 6\nf 4 2 6\nf 1 4 6\n",
+        );
+        let subdivided = subdivide(&model, &SubdivideOptions::default());
+        assert_eq!(subdivided.indices.len(), 32 * 3);
+        assert!(subdivided.polygons.is_empty());
+        // 1 - 4 beta of the way for the corners of valence 4, the edge points between
+        assert!(has_position(&subdivided, [0.515625, 0.0, 0.0]));
+        assert!(has_position(&subdivided, [0.375, 0.375, 0.0]));
+        assert_eq!(positions(&subdivided).len(), 6 + 12);
+    }
+
+    #[test]
+    fn interpolated_texture_coordinates() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\np 1\n",
+        );
+        let subdivided = subdivide(&model, &SubdivideOptions::default());
+        let center = (subdivided.vertices.iter())
+            .find(|v| v.position.x == 0.5 && v.position.y == 0.5)
+            .unwrap();
+        assert_eq!(center.texture_coordinates, (0.5, 0.5));
+        // the corners of the border stay, its middles move along it
+        assert!(has_position(&subdivided, [0.0; 3]));
+        assert!(has_position(&subdivided, [0.5, 0.0, 0.0]));
+        // the point keeps its vertex
+        let point = &subdivided.vertices[subdivided.point_indices[0] as usize];
+        assert_eq!([point.position.x, point.position.y], [0.0, 0.0]);
+        assert_eq!(subdivided.vertices.len(), 9 + 1);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::math::vector::Vector3;
+use crate::mesh;
+use crate::obj::{BoundingBox, Vertex};
+use std::f32::consts::{PI, TAU};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Axis {
+    X,
+    #[default]
+    Y,
+    Z,
+}
+
+/// How positions are turned into texture coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Projection {
+    /// Positions seen along the axis
+    Planar,
+    /// Each face is projected along the axis closest to its normal, like a planar projection on
+    /// each side of a box
+    Box,
+    /// Longitude around the axis and latitude from its poles, around the center of the model
+    Spherical,
+    /// Angle around the axis and height along it, around the center of the model
+    #[default]
+    Cylindrical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvOptions {
+    pub projection: Projection,
+    /// Viewing axis of planar projections, vertical axis of the spherical and cylindrical ones.
+    /// Box projections pick it per face
+    pub axis: Axis,
+    /// Texture repetitions along the largest side of the model. Around the spherical and
+    /// cylindrical axis, the texture is repeated a whole number of times so it stays square at
+    /// the equator
+    pub scale: f32,
+}
+
+impl Default for UvOptions {
+    fn default() -> Self {
+        UvOptions {
+            projection: Projection::default(),
+            axis: Axis::default(),
+            scale: 1.0,
+        }
+    }
+}
+
+struct Projector {
+    options: UvOptions,
+    min: Vector3<f32>,
+    center: Vector3<f32>,
+    size: f32,
+    /// Repetitions around the axis
+    turns: f32,
+}
+
+impl Projector {
+    fn new(bounds: &BoundingBox, options: &UvOptions) -> Projector {
+        let size = bounds.largest_axis();
+        Projector {
+            options: *options,
+            min: bounds.min,
+            center: bounds.center(),
+            size: if size > 0.0 { size } else { 1.0 },
+            turns: (options.scale * PI).round().max(1.0),
+        }
+    }
+    /// Seen along `axis`, with the other axes pointing right and up
+    fn planar(&self, axis: Axis, position: Vector3<f32>) -> (f32, f32) {
+        let d = (position - self.min) / self.size * self.options.scale;
+        match axis {
+            Axis::X => (-d.z, d.y),
+            Axis::Y => (d.x, -d.z),
+            Axis::Z => (d.x, d.y),
+        }
+    }
+    /// Box side facing `normal`, textures are not mirrored on the negative sides
+    fn box_side(&self, normal: Vector3<f32>, position: Vector3<f32>) -> (f32, f32) {
+        let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+        let (axis, negative) = if x > y && x > z {
+            (Axis::X, normal.x < 0.0)
+        } else if z > y {
+            (Axis::Z, normal.z < 0.0)
+        } else {
+            (Axis::Y, normal.y < 0.0)
+        };
+        let (u, v) = self.planar(axis, position);
+        if negative { (-u, v) } else { (u, v) }
+    }
+    /// Fraction of a turn around the axis, None on the axis, and v
+    fn around(&self, position: Vector3<f32>) -> (Option<f32>, f32) {
+        let d = position - self.center;
+        // the axis goes up, the two others are in the horizontal plane
+        let (a, height, b) = match self.options.axis {
+            Axis::X => (d.z, d.x, d.y),
+            Axis::Y => (d.x, d.y, d.z),
+            Axis::Z => (d.y, d.z, d.x),
+        };
+        let radius = a.hypot(b);
+        let turn = (radius > self.size * 1e-6).then(|| (a.atan2(b) / TAU).rem_euclid(1.0));
+        let v = match self.options.projection {
+            Projection::Spherical => {
+                let distance = d.norm();
+                let latitude = if distance > 0.0 {
+                    (height / distance).clamp(-1.0, 1.0).asin()
+                } else {
+                    0.0
+                };
+                // radians on a sphere as wide as the model, from the south pole
+                (latitude + PI / 2.0) / 2.0 * self.options.scale
+            }
+            _ => (height + self.size / 2.0) / self.size * self.options.scale,
+        };
+        (turn, v)
+    }
+    /// Texture coordinates of a vertex outside of any triangle
+    fn point(&self, position: Vector3<f32>, normal: Vector3<f32>) -> (f32, f32) {
+        match self.options.projection {
+            Projection::Planar => self.planar(self.options.axis, position),
+            Projection::Box => self.box_side(normal, position),
+            Projection::Spherical | Projection::Cylindrical => {
+                let (turn, v) = self.around(position);
+                (turn.unwrap_or(0.0) * self.turns, v)
+            }
+        }
+    }
+    /// Texture coordinates of the corners of a triangle, continuous across the triangle
+    fn triangle(&self, positions: [Vector3<f32>; 3]) -> [(f32, f32); 3] {
+        let [a, b, c] = positions;
+        match self.options.projection {
+            Projection::Planar => positions.map(|p| self.planar(self.options.axis, p)),
+            Projection::Box => {
+                let normal = (b - a).cross(&(c - a));
+                positions.map(|p| self.box_side(normal, p))
+            }
+            Projection::Spherical | Projection::Cylindrical => {
+                let around = positions.map(|p| self.around(p));
+                let known = || around.iter().filter_map(|(turn, _)| *turn);
+                // a triangle over the seam gets the corners before it one turn further
+                let wraps = known().fold(f32::INFINITY, f32::min) + 0.5
+                    < known().fold(f32::NEG_INFINITY, f32::max);
+                let unwrap = |turn: f32| {
+                    if wraps && turn < 0.5 {
+                        turn + 1.0
+                    } else {
+                        turn
+                    }
+                };
+                let count = known().count();
+                // corners on the axis have no angle, they take the middle of the others
+                let middle = if count > 0 {
+                    known().map(unwrap).sum::<f32>() / count as f32
+                } else {
+                    0.0
+                };
+                around.map(|(turn, v)| (turn.map_or(middle, unwrap) * self.turns, v))
+            }
+        }
+    }
+}
+
+/// Sets the texture coordinates of the vertices passing `replace` with the projection of their
+/// position
+/// Triangles get continuous coordinates : a vertex whose triangles need different ones, on the
+/// seam of a spherical or cylindrical projection, on the edges of a box projection or at a pole,
+/// is split, the copies are appended to `vertices` and the returned list has the vertex each one
+/// copies
+pub fn project(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut [u32],
+    bounds: &BoundingBox,
+    options: &UvOptions,
+    replace: impl Fn(u32) -> bool,
+) -> Vec<u32> {
+    let projector = Projector::new(bounds, options);
+    // lines and points
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        if replace(i as u32) {
+            vertex.texture_coordinates = projector.point(vertex.position, vertex.normal);
+        }
+    }
+    let corner_count = indices.len() / 3 * 3;
+    let mut corners = Vec::new();
+    for (triangle_no, triangle) in indices[..corner_count].chunks_exact(3).enumerate() {
+        if !triangle.iter().any(|&i| replace(i)) {
+            continue;
+        }
+        let positions = [0, 1, 2].map(|k| vertices[triangle[k] as usize].position);
+        for (k, uv) in projector.triangle(positions).into_iter().enumerate() {
+            if replace(triangle[k]) {
+                let vertex = &vertices[triangle[k] as usize];
+                let vertex = Vertex {
+                    texture_coordinates: uv,
+                    ..*vertex
+                };
+                corners.push((triangle_no * 3 + k, vertex));
+            }
+        }
+    }
+    mesh::split_vertices(vertices, indices, corners, |v| {
+        let (u, v) = v.texture_coordinates;
+        [u.to_bits(), v.to_bits()]
+    })
+}
+
+/// Square texture of `squares` by `squares` alternating light and dark squares, to check the
+/// texture coordinates
+pub fn checker_texture(size: u32, squares: u32) -> image::RgbaImage {
+    let square = (size / squares.max(1)).max(1);
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        if (x / square + y / square).is_multiple_of(2) {
+            image::Rgba([230, 230, 230, 255])
+        } else {
+            image::Rgba([40, 40, 40, 255])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vector::Vector4;
+
+    fn point([x, y, z]: [f32; 3]) -> Vector3<f32> {
+        Vector3 { x, y, z }
+    }
+
+    fn assert_close((u, v): (f32, f32), (expected_u, expected_v): (f32, f32)) {
+        assert!(
+            (u - expected_u).abs() < 1e-4 && (v - expected_v).abs() < 1e-4,
+            "({u}, {v}) instead of ({expected_u}, {expected_v})"
+        );
+    }
+
+    fn projector(projection: Projection, axis: Axis, bounds: [[f32; 3]; 2]) -> Projector {
+        let bounds = BoundingBox::from_points(bounds.into_iter().map(point));
+        let options = UvOptions {
+            projection,
+            axis,
+            scale: 1.0,
+        };
+        Projector::new(&bounds, &options)
+    }
+
+    #[test]
+    fn planar() {
+        let bounds = [[0.0, 0.0, 0.0], [2.0, 1.0, 1.0]];
+        let corner = point([2.0, 1.0, 1.0]);
+        // the largest side of the model is 1 long in the texture
+        let z = projector(Projection::Planar, Axis::Z, bounds);
+        assert_close(z.planar(Axis::Z, point([0.0; 3])), (0.0, 0.0));
+        assert_close(z.planar(Axis::Z, corner), (1.0, 0.5));
+        assert_close(z.planar(Axis::Y, corner), (1.0, -0.5));
+        assert_close(z.planar(Axis::X, corner), (-0.5, 0.5));
+        let y = projector(Projection::Planar, Axis::Y, bounds);
+        assert_close(y.point(corner, Vector3::zero()), (1.0, -0.5));
+    }
+
+    #[test]
+    fn box_sides() {
+        let bounds = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]];
+        let projector = projector(Projection::Box, Axis::Y, bounds);
+        // facing +x
+        let uv = projector.triangle([[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 0.0, 1.0]].map(point));
+        assert_close(uv[1], (0.0, 1.0));
+        assert_close(uv[2], (-1.0, 0.0));
+        // facing -x, u goes the other way so the texture is not mirrored
+        let uv = projector.triangle([[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]].map(point));
+        assert_close(uv[1], (1.0, 0.0));
+        assert_close(uv[2], (0.0, 1.0));
+        // facing +y
+        let uv = projector.triangle([[0.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 1.0, 0.0]].map(point));
+        assert_close(uv[1], (0.0, -1.0));
+        assert_close(uv[2], (1.0, 0.0));
+    }
+
+    #[test]
+    fn spherical_poles_and_equator() {
+        let projector = projector(Projection::Spherical, Axis::Y, [[-1.0; 3], [1.0; 3]]);
+        assert_eq!(projector.turns, 3.0);
+        // the poles are on the axis
+        assert_eq!(projector.around(point([0.0, -1.0, 0.0])), (None, 0.0));
+        let (turn, v) = projector.around(point([0.0, 1.0, 0.0]));
+        assert_eq!(turn, None);
+        assert_close((0.0, v), (0.0, PI / 2.0));
+        // a quarter turn along the equator
+        assert_close(
+            projector.point(point([0.0, 0.0, 1.0]), Vector3::zero()),
+            (0.0, PI / 4.0),
+        );
+        assert_close(
+            projector.point(point([1.0, 0.0, 0.0]), Vector3::zero()),
+            (0.75, PI / 4.0),
+        );
+        // a pole in a triangle takes the middle of the other corners
+        let uv = projector.triangle([[0.5, 0.0, 1.0], [1.0, 0.0, 0.5], [0.0, 1.0, 0.0]].map(point));
+        assert_close(uv[2], ((uv[0].0 + uv[1].0) / 2.0, PI / 2.0));
+    }
+
+    #[test]
+    fn spherical_seam() {
+        let projector = projector(Projection::Spherical, Axis::Y, [[-1.0; 3], [1.0; 3]]);
+        let left = projector.point(point([-0.1, 0.0, 1.0]), Vector3::zero());
+        let right = projector.point(point([0.1, 0.0, 1.0]), Vector3::zero());
+        // alone, the corners are on both ends of the texture
+        assert!(left.0 > 2.9 && right.0 < 0.1);
+        // in a triangle over the seam, the one after it is a turn further
+        let uv =
+            projector.triangle([[-0.1, 0.0, 1.0], [0.1, 0.0, 1.0], [0.0, 1.0, 0.0]].map(point));
+        assert_close(uv[0], left);
+        assert_close(uv[1], (right.0 + 3.0, right.1));
+        assert_close(uv[2], (3.0, PI / 2.0));
+    }
+
+    #[test]
+    fn cylindrical() {
+        let projector = projector(Projection::Cylindrical, Axis::Z, [[-1.0; 3], [1.0; 3]]);
+        assert_close(
+            projector.point(point([1.0, 0.0, -1.0]), Vector3::zero()),
+            (0.0, 0.0),
+        );
+        assert_close(
+            projector.point(point([1.0, 0.0, 0.0]), Vector3::zero()),
+            (0.0, 0.5),
+        );
+        assert_close(
+            projector.point(point([0.0, 1.0, 1.0]), Vector3::zero()),
+            (0.75, 1.0),
+        );
+    }
+
+    #[test]
+    fn seam_splits_vertices() {
+        let vertex = |position| Vertex {
+            position: point(position),
+            color: Vector3::zero(),
+            texture_coordinates: (0.0, 0.0),
+            normal: Vector3::zero(),
+            tangent: Vector4::zero(),
+        };
+        // two triangles on both sides of the seam of a cylinder, sharing its edge
+        let mut vertices = vec![
+            vertex([-0.5, 0.0, 0.866]),
+            vertex([0.0, 0.0, 1.0]),
+            vertex([0.0, 1.0, 1.0]),
+            vertex([0.5, 0.0, 0.866]),
+        ];
+        let mut indices = [0, 1, 2, 1, 3, 2];
+        let bounds =
+            BoundingBox::from_points([[-1.0, 0.0, -1.0], [1.0, 1.0, 1.0]].map(point).into_iter());
+        let options = UvOptions::default();
+        let mut sources = project(&mut vertices, &mut indices, &bounds, &options, |_| true);
+        sources.sort();
+        assert_eq!(sources, [1, 2]);
+        assert_eq!(vertices.len(), 6);
+        for triangle in indices.chunks(3) {
+            let u = triangle
+                .iter()
+                .map(|&i| vertices[i as usize].texture_coordinates.0);
+            let (min, max) = u.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), u| {
+                (min.min(u), max.max(u))
+            });
+            assert!(max - min < 1.0, "{triangle:?} goes around the texture");
+        }
+        // the edge on the seam is at the start of the texture on one side, at its end on the other
+        assert_close(
+            vertices[indices[3] as usize].texture_coordinates,
+            (0.0, 0.25),
+        );
+        assert_close(
+            vertices[indices[1] as usize].texture_coordinates,
+            (3.0, 0.25),
+        );
+    }
+}
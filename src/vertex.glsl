@@ -3,10 +3,13 @@ layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aColor;
 layout (location = 2) in vec2 aTexCoord;
 layout (location = 3) in vec3 aNorm;
+// xyz is the tangent, w the sign of the bitangent, zero when the tangents were not computed
+layout (location = 4) in vec4 aTangent;
 
 out vec3 vertexColor;
 out vec2 TexCoord;
 out vec3 vertexNorm;
+out vec4 vertexTangent;
 out vec3 FragPos;
 
 uniform mat4 model;
@@ -18,6 +21,7 @@ void main()
     gl_Position = projection * view * model * vec4(aPos.x, aPos.y, aPos.z, 1.0);
     vertexColor = aColor;
     TexCoord = aTexCoord;
-    vertexNorm = vec3(model * vec4(aNorm, 1.0));
+    vertexNorm = vec3(model * vec4(aNorm, 0.0));
+    vertexTangent = vec4(vec3(model * vec4(aTangent.xyz, 0.0)), aTangent.w);
     FragPos = vec3(model * vec4(aPos, 1.0));
 }